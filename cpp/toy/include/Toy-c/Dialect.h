@@ -25,6 +25,19 @@ MLIR_CAPI_EXPORTED MlirPass mlirToyCreateLowerToAffine();
 
 MLIR_CAPI_EXPORTED MlirPass mlirToyCreateShapeInference();
 
+/// Creates a pass specializing the functions called by `main` for the shapes
+/// of their arguments, so that calls which aren't inlined can be lowered.
+MLIR_CAPI_EXPORTED MlirPass mlirToyCreateSpecialization();
+
+/// Creates a toy struct type holding the given element types, which must be
+/// tensor or struct types. There must be at least one element type.
+MLIR_CAPI_EXPORTED MlirType mlirToyStructTypeGet(MlirContext ctx,
+                                                 intptr_t numElements,
+                                                 MlirType const *elements);
+
+/// Checks whether the given type is a toy struct type.
+MLIR_CAPI_EXPORTED bool mlirTypeIsAToyStructType(MlirType type);
+
 MLIR_DECLARE_CAPI_DIALECT_REGISTRATION(Toy, toy);
 
 
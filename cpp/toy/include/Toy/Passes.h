@@ -21,6 +21,7 @@ class Pass;
 namespace toy {
 
 std::unique_ptr<Pass> createShapeInferencePass();
+std::unique_ptr<Pass> createSpecializationPass();
 std::unique_ptr<Pass> createLowerToAffinePass();
 std::unique_ptr<Pass> createLowerToLLVMPass();
 
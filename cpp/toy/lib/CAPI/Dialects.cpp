@@ -11,6 +11,7 @@
 #include "Toy/Dialect.h"
 #include "mlir/CAPI/Registration.h"
 
+#include "mlir/CAPI/IR.h"
 #include "mlir/CAPI/Wrap.h"
 #include "Toy/Passes.h"
 #include "mlir/Pass/Pass.h"
@@ -23,6 +24,10 @@ MlirPass mlirToyCreateShapeInference() {
   return wrap(mlir::toy::createShapeInferencePass().release());
 }
 
+MlirPass mlirToyCreateSpecialization() {
+  return wrap(mlir::toy::createSpecializationPass().release());
+}
+
 MlirPass mlirToyCreateLowerToAffine() {
   return wrap(mlir::toy::createLowerToAffinePass().release());
 }
@@ -30,3 +35,14 @@ MlirPass mlirToyCreateLowerToAffine() {
 MlirPass mlirToyCreateLowerToLLVM() {
   return wrap(mlir::toy::createLowerToLLVMPass().release());
 }
+
+MlirType mlirToyStructTypeGet(MlirContext ctx, intptr_t numElements,
+                              MlirType const *elements) {
+  llvm::SmallVector<mlir::Type, 4> elementTypes;
+  (void)unwrapList(numElements, elements, elementTypes);
+  return wrap(mlir::toy::StructType::get(elementTypes));
+}
+
+bool mlirTypeIsAToyStructType(MlirType type) {
+  return unwrap(type).isa<mlir::toy::StructType>();
+}
@@ -15,6 +15,7 @@
 
 #include "mlir/IR/Builders.h"
 #include "mlir/IR/BuiltinTypes.h"
+#include "mlir/IR/DialectImplementation.h"
 #include "mlir/IR/OpImplementation.h"
 #include "mlir/Transforms/InliningUtils.h"
 
@@ -48,6 +49,13 @@ struct ToyInlinerInterface : public DialectInlinerInterface {
     return true;
   }
 
+  /// Calls within the regions of toy operations, e.g. in the body of a loop,
+  /// can be inlined.
+  bool isLegalToInline(Region *, Region *, bool,
+                       BlockAndValueMapping &) const final {
+    return true;
+  }
+
   //===--------------------------------------------------------------------===//
   // Transformation Hooks
   //===--------------------------------------------------------------------===//
@@ -89,6 +97,18 @@ void ToyDialect::initialize() {
 #include "Toy/Ops.cpp.inc"
       >();
   addInterfaces<ToyInlinerInterface>();
+  addTypes<StructType>();
+}
+
+mlir::Operation *ToyDialect::materializeConstant(mlir::OpBuilder &builder,
+                                                 mlir::Attribute value,
+                                                 mlir::Type type,
+                                                 mlir::Location loc) {
+  if (type.isa<StructType>())
+    return builder.create<StructConstantOp>(loc, type,
+                                            value.cast<mlir::ArrayAttr>());
+  return builder.create<ConstantOp>(loc, type,
+                                    value.cast<mlir::DenseElementsAttr>());
 }
 
 //===----------------------------------------------------------------------===//
@@ -143,6 +163,98 @@ static void printBinaryOp(mlir::OpAsmPrinter &printer, mlir::Operation *op) {
   printer.printFunctionalType(op->getOperandTypes(), op->getResultTypes());
 }
 
+/// Returns the type of the result of an operation computed from `value`
+/// before shape inference: an unranked tensor of the elements of `value`.
+static mlir::Type getUnrankedLike(mlir::Value value) {
+  auto elementType = value.getType().cast<TensorType>().getElementType();
+  return UnrankedTensorType::get(elementType);
+}
+
+/// Returns the shape resulting from broadcasting the shapes of `lhs` and `rhs`
+/// against each other, or None if they are incompatible. The shapes are
+/// aligned on their trailing dimensions, and each pair of dimensions must
+/// either match or contain a 1, which is repeated along the other dimension. A
+/// missing dimension behaves as a 1, so that a 0-d tensor is broadcast to any
+/// shape.
+static llvm::Optional<SmallVector<int64_t, 4>>
+getBroadcastedShape(RankedTensorType lhs, RankedTensorType rhs) {
+  ArrayRef<int64_t> lhsShape = lhs.getShape();
+  ArrayRef<int64_t> rhsShape = rhs.getShape();
+  if (lhsShape.size() < rhsShape.size())
+    std::swap(lhsShape, rhsShape);
+
+  SmallVector<int64_t, 4> shape(lhsShape.begin(), lhsShape.end());
+  size_t offset = lhsShape.size() - rhsShape.size();
+  for (auto it : llvm::enumerate(rhsShape)) {
+    int64_t &dim = shape[offset + it.index()];
+    if (dim == 1)
+      dim = it.value();
+    else if (it.value() != 1 && it.value() != dim)
+      return llvm::None;
+  }
+  return shape;
+}
+
+/// Infer the output shape of a binary element-wise operation by broadcasting
+/// the shapes of its operands. The result is left unranked if they are
+/// incompatible, which the verifier reports.
+static void inferBroadcastedShape(mlir::Operation *op) {
+  auto lhs = op->getOperand(0).getType().dyn_cast<RankedTensorType>();
+  auto rhs = op->getOperand(1).getType().dyn_cast<RankedTensorType>();
+  if (!lhs || !rhs)
+    return;
+  if (auto shape = getBroadcastedShape(lhs, rhs))
+    op->getResult(0).setType(
+        RankedTensorType::get(*shape, lhs.getElementType()));
+}
+
+/// Verify that the operands of a binary element-wise operation can be broadcast
+/// against each other, once their shapes are known.
+static mlir::LogicalResult verifyBroadcast(mlir::Operation *op) {
+  auto lhs = op->getOperand(0).getType().dyn_cast<RankedTensorType>();
+  auto rhs = op->getOperand(1).getType().dyn_cast<RankedTensorType>();
+  if (!lhs || !rhs || getBroadcastedShape(lhs, rhs))
+    return mlir::success();
+  return op->emitOpError()
+         << "expects operands of broadcastable shapes, but got " << lhs
+         << " and " << rhs;
+}
+
+/// Infer the output shape of a reduction by dropping the reduced dimension
+/// `axis` from the shape of its input. The result is left unranked if the axis
+/// is out of the input rank, which the verifier reports.
+static void inferReducedShape(mlir::Operation *op) {
+  int64_t axis = op->getAttrOfType<IntegerAttr>("axis").getInt();
+  auto inputType = op->getOperand(0).getType().cast<RankedTensorType>();
+  if (axis < 0 || axis >= inputType.getRank())
+    return;
+  SmallVector<int64_t, 4> shape(inputType.getShape().begin(),
+                                inputType.getShape().end());
+  shape.erase(shape.begin() + axis);
+  op->getResult(0).setType(
+      RankedTensorType::get(shape, inputType.getElementType()));
+}
+
+/// Verify that the dimension reduced by a reduction is within the rank of its
+/// input, once its shape is known.
+static mlir::LogicalResult verifyReduction(mlir::Operation *op) {
+  int64_t axis = op->getAttrOfType<IntegerAttr>("axis").getInt();
+  if (axis < 0)
+    return op->emitOpError() << "expects a non-negative axis, but got " << axis;
+
+  auto inputType = op->getOperand(0).getType().dyn_cast<RankedTensorType>();
+  if (!inputType)
+    return mlir::success();
+  if (axis >= inputType.getRank())
+    return op->emitOpError()
+           << "reduced axis " << axis << " is out of range for " << inputType;
+  auto resultType = op->getResult(0).getType().dyn_cast<RankedTensorType>();
+  if (resultType && resultType.getRank() != inputType.getRank() - 1)
+    return op->emitOpError()
+           << "expects the result shape to drop the reduced axis";
+  return mlir::success();
+}
+
 //===----------------------------------------------------------------------===//
 // ConstantOp
 
@@ -182,49 +294,104 @@ static void print(mlir::OpAsmPrinter &printer, ConstantOp op) {
   printer << op.value();
 }
 
-/// Verifier for the constant operation. This corresponds to the `::verify(...)`
-/// in the op definition.
-static mlir::LogicalResult verify(ConstantOp op) {
-  // If the return type of the constant is not an unranked tensor, the shape
-  // must match the shape of the attribute holding the data.
-  auto resultType = op.getResult().getType().dyn_cast<mlir::RankedTensorType>();
-  if (!resultType)
-    return success();
-
-  // Check that the rank of the attribute type matches the rank of the constant
-  // result type.
-  auto attrType = op.value().getType().cast<mlir::TensorType>();
-  if (attrType.getRank() != resultType.getRank()) {
-    return op.emitOpError(
-               "return type must match the one of the attached value "
-               "attribute: ")
-           << attrType.getRank() << " != " << resultType.getRank();
-  }
+/// Verify that the given attribute value is valid for the given type.
+static mlir::LogicalResult verifyConstantForType(mlir::Type type,
+                                                 mlir::Attribute opaqueValue,
+                                                 mlir::Operation *op) {
+  if (type.isa<mlir::TensorType>()) {
+    // Check that the value is an elements attribute.
+    auto attrValue = opaqueValue.dyn_cast<mlir::DenseElementsAttr>();
+    if (!attrValue)
+      return op->emitError("constant of TensorType must be initialized by "
+                           "a DenseElementsAttr, got ")
+             << opaqueValue;
+
+    // Check that the elements of the attribute are those of the result.
+    auto attrElementType = attrValue.getType().getElementType();
+    if (attrElementType != type.cast<mlir::TensorType>().getElementType())
+      return op->emitOpError("return type must have the element type of the "
+                             "attached value attribute: ")
+             << attrElementType;
 
-  // Check that each of the dimensions match between the two types.
-  for (int dim = 0, dimE = attrType.getRank(); dim < dimE; ++dim) {
-    if (attrType.getShape()[dim] != resultType.getShape()[dim]) {
-      return op.emitOpError(
-                 "return type shape mismatches its attribute at dimension ")
-             << dim << ": " << attrType.getShape()[dim]
-             << " != " << resultType.getShape()[dim];
+    // If the return type of the constant is not an unranked tensor, the shape
+    // must match the shape of the attribute holding the data.
+    auto resultType = type.dyn_cast<mlir::RankedTensorType>();
+    if (!resultType)
+      return success();
+
+    // Check that the rank of the attribute type matches the rank of the
+    // constant result type.
+    auto attrType = attrValue.getType().cast<mlir::TensorType>();
+    if (attrType.getRank() != resultType.getRank()) {
+      return op->emitOpError("return type must match the one of the attached "
+                             "value attribute: ")
+             << attrType.getRank() << " != " << resultType.getRank();
+    }
+
+    // Check that each of the dimensions match between the two types.
+    for (int dim = 0, dimE = attrType.getRank(); dim < dimE; ++dim) {
+      if (attrType.getShape()[dim] != resultType.getShape()[dim]) {
+        return op->emitOpError(
+                   "return type shape mismatches its attribute at dimension ")
+               << dim << ": " << attrType.getShape()[dim]
+               << " != " << resultType.getShape()[dim];
+      }
     }
+    return mlir::success();
   }
+  auto resultType = type.cast<StructType>();
+  llvm::ArrayRef<mlir::Type> resultElementTypes = resultType.getElementTypes();
+
+  // Verify that the initializer is an Array.
+  auto attrValue = opaqueValue.dyn_cast<ArrayAttr>();
+  if (!attrValue || attrValue.getValue().size() != resultElementTypes.size())
+    return op->emitError("constant of StructType must be initialized by an "
+                         "ArrayAttr with the same number of elements, got ")
+           << opaqueValue;
+
+  // Check that each of the elements are valid.
+  llvm::ArrayRef<mlir::Attribute> attrElementValues = attrValue.getValue();
+  for (const auto it : llvm::zip(resultElementTypes, attrElementValues))
+    if (failed(verifyConstantForType(std::get<0>(it), std::get<1>(it), op)))
+      return mlir::failure();
   return mlir::success();
 }
 
+/// Verifier for the constant operation. This corresponds to the `::verify(...)`
+/// in the op definition.
+static mlir::LogicalResult verify(ConstantOp op) {
+  return verifyConstantForType(op.getResult().getType(), op.value(), op);
+}
+
+static mlir::LogicalResult verify(StructConstantOp op) {
+  return verifyConstantForType(op.getResult().getType(), op.value(), op);
+}
+
+//===----------------------------------------------------------------------===//
+// AbsOp
+
+void AbsOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                  mlir::Value input) {
+  state.addTypes(getUnrankedLike(input));
+  state.addOperands(input);
+}
+
+/// Infer the output shape of the AbsOp, this is required by the shape inference
+/// interface.
+void AbsOp::inferShapes() { getResult().setType(input().getType()); }
+
 //===----------------------------------------------------------------------===//
 // AddOp
 
 void AddOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
                   mlir::Value lhs, mlir::Value rhs) {
-  state.addTypes(UnrankedTensorType::get(builder.getF64Type()));
+  state.addTypes(getUnrankedLike(lhs));
   state.addOperands({lhs, rhs});
 }
 
 /// Infer the output shape of the AddOp, this is required by the shape inference
 /// interface.
-void AddOp::inferShapes() { getResult().setType(getOperand(0).getType()); }
+void AddOp::inferShapes() { inferBroadcastedShape(*this); }
 
 //===----------------------------------------------------------------------===//
 // CastOp
@@ -248,6 +415,173 @@ bool CastOp::areCastCompatible(TypeRange inputs, TypeRange outputs) {
   return !input.hasRank() || !output.hasRank() || input == output;
 }
 
+//===----------------------------------------------------------------------===//
+// DivOp
+
+void DivOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                  mlir::Value lhs, mlir::Value rhs) {
+  state.addTypes(getUnrankedLike(lhs));
+  state.addOperands({lhs, rhs});
+}
+
+/// Infer the output shape of the DivOp, this is required by the shape inference
+/// interface.
+void DivOp::inferShapes() { inferBroadcastedShape(*this); }
+
+//===----------------------------------------------------------------------===//
+// ExpOp
+
+void ExpOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                  mlir::Value input) {
+  state.addTypes(getUnrankedLike(input));
+  state.addOperands(input);
+}
+
+/// Infer the output shape of the ExpOp, this is required by the shape inference
+/// interface.
+void ExpOp::inferShapes() { getResult().setType(input().getType()); }
+
+//===----------------------------------------------------------------------===//
+// ExtractOp
+
+/// Returns the integers held by an array attribute.
+static SmallVector<int64_t, 4> getI64Array(mlir::ArrayAttr attr) {
+  SmallVector<int64_t, 4> values;
+  for (auto value : attr.getAsRange<IntegerAttr>())
+    values.push_back(value.getInt());
+  return values;
+}
+
+void ExtractOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                      mlir::Value input, mlir::ValueRange indices,
+                      ArrayRef<int64_t> dims) {
+  build(builder, state, getUnrankedLike(input), input,
+        indices, builder.getI64ArrayAttr(dims));
+}
+
+/// Returns the shape of the result of an ExtractOp, dropping the dimensions
+/// `dims` from the shape of its input.
+static SmallVector<int64_t, 4> getExtractedShape(ArrayRef<int64_t> shape,
+                                                 ArrayRef<int64_t> dims) {
+  SmallVector<int64_t, 4> result;
+  for (auto it : llvm::enumerate(shape))
+    if (!llvm::is_contained(dims, static_cast<int64_t>(it.index())))
+      result.push_back(it.value());
+  return result;
+}
+
+/// Infer the output shape of the ExtractOp, this is required by the shape
+/// inference interface.
+void ExtractOp::inferShapes() {
+  auto inputType = input().getType().cast<RankedTensorType>();
+  auto shape = getExtractedShape(inputType.getShape(), getI64Array(dims()));
+  getResult().setType(
+      RankedTensorType::get(shape, inputType.getElementType()));
+}
+
+static mlir::LogicalResult verify(ExtractOp op) {
+  auto dims = getI64Array(op.dims());
+  if (op.indices().size() != dims.size())
+    return op.emitOpError() << "expects one index per extracted dimension";
+  for (auto it : llvm::enumerate(dims)) {
+    size_t i = it.index();
+    if (it.value() < 0 || (i > 0 && dims[i - 1] >= it.value()))
+      return op.emitOpError()
+             << "expects the extracted dimensions in increasing order";
+  }
+  for (Type type : op.indices().getTypes()) {
+    auto indexType = type.dyn_cast<RankedTensorType>();
+    if (indexType && indexType.getRank() != 0)
+      return op.emitOpError() << "expects 0-d indices, but got " << type;
+  }
+
+  auto inputType = op.input().getType().dyn_cast<RankedTensorType>();
+  if (!inputType)
+    return mlir::success();
+  if (!dims.empty() && dims.back() >= inputType.getRank())
+    return op.emitOpError() << "extracted dimension " << dims.back()
+                            << " is out of range for " << inputType;
+  auto shape = getExtractedShape(inputType.getShape(), dims);
+  auto resultType = op.getType().dyn_cast<RankedTensorType>();
+  if (resultType && resultType.getShape() != ArrayRef<int64_t>(shape))
+    return op.emitOpError()
+           << "expects the result shape to drop the extracted dimensions";
+  return mlir::success();
+}
+
+//===----------------------------------------------------------------------===//
+// ForOp
+
+/// Returns whether the types `lhs` and `rhs` of a carried value agree, which
+/// they do as long as one of them isn't inferred.
+static bool areCarriedTypesCompatible(mlir::Type lhs, mlir::Type rhs) {
+  return lhs == rhs || lhs.isa<UnrankedTensorType>() ||
+         rhs.isa<UnrankedTensorType>();
+}
+
+/// Verify that the results of `op` have the types of the values it carries.
+static mlir::LogicalResult verifyResults(mlir::Operation *op,
+                                         mlir::TypeRange carriedTypes) {
+  for (auto it : llvm::zip(op->getResultTypes(), carriedTypes))
+    if (!areCarriedTypesCompatible(std::get<0>(it), std::get<1>(it)))
+      return op->emitOpError()
+             << "expects its results to have the types of its carried values";
+  return mlir::success();
+}
+
+/// Verify that `region` of `op` ends with a "toy.yield" of values of the types
+/// in `carriedTypes`. The lowering copies the yielded values into the buffers
+/// of the carried ones, so they can't change shape in the region.
+static mlir::LogicalResult verifyYield(mlir::Operation *op,
+                                       mlir::Region &region,
+                                       mlir::TypeRange carriedTypes) {
+  Block &block = region.front();
+  auto yield = block.empty() ? YieldOp() : dyn_cast<YieldOp>(block.back());
+  if (!yield)
+    return op->emitOpError() << "expects its regions to end with 'toy.yield'";
+
+  if (yield.getNumOperands() != carriedTypes.size())
+    return yield.emitOpError()
+           << "yields " << yield.getNumOperands()
+           << " values, but its parent carries " << carriedTypes.size();
+
+  for (auto it : llvm::enumerate(
+           llvm::zip(yield.getOperandTypes(), carriedTypes))) {
+    mlir::Type yieldedType = std::get<0>(it.value());
+    mlir::Type carriedType = std::get<1>(it.value());
+    if (!areCarriedTypesCompatible(yieldedType, carriedType))
+      return yield.emitOpError()
+             << "yields " << yieldedType << " for the carried value #"
+             << it.index() << " of type " << carriedType;
+  }
+  return mlir::success();
+}
+
+/// Infer the types of the values carried by the ForOp, this is required by the
+/// shape inference interface. They keep the type of the initial values.
+void ForOp::inferShapes() {
+  for (auto it : llvm::enumerate(initArgs())) {
+    mlir::Type type = it.value().getType();
+    getResult(it.index()).setType(type);
+    body().getArgument(it.index() + 1).setType(type);
+  }
+}
+
+static mlir::LogicalResult verify(ForOp op) {
+  size_t numCarried = op.initArgs().size();
+  if (op.getNumResults() != numCarried)
+    return op.emitOpError() << "expects one result per carried value";
+
+  // The body takes the induction variable, followed by the carried values.
+  if (op.body().getNumArguments() != numCarried + 1)
+    return op.emitOpError() << "expects the body to take "
+                            << numCarried + 1 << " arguments";
+
+  if (failed(verifyResults(op, op.initArgs().getTypes())))
+    return mlir::failure();
+  return verifyYield(op, op.body(), op.initArgs().getTypes());
+}
+
 //===----------------------------------------------------------------------===//
 // GenericCallOp
 
@@ -270,18 +604,143 @@ CallInterfaceCallable GenericCallOp::getCallableForCallee() {
 /// call interface.
 Operation::operand_range GenericCallOp::getArgOperands() { return inputs(); }
 
+//===----------------------------------------------------------------------===//
+// IfOp
+
+/// Infer the types of the values carried by the IfOp, this is required by the
+/// shape inference interface. They keep the type of the carried values.
+void IfOp::inferShapes() {
+  for (auto it : llvm::enumerate(carried()))
+    getResult(it.index()).setType(it.value().getType());
+}
+
+static mlir::LogicalResult verify(IfOp op) {
+  size_t numCarried = op.carried().size();
+  if (op.getNumResults() != numCarried)
+    return op.emitOpError() << "expects one result per carried value";
+
+  if (failed(verifyResults(op, op.carried().getTypes())))
+    return mlir::failure();
+  if (failed(verifyYield(op, op.thenRegion(), op.carried().getTypes())))
+    return mlir::failure();
+  return verifyYield(op, op.elseRegion(), op.carried().getTypes());
+}
+
+//===----------------------------------------------------------------------===//
+// LogOp
+
+void LogOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                  mlir::Value input) {
+  state.addTypes(getUnrankedLike(input));
+  state.addOperands(input);
+}
+
+/// Infer the output shape of the LogOp, this is required by the shape inference
+/// interface.
+void LogOp::inferShapes() { getResult().setType(input().getType()); }
+
+//===----------------------------------------------------------------------===//
+// LtOp
+
+void LtOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                 mlir::Value lhs, mlir::Value rhs) {
+  state.addTypes(getUnrankedLike(lhs));
+  state.addOperands({lhs, rhs});
+}
+
+/// Infer the output shape of the LtOp, this is required by the shape inference
+/// interface.
+void LtOp::inferShapes() { inferBroadcastedShape(*this); }
+
+//===----------------------------------------------------------------------===//
+// MatMulOp
+
+void MatMulOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                     mlir::Value lhs, mlir::Value rhs) {
+  state.addTypes(getUnrankedLike(lhs));
+  state.addOperands({lhs, rhs});
+}
+
+/// Infer the output shape of the MatMulOp, this is required by the shape
+/// inference interface. The result is left unranked if the operands can't be
+/// multiplied, which the verifier reports.
+void MatMulOp::inferShapes() {
+  auto lhsType = lhs().getType().cast<RankedTensorType>();
+  auto rhsType = rhs().getType().cast<RankedTensorType>();
+  if (lhsType.getRank() != 2 || rhsType.getRank() != 2 ||
+      lhsType.getDimSize(1) != rhsType.getDimSize(0))
+    return;
+  getResult().setType(RankedTensorType::get(
+      {lhsType.getDimSize(0), rhsType.getDimSize(1)},
+      lhsType.getElementType()));
+}
+
+static mlir::LogicalResult verify(MatMulOp op) {
+  auto lhsType = op.lhs().getType().dyn_cast<RankedTensorType>();
+  auto rhsType = op.rhs().getType().dyn_cast<RankedTensorType>();
+  if (!lhsType || !rhsType)
+    return mlir::success();
+  if (lhsType.getRank() != 2 || rhsType.getRank() != 2)
+    return op.emitOpError() << "expects matrices, but got " << lhsType
+                            << " and " << rhsType;
+  if (lhsType.getDimSize(1) != rhsType.getDimSize(0))
+    return op.emitOpError()
+           << "expects as many columns in lhs as rows in rhs, but got "
+           << lhsType << " and " << rhsType;
+  return mlir::success();
+}
+
+//===----------------------------------------------------------------------===//
+// MaxOp
+
+void MaxOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                  mlir::Value input, int64_t axis) {
+  build(builder, state, getUnrankedLike(input), input,
+        builder.getI64IntegerAttr(axis));
+}
+
+/// Infer the output shape of the MaxOp, this is required by the shape inference
+/// interface.
+void MaxOp::inferShapes() { inferReducedShape(*this); }
+
+//===----------------------------------------------------------------------===//
+// MeanOp
+
+void MeanOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                   mlir::Value input, int64_t axis) {
+  build(builder, state, getUnrankedLike(input), input,
+        builder.getI64IntegerAttr(axis));
+}
+
+/// Infer the output shape of the MeanOp, this is required by the shape
+/// inference interface.
+void MeanOp::inferShapes() { inferReducedShape(*this); }
+
 //===----------------------------------------------------------------------===//
 // MulOp
 
 void MulOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
                   mlir::Value lhs, mlir::Value rhs) {
-  state.addTypes(UnrankedTensorType::get(builder.getF64Type()));
+  state.addTypes(getUnrankedLike(lhs));
   state.addOperands({lhs, rhs});
 }
 
 /// Infer the output shape of the MulOp, this is required by the shape inference
 /// interface.
-void MulOp::inferShapes() { getResult().setType(getOperand(0).getType()); }
+void MulOp::inferShapes() { inferBroadcastedShape(*this); }
+
+//===----------------------------------------------------------------------===//
+// NegOp
+
+void NegOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                  mlir::Value input) {
+  state.addTypes(getUnrankedLike(input));
+  state.addOperands(input);
+}
+
+/// Infer the output shape of the NegOp, this is required by the shape inference
+/// interface.
+void NegOp::inferShapes() { getResult().setType(input().getType()); }
 
 //===----------------------------------------------------------------------===//
 // ReturnOp
@@ -291,10 +750,6 @@ static mlir::LogicalResult verify(ReturnOp op) {
   // trait attached to the operation definition.
   auto function = cast<FuncOp>(op->getParentOp());
 
-  /// ReturnOps can only have a single optional operand.
-  if (op.getNumOperands() > 1)
-    return op.emitOpError() << "expects at most 1 return operand";
-
   // The operand number and types must match the function signature.
   const auto &results = function.getType().getResults();
   if (op.getNumOperands() != results.size())
@@ -303,29 +758,169 @@ static mlir::LogicalResult verify(ReturnOp op) {
            << op.getNumOperands() << ") as the enclosing function ("
            << results.size() << ")";
 
-  // If the operation does not have an input, we are done.
-  if (!op.hasOperand())
-    return mlir::success();
+  // Check that the result types of the function match the operand types.
+  for (auto it : llvm::zip(op.getOperandTypes(), results)) {
+    auto inputType = std::get<0>(it);
+    auto resultType = std::get<1>(it);
+    if (inputType == resultType ||
+        inputType.isa<mlir::UnrankedTensorType>() ||
+        resultType.isa<mlir::UnrankedTensorType>())
+      continue;
+
+    return op.emitError() << "type of return operand (" << inputType
+                          << ") doesn't match function result type ("
+                          << resultType << ")";
+  }
+  return mlir::success();
+}
+
+//===----------------------------------------------------------------------===//
+// SliceOp
+
+void SliceOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                    mlir::Value input, ArrayRef<int64_t> starts,
+                    ArrayRef<int64_t> ends) {
+  build(builder, state, getUnrankedLike(input), input,
+        builder.getI64ArrayAttr(starts), builder.getI64ArrayAttr(ends));
+}
 
-  auto inputType = *op.operand_type_begin();
-  auto resultType = results.front();
+/// Returns the shape of the result of a SliceOp, given the shape of its input,
+/// or None if the bounds aren't within the input shape.
+static llvm::Optional<SmallVector<int64_t, 4>>
+getSlicedShape(ArrayRef<int64_t> shape, ArrayRef<int64_t> starts,
+               ArrayRef<int64_t> ends) {
+  if (starts.size() > shape.size())
+    return llvm::None;
 
-  // Check that the result type of the function matches the operand type.
-  if (inputType == resultType || inputType.isa<mlir::UnrankedTensorType>() ||
-      resultType.isa<mlir::UnrankedTensorType>())
+  SmallVector<int64_t, 4> result(shape.begin(), shape.end());
+  for (auto it : llvm::enumerate(llvm::zip(starts, ends))) {
+    int64_t start = std::get<0>(it.value());
+    int64_t end = std::get<1>(it.value());
+    if (end == -1)
+      end = shape[it.index()];
+    if (start < 0 || start >= end || end > shape[it.index()])
+      return llvm::None;
+    result[it.index()] = end - start;
+  }
+  return result;
+}
+
+/// Infer the output shape of the SliceOp, this is required by the shape
+/// inference interface. The result is left unranked if the bounds are out of
+/// the input shape, which the verifier reports.
+void SliceOp::inferShapes() {
+  auto inputType = input().getType().cast<RankedTensorType>();
+  if (auto shape = getSlicedShape(inputType.getShape(), getI64Array(starts()),
+                                  getI64Array(ends())))
+    getResult().setType(
+        RankedTensorType::get(*shape, inputType.getElementType()));
+}
+
+static mlir::LogicalResult verify(SliceOp op) {
+  auto starts = getI64Array(op.starts());
+  auto ends = getI64Array(op.ends());
+  if (starts.size() != ends.size())
+    return op.emitOpError() << "expects as many starts as ends";
+
+  auto inputType = op.input().getType().dyn_cast<RankedTensorType>();
+  if (!inputType)
     return mlir::success();
+  auto shape = getSlicedShape(inputType.getShape(), starts, ends);
+  if (!shape)
+    return op.emitOpError() << "expects non-empty ranges within the bounds of "
+                            << inputType;
+  auto resultType = op.getType().dyn_cast<RankedTensorType>();
+  if (resultType && resultType.getShape() != ArrayRef<int64_t>(*shape))
+    return op.emitOpError()
+           << "expects the result shape to be the size of the ranges";
+  return mlir::success();
+}
+
+//===----------------------------------------------------------------------===//
+// SqrtOp
 
-  return op.emitError() << "type of return operand (" << inputType
-                        << ") doesn't match function result type ("
-                        << resultType << ")";
+void SqrtOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                   mlir::Value input) {
+  state.addTypes(getUnrankedLike(input));
+  state.addOperands(input);
 }
 
+/// Infer the output shape of the SqrtOp, this is required by the shape
+/// inference interface.
+void SqrtOp::inferShapes() { getResult().setType(input().getType()); }
+
+//===----------------------------------------------------------------------===//
+// SubOp
+
+void SubOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                  mlir::Value lhs, mlir::Value rhs) {
+  state.addTypes(getUnrankedLike(lhs));
+  state.addOperands({lhs, rhs});
+}
+
+/// Infer the output shape of the SubOp, this is required by the shape inference
+/// interface.
+void SubOp::inferShapes() { inferBroadcastedShape(*this); }
+
+//===----------------------------------------------------------------------===//
+// SumOp
+
+void SumOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                  mlir::Value input, int64_t axis) {
+  build(builder, state, getUnrankedLike(input), input,
+        builder.getI64IntegerAttr(axis));
+}
+
+/// Infer the output shape of the SumOp, this is required by the shape inference
+/// interface.
+void SumOp::inferShapes() { inferReducedShape(*this); }
+
+//===----------------------------------------------------------------------===//
+// StructAccessOp
+
+void StructAccessOp::build(mlir::OpBuilder &b, mlir::OperationState &state,
+                           mlir::Value input, size_t index) {
+  // Extract the result type from the input type.
+  StructType structTy = input.getType().cast<StructType>();
+  assert(index < structTy.getNumElementTypes());
+  mlir::Type resultType = structTy.getElementTypes()[index];
+
+  // Call into the auto-generated build method.
+  build(b, state, resultType, input, b.getI64IntegerAttr(index));
+}
+
+static mlir::LogicalResult verify(StructAccessOp op) {
+  StructType structTy = op.input().getType().cast<StructType>();
+  size_t index = op.index();
+  if (index >= structTy.getNumElementTypes())
+    return op.emitOpError()
+           << "index should be within the range of the input struct type";
+  mlir::Type resultType = op.getResult().getType();
+  if (resultType != structTy.getElementTypes()[index])
+    return op.emitOpError() << "must have the same result type as the struct "
+                               "element referred to by the index";
+  return mlir::success();
+}
+
+//===----------------------------------------------------------------------===//
+// TanhOp
+
+void TanhOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
+                   mlir::Value input) {
+  state.addTypes(getUnrankedLike(input));
+  state.addOperands(input);
+}
+
+/// Infer the output shape of the TanhOp, this is required by the shape
+/// inference interface.
+void TanhOp::inferShapes() { getResult().setType(input().getType()); }
+
 //===----------------------------------------------------------------------===//
 // TransposeOp
 
 void TransposeOp::build(mlir::OpBuilder &builder, mlir::OperationState &state,
                         mlir::Value value) {
-  state.addTypes(UnrankedTensorType::get(builder.getF64Type()));
+  state.addTypes(getUnrankedLike(value));
   state.addOperands(value);
 }
 
@@ -350,6 +945,133 @@ static mlir::LogicalResult verify(TransposeOp op) {
   return mlir::success();
 }
 
+//===----------------------------------------------------------------------===//
+// Toy Types
+//===----------------------------------------------------------------------===//
+
+namespace mlir {
+namespace toy {
+namespace detail {
+/// This class represents the internal storage of the Toy `StructType`.
+struct StructTypeStorage : public mlir::TypeStorage {
+  /// The `KeyTy` is a required type that provides an interface for the storage
+  /// instance. This type will be used when uniquing an instance of the type
+  /// storage. For our struct type, we will unique each instance structurally on
+  /// the elements that it contains.
+  using KeyTy = llvm::ArrayRef<mlir::Type>;
+
+  /// A constructor for the type storage instance.
+  StructTypeStorage(llvm::ArrayRef<mlir::Type> elementTypes)
+      : elementTypes(elementTypes) {}
+
+  /// Define the comparison function for the key type with the current storage
+  /// instance. This is used when constructing a new instance to ensure that we
+  /// haven't already uniqued an instance of the given key.
+  bool operator==(const KeyTy &key) const { return key == elementTypes; }
+
+  /// Define a hash function for the key type. This is used when uniquing
+  /// instances of the storage, see the `StructType::get` method.
+  static llvm::hash_code hashKey(const KeyTy &key) {
+    return llvm::hash_value(key);
+  }
+
+  /// Define a construction function for the key type from a set of parameters.
+  static KeyTy getKey(llvm::ArrayRef<mlir::Type> elementTypes) {
+    return KeyTy(elementTypes);
+  }
+
+  /// Define a construction method for creating a new instance of this storage.
+  /// This method takes an instance of a storage allocator, and an instance of a
+  /// `KeyTy`. The given allocator must be used for *all* necessary dynamic
+  /// allocations used to create the type storage and its internal.
+  static StructTypeStorage *construct(mlir::TypeStorageAllocator &allocator,
+                                      const KeyTy &key) {
+    // Copy the elements from the provided `KeyTy` into the allocator.
+    llvm::ArrayRef<mlir::Type> elementTypes = allocator.copyInto(key);
+
+    // Allocate the storage instance and construct it.
+    return new (allocator.allocate<StructTypeStorage>())
+        StructTypeStorage(elementTypes);
+  }
+
+  /// The following field contains the element types of the struct.
+  llvm::ArrayRef<mlir::Type> elementTypes;
+};
+} // namespace detail
+} // namespace toy
+} // namespace mlir
+
+/// Create an instance of a `StructType` with the given element types. There
+/// *must* be at least one element type.
+StructType StructType::get(llvm::ArrayRef<mlir::Type> elementTypes) {
+  assert(!elementTypes.empty() && "expected at least 1 element type");
+
+  // Call into a helper 'get' method in 'TypeBase' to get a uniqued instance
+  // of this type. The first parameter is the context to unique in. The
+  // parameters after the context are forwarded to the storage instance.
+  mlir::MLIRContext *ctx = elementTypes.front().getContext();
+  return Base::get(ctx, elementTypes);
+}
+
+/// Returns the element types of this struct type.
+llvm::ArrayRef<mlir::Type> StructType::getElementTypes() {
+  // 'getImpl' returns a pointer to the internal storage instance.
+  return getImpl()->elementTypes;
+}
+
+/// Parse an instance of a type registered to the toy dialect.
+mlir::Type ToyDialect::parseType(mlir::DialectAsmParser &parser) const {
+  // Parse a struct type in the following form:
+  //   struct-type ::= `struct` `<` type (`,` type)* `>`
+
+  // NOTE: All MLIR parser function return a ParseResult. This is a
+  // specialization of LogicalResult that auto-converts to a `true` boolean
+  // value on failure to allow for chaining, but may be used with explicit
+  // `mlir::failed/mlir::succeeded` as desired.
+
+  // Parse: `struct` `<`
+  if (parser.parseKeyword("struct") || parser.parseLess())
+    return Type();
+
+  // Parse the element types of the struct.
+  SmallVector<mlir::Type, 1> elementTypes;
+  do {
+    // Parse the current element type.
+    SMLoc typeLoc = parser.getCurrentLocation();
+    mlir::Type elementType;
+    if (parser.parseType(elementType))
+      return nullptr;
+
+    // Check that the type is either a TensorType or another StructType.
+    if (!elementType.isa<mlir::TensorType, StructType>()) {
+      parser.emitError(typeLoc, "element type for a struct must either "
+                                "be a TensorType or a StructType, got: ")
+          << elementType;
+      return Type();
+    }
+    elementTypes.push_back(elementType);
+
+    // Parse the optional: `,`
+  } while (succeeded(parser.parseOptionalComma()));
+
+  // Parse: `>`
+  if (parser.parseGreater())
+    return Type();
+  return StructType::get(elementTypes);
+}
+
+/// Print an instance of a type registered to the toy dialect.
+void ToyDialect::printType(mlir::Type type,
+                           mlir::DialectAsmPrinter &printer) const {
+  // Currently the only toy type is a struct type.
+  StructType structType = type.cast<StructType>();
+
+  // Print the struct type according to the parser format.
+  printer << "struct<";
+  llvm::interleaveComma(structType.getElementTypes(), printer);
+  printer << '>';
+}
+
 //===----------------------------------------------------------------------===//
 // TableGen'd op method definitions
 //===----------------------------------------------------------------------===//
@@ -8,7 +8,8 @@
 //
 // This file implements a partial lowering of Toy operations to a combination of
 // affine loops, memref operations and standard operations. This lowering
-// expects that all calls have been inlined, and all shapes have been resolved.
+// expects that the calls left have been specialized, and all shapes have been
+// resolved.
 //
 //===----------------------------------------------------------------------===//
 
@@ -17,7 +18,9 @@
 
 #include "mlir/Dialect/Affine/IR/AffineOps.h"
 #include "mlir/Dialect/Arithmetic/IR/Arithmetic.h"
+#include "mlir/Dialect/Math/IR/Math.h"
 #include "mlir/Dialect/MemRef/IR/MemRef.h"
+#include "mlir/Dialect/SCF/SCF.h"
 #include "mlir/Dialect/StandardOps/IR/Ops.h"
 #include "mlir/Pass/Pass.h"
 #include "mlir/Transforms/DialectConversion.h"
@@ -37,20 +40,65 @@ static MemRefType convertTensorToMemRef(TensorType type) {
 
 /// Insert an allocation and deallocation for the given MemRefType.
 static Value insertAllocAndDealloc(MemRefType type, Location loc,
-                                   PatternRewriter &rewriter) {
-  auto alloc = rewriter.create<memref::AllocOp>(loc, type);
+                                   OpBuilder &builder) {
+  auto alloc = builder.create<memref::AllocOp>(loc, type);
 
-  // Make sure to allocate at the beginning of the block.
-  auto *parentBlock = alloc->getBlock();
+  // Make sure to allocate at the beginning of the function. Operations nested
+  // in a loop then reuse the same buffer at each iteration.
+  auto *parentBlock = &alloc->getParentOfType<FuncOp>().getBody().front();
   alloc->moveBefore(&parentBlock->front());
 
-  // Make sure to deallocate this alloc at the end of the block. This is fine
-  // as toy functions have no control flow.
-  auto dealloc = rewriter.create<memref::DeallocOp>(loc, alloc);
+  // Make sure to deallocate this alloc at the end of the function. This is
+  // fine as the control flow of toy functions is structured: their entry block
+  // is the only one and ends with the return.
+  auto dealloc = builder.create<memref::DeallocOp>(loc, alloc);
   dealloc->moveBefore(&parentBlock->back());
   return alloc;
 }
 
+/// Copy the elements of the `source` memref into the `dest` one, which has the
+/// same shape.
+static void copyMemRef(OpBuilder &builder, Location loc, Value source,
+                       Value dest) {
+  auto memRefType = dest.getType().cast<MemRefType>();
+  SmallVector<int64_t, 4> lowerBounds(memRefType.getRank(), /*Value=*/0);
+  SmallVector<int64_t, 4> steps(memRefType.getRank(), /*Value=*/1);
+  buildAffineLoopNest(
+      builder, loc, lowerBounds, memRefType.getShape(), steps,
+      [&](OpBuilder &nestedBuilder, Location loc, ValueRange ivs) {
+        auto element = nestedBuilder.create<AffineLoadOp>(loc, source, ivs);
+        nestedBuilder.create<AffineStoreOp>(loc, element, dest, ivs);
+      });
+}
+
+/// Load the element of the `memRef` operand of a broadcasting operation which
+/// contributes to the result at index `loopIvs`. The operand shape is aligned
+/// on the trailing dimensions of the result, and its size-1 dimensions are
+/// always accessed at index 0, so that they are repeated along the result.
+static Value loadBroadcasted(OpBuilder &builder, Location loc, Value memRef,
+                             ValueRange loopIvs) {
+  ArrayRef<int64_t> shape = memRef.getType().cast<MemRefType>().getShape();
+  unsigned offset = loopIvs.size() - shape.size();
+  SmallVector<AffineExpr, 4> exprs;
+  for (auto it : llvm::enumerate(shape))
+    exprs.push_back(it.value() == 1
+                        ? builder.getAffineConstantExpr(0)
+                        : builder.getAffineDimExpr(offset + it.index()));
+  auto map = AffineMap::get(loopIvs.size(), /*symbolCount=*/0, exprs,
+                            builder.getContext());
+  return builder.create<AffineLoadOp>(loc, memRef, map, loopIvs);
+}
+
+/// Create a `FloatOp` combining `lhs` and `rhs` if they are floating-point
+/// values, or an `IntOp` if they are integers.
+template <typename FloatOp, typename IntOp>
+static Value createArithOp(OpBuilder &builder, Location loc, Value lhs,
+                           Value rhs) {
+  if (lhs.getType().isa<IntegerType>())
+    return builder.create<IntOp>(loc, lhs, rhs);
+  return builder.create<FloatOp>(loc, lhs, rhs);
+}
+
 /// This defines the function type used to process an iteration of a lowered
 /// loop. It takes as input an OpBuilder, an range of memRefOperands
 /// corresponding to the operands of the input operation, and the range of loop
@@ -94,7 +142,7 @@ namespace {
 // ToyToAffine RewritePatterns: Binary operations
 //===----------------------------------------------------------------------===//
 
-template <typename BinaryOp, typename LoweredBinaryOp>
+template <typename BinaryOp, typename LoweredFloatOp, typename LoweredIntOp>
 struct BinaryOpLowering : public ConversionPattern {
   BinaryOpLowering(MLIRContext *ctx)
       : ConversionPattern(BinaryOp::getOperationName(), 1, ctx) {}
@@ -113,20 +161,96 @@ struct BinaryOpLowering : public ConversionPattern {
           typename BinaryOp::Adaptor binaryAdaptor(memRefOperands);
 
           // Generate loads for the element of 'lhs' and 'rhs' at the inner
-          // loop.
+          // loop, repeating the operands broadcast along the result.
           auto loadedLhs =
-              builder.create<AffineLoadOp>(loc, binaryAdaptor.lhs(), loopIvs);
+              loadBroadcasted(builder, loc, binaryAdaptor.lhs(), loopIvs);
           auto loadedRhs =
-              builder.create<AffineLoadOp>(loc, binaryAdaptor.rhs(), loopIvs);
+              loadBroadcasted(builder, loc, binaryAdaptor.rhs(), loopIvs);
 
           // Create the binary operation performed on the loaded values.
-          return builder.create<LoweredBinaryOp>(loc, loadedLhs, loadedRhs);
+          return createArithOp<LoweredFloatOp, LoweredIntOp>(
+              builder, loc, loadedLhs, loadedRhs);
+        });
+    return success();
+  }
+};
+using AddOpLowering =
+    BinaryOpLowering<toy::AddOp, arith::AddFOp, arith::AddIOp>;
+using DivOpLowering =
+    BinaryOpLowering<toy::DivOp, arith::DivFOp, arith::DivSIOp>;
+using MulOpLowering =
+    BinaryOpLowering<toy::MulOp, arith::MulFOp, arith::MulIOp>;
+using SubOpLowering =
+    BinaryOpLowering<toy::SubOp, arith::SubFOp, arith::SubIOp>;
+
+//===----------------------------------------------------------------------===//
+// ToyToAffine RewritePatterns: Unary operations
+//===----------------------------------------------------------------------===//
+
+template <typename UnaryOp, typename LoweredUnaryOp>
+struct UnaryOpLowering : public ConversionPattern {
+  UnaryOpLowering(MLIRContext *ctx)
+      : ConversionPattern(UnaryOp::getOperationName(), 1, ctx) {}
+
+  LogicalResult
+  matchAndRewrite(Operation *op, ArrayRef<Value> operands,
+                  ConversionPatternRewriter &rewriter) const final {
+    auto loc = op->getLoc();
+    lowerOpToLoops(op, operands, rewriter,
+                   [loc](OpBuilder &builder, ValueRange memRefOperands,
+                         ValueRange loopIvs) {
+                     typename UnaryOp::Adaptor unaryAdaptor(memRefOperands);
+                     auto loadedInput = builder.create<AffineLoadOp>(
+                         loc, unaryAdaptor.input(), loopIvs);
+                     return builder.create<LoweredUnaryOp>(loc, loadedInput);
+                   });
+    return success();
+  }
+};
+using AbsOpLowering = UnaryOpLowering<toy::AbsOp, math::AbsOp>;
+using ExpOpLowering = UnaryOpLowering<toy::ExpOp, math::ExpOp>;
+using LogOpLowering = UnaryOpLowering<toy::LogOp, math::LogOp>;
+using NegOpLowering = UnaryOpLowering<toy::NegOp, arith::NegFOp>;
+using SqrtOpLowering = UnaryOpLowering<toy::SqrtOp, math::SqrtOp>;
+using TanhOpLowering = UnaryOpLowering<toy::TanhOp, math::TanhOp>;
+
+//===----------------------------------------------------------------------===//
+// ToyToAffine RewritePatterns: Comparison operations
+//===----------------------------------------------------------------------===//
+
+struct LtOpLowering : public ConversionPattern {
+  LtOpLowering(MLIRContext *ctx)
+      : ConversionPattern(toy::LtOp::getOperationName(), 1, ctx) {}
+
+  LogicalResult
+  matchAndRewrite(Operation *op, ArrayRef<Value> operands,
+                  ConversionPatternRewriter &rewriter) const final {
+    auto loc = op->getLoc();
+    lowerOpToLoops(
+        op, operands, rewriter,
+        [loc](OpBuilder &builder, ValueRange memRefOperands,
+              ValueRange loopIvs) -> Value {
+          toy::LtOp::Adaptor ltAdaptor(memRefOperands);
+          auto loadedLhs =
+              loadBroadcasted(builder, loc, ltAdaptor.lhs(), loopIvs);
+          auto loadedRhs =
+              loadBroadcasted(builder, loc, ltAdaptor.rhs(), loopIvs);
+
+          // The comparison yields an i1, which is turned into a 0 or a 1 of
+          // the element type of the operands, as Toy has no boolean tensors.
+          Type elementType = loadedLhs.getType();
+          if (elementType.isa<IntegerType>()) {
+            auto isLess = builder.create<arith::CmpIOp>(
+                loc, arith::CmpIPredicate::slt, loadedLhs, loadedRhs);
+            return builder.create<arith::ExtUIOp>(loc, elementType, isLess);
+          }
+          auto isLess = builder.create<arith::CmpFOp>(
+              loc, arith::CmpFPredicate::OLT, loadedLhs, loadedRhs);
+          return builder.create<arith::UIToFPOp>(loc, elementType, isLess);
         });
     return success();
   }
 };
-using AddOpLowering = BinaryOpLowering<toy::AddOp, arith::AddFOp>;
-using MulOpLowering = BinaryOpLowering<toy::MulOp, arith::MulFOp>;
 
 //===----------------------------------------------------------------------===//
 // ToyToAffine RewritePatterns: Constant operations
@@ -168,7 +292,9 @@ struct ConstantOpLowering : public OpRewritePattern<toy::ConstantOp> {
     // functor recursively walks the dimensions of the constant shape,
     // generating a store when the recursion hits the base case.
     SmallVector<Value, 2> indices;
-    auto valueIt = constantValue.value_begin<FloatAttr>();
+    // NB: the elements are float or integer attributes, depending on the
+    // element type of the constant
+    auto valueIt = constantValue.value_begin<Attribute>();
     std::function<void(uint64_t)> storeElements = [&](uint64_t dimension) {
       // The last dimension is the base case of the recursion, at this point
       // we store the element at the given index.
@@ -197,6 +323,207 @@ struct ConstantOpLowering : public OpRewritePattern<toy::ConstantOp> {
   }
 };
 
+//===----------------------------------------------------------------------===//
+// ToyToAffine RewritePatterns: Indexing operations
+//===----------------------------------------------------------------------===//
+
+/// Returns the integers held by an array attribute.
+static SmallVector<int64_t, 4> getI64Array(ArrayAttr attr) {
+  SmallVector<int64_t, 4> values;
+  for (auto value : attr.getAsRange<IntegerAttr>())
+    values.push_back(value.getInt());
+  return values;
+}
+
+struct ExtractOpLowering : public ConversionPattern {
+  ExtractOpLowering(MLIRContext *ctx)
+      : ConversionPattern(toy::ExtractOp::getOperationName(), 1, ctx) {}
+
+  LogicalResult
+  matchAndRewrite(Operation *op, ArrayRef<Value> operands,
+                  ConversionPatternRewriter &rewriter) const final {
+    auto loc = op->getLoc();
+    auto dims = getI64Array(cast<toy::ExtractOp>(op).dims());
+    lowerOpToLoops(
+        op, operands, rewriter,
+        [&](OpBuilder &builder, ValueRange memRefOperands,
+            ValueRange loopIvs) {
+          Value input = memRefOperands.front();
+          auto indices = memRefOperands.drop_front();
+
+          // The extracted dimensions are accessed at the index loaded from
+          // their operand, which isn't an affine expression, and the other
+          // ones at the next loop induction variable.
+          SmallVector<Value, 4> ivs;
+          auto index = indices.begin();
+          auto loopIv = loopIvs.begin();
+          int64_t rank = input.getType().cast<MemRefType>().getRank();
+          for (int64_t dim = 0; dim < rank; ++dim) {
+            if (!llvm::is_contained(dims, dim)) {
+              ivs.push_back(*loopIv++);
+              continue;
+            }
+            Value element = builder.create<memref::LoadOp>(loc, *index++);
+            Value integer = builder.create<arith::FPToSIOp>(
+                loc, builder.getI64Type(), element);
+            ivs.push_back(builder.create<arith::IndexCastOp>(
+                loc, builder.getIndexType(), integer));
+          }
+          return builder.create<memref::LoadOp>(loc, input, ivs);
+        });
+    return success();
+  }
+};
+
+struct SliceOpLowering : public ConversionPattern {
+  SliceOpLowering(MLIRContext *ctx)
+      : ConversionPattern(toy::SliceOp::getOperationName(), 1, ctx) {}
+
+  LogicalResult
+  matchAndRewrite(Operation *op, ArrayRef<Value> operands,
+                  ConversionPatternRewriter &rewriter) const final {
+    auto loc = op->getLoc();
+    auto starts = getI64Array(cast<toy::SliceOp>(op).starts());
+    lowerOpToLoops(
+        op, operands, rewriter,
+        [&](OpBuilder &builder, ValueRange memRefOperands,
+            ValueRange loopIvs) {
+          toy::SliceOpAdaptor sliceAdaptor(memRefOperands);
+
+          // Offset the indices of the sliced dimensions by the start of their
+          // range.
+          SmallVector<AffineExpr, 4> exprs;
+          for (unsigned i = 0; i < loopIvs.size(); ++i) {
+            AffineExpr expr = builder.getAffineDimExpr(i);
+            exprs.push_back(i < starts.size() ? expr + starts[i] : expr);
+          }
+          auto map = AffineMap::get(loopIvs.size(), /*symbolCount=*/0, exprs,
+                                    builder.getContext());
+          return builder.create<AffineLoadOp>(loc, sliceAdaptor.input(), map,
+                                              loopIvs);
+        });
+    return success();
+  }
+};
+
+//===----------------------------------------------------------------------===//
+// ToyToAffine RewritePatterns: Matrix multiplication operations
+//===----------------------------------------------------------------------===//
+
+struct MatMulOpLowering : public ConversionPattern {
+  MatMulOpLowering(MLIRContext *ctx)
+      : ConversionPattern(toy::MatMulOp::getOperationName(), 1, ctx) {}
+
+  LogicalResult
+  matchAndRewrite(Operation *op, ArrayRef<Value> operands,
+                  ConversionPatternRewriter &rewriter) const final {
+    auto loc = op->getLoc();
+    lowerOpToLoops(
+        op, operands, rewriter,
+        [loc](OpBuilder &builder, ValueRange memRefOperands,
+              ValueRange loopIvs) {
+          toy::MatMulOpAdaptor matmulAdaptor(memRefOperands);
+          Value lhs = matmulAdaptor.lhs();
+          Value rhs = matmulAdaptor.rhs();
+          auto lhsType = lhs.getType().cast<MemRefType>();
+          int64_t inner = lhsType.getDimSize(1);
+
+          // Accumulate the products of the row of `lhs` and the column of
+          // `rhs` in a loop carrying the partial sum.
+          Value zero = builder.create<arith::ConstantOp>(
+              loc, builder.getZeroAttr(lhsType.getElementType()));
+          auto loop = builder.create<AffineForOp>(
+              loc, /*lowerBound=*/0, inner, /*step=*/1, ValueRange{zero},
+              [&](OpBuilder &nestedBuilder, Location loc, Value iv,
+                  ValueRange iterArgs) {
+                Value lhsElement = nestedBuilder.create<AffineLoadOp>(
+                    loc, lhs, ValueRange{loopIvs[0], iv});
+                Value rhsElement = nestedBuilder.create<AffineLoadOp>(
+                    loc, rhs, ValueRange{iv, loopIvs[1]});
+                Value product =
+                    createArithOp<arith::MulFOp, arith::MulIOp>(
+                        nestedBuilder, loc, lhsElement, rhsElement);
+                Value sum = createArithOp<arith::AddFOp, arith::AddIOp>(
+                    nestedBuilder, loc, iterArgs.front(), product);
+                nestedBuilder.create<AffineYieldOp>(loc, sum);
+              });
+          return loop.getResult(0);
+        });
+    return success();
+  }
+};
+
+//===----------------------------------------------------------------------===//
+// ToyToAffine RewritePatterns: Reduction operations
+//===----------------------------------------------------------------------===//
+
+template <typename ReductionOp, typename LoweredFloatOp,
+          typename LoweredIntOp>
+struct ReductionOpLowering : public ConversionPattern {
+  ReductionOpLowering(MLIRContext *ctx)
+      : ConversionPattern(ReductionOp::getOperationName(), 1, ctx) {}
+
+  LogicalResult
+  matchAndRewrite(Operation *op, ArrayRef<Value> operands,
+                  ConversionPatternRewriter &rewriter) const final {
+    auto loc = op->getLoc();
+    int64_t axis = static_cast<int64_t>(cast<ReductionOp>(op).axis());
+    bool isMean = isa<toy::MeanOp>(op);
+    lowerOpToLoops(
+        op, operands, rewriter,
+        [&](OpBuilder &builder, ValueRange memRefOperands,
+            ValueRange loopIvs) {
+          typename ReductionOp::Adaptor reductionAdaptor(memRefOperands);
+          Value input = reductionAdaptor.input();
+          int64_t size = input.getType().cast<MemRefType>().getDimSize(axis);
+
+          // The input is accessed at the index of the result, with the index
+          // along the reduced dimension inserted.
+          auto loadInput = [&](OpBuilder &loadBuilder, Value index) -> Value {
+            SmallVector<Value, 4> ivs(loopIvs.begin(), loopIvs.end());
+            ivs.insert(ivs.begin() + axis, index);
+            return loadBuilder.create<AffineLoadOp>(loc, input, ivs);
+          };
+
+          // Start from the first element along the reduced dimension, and
+          // combine it with the next ones in a loop carrying the partial
+          // result.
+          Value zero = builder.create<arith::ConstantIndexOp>(loc, 0);
+          Value first = loadInput(builder, zero);
+          auto loop = builder.create<AffineForOp>(
+              loc, /*lowerBound=*/1, size, /*step=*/1, ValueRange{first},
+              [&](OpBuilder &nestedBuilder, Location loc, Value iv,
+                  ValueRange iterArgs) {
+                Value element = loadInput(nestedBuilder, iv);
+                Value combined = createArithOp<LoweredFloatOp, LoweredIntOp>(
+                    nestedBuilder, loc, iterArgs.front(), element);
+                nestedBuilder.create<AffineYieldOp>(loc, combined);
+              });
+          Value result = loop.getResult(0);
+          if (!isMean)
+            return result;
+
+          // The mean is the sum divided by the number of elements, rounded
+          // towards zero for integers.
+          Type elementType = result.getType();
+          Attribute countAttr =
+              elementType.isa<IntegerType>()
+                  ? Attribute(builder.getIntegerAttr(elementType, size))
+                  : Attribute(builder.getFloatAttr(elementType, size));
+          Value count = builder.create<arith::ConstantOp>(loc, countAttr);
+          return createArithOp<arith::DivFOp, arith::DivSIOp>(builder, loc,
+                                                             result, count);
+        });
+    return success();
+  }
+};
+using MaxOpLowering =
+    ReductionOpLowering<toy::MaxOp, arith::MaxFOp, arith::MaxSIOp>;
+using MeanOpLowering =
+    ReductionOpLowering<toy::MeanOp, arith::AddFOp, arith::AddIOp>;
+using SumOpLowering =
+    ReductionOpLowering<toy::SumOp, arith::AddFOp, arith::AddIOp>;
+
 //===----------------------------------------------------------------------===//
 // ToyToAffine RewritePatterns: Print operations
 //===----------------------------------------------------------------------===//
@@ -219,22 +546,63 @@ struct PrintOpLowering : public OpConversionPattern<toy::PrintOp> {
 // ToyToAffine RewritePatterns: Return operations
 //===----------------------------------------------------------------------===//
 
-struct ReturnOpLowering : public OpRewritePattern<toy::ReturnOp> {
-  using OpRewritePattern<toy::ReturnOp>::OpRewritePattern;
+struct ReturnOpLowering : public ConversionPattern {
+  ReturnOpLowering(MLIRContext *ctx)
+      : ConversionPattern(toy::ReturnOp::getOperationName(), 1, ctx) {}
 
-  LogicalResult matchAndRewrite(toy::ReturnOp op,
-                                PatternRewriter &rewriter) const final {
-    // During this lowering, we expect that all function calls have been
-    // inlined.
-    if (op.hasOperand())
-      return failure();
+  LogicalResult
+  matchAndRewrite(Operation *op, ArrayRef<Value> operands,
+                  ConversionPatternRewriter &rewriter) const final {
+    // The returned values are copied into the buffers given by the caller,
+    // which are the last arguments of the function. The copies go before the
+    // deallocations of the buffers of the function, which end its body.
+    Block *block = op->getBlock();
+    Operation *insertionPoint = op;
+    while (insertionPoint->getPrevNode() &&
+           isa<memref::DeallocOp>(insertionPoint->getPrevNode()))
+      insertionPoint = insertionPoint->getPrevNode();
+    rewriter.setInsertionPoint(insertionPoint);
+    unsigned firstResult = block->getNumArguments() - operands.size();
+    for (auto it : llvm::enumerate(operands))
+      copyMemRef(rewriter, op->getLoc(), it.value(),
+                 block->getArgument(firstResult + it.index()));
 
     // We lower "toy.return" directly to "std.return".
+    rewriter.setInsertionPoint(op);
     rewriter.replaceOpWithNewOp<ReturnOp>(op);
     return success();
   }
 };
 
+//===----------------------------------------------------------------------===//
+// ToyToAffine RewritePatterns: GenericCall operations
+//===----------------------------------------------------------------------===//
+
+struct GenericCallOpLowering : public ConversionPattern {
+  GenericCallOpLowering(MLIRContext *ctx)
+      : ConversionPattern(toy::GenericCallOp::getOperationName(), 1, ctx) {}
+
+  LogicalResult
+  matchAndRewrite(Operation *op, ArrayRef<Value> operands,
+                  ConversionPatternRewriter &rewriter) const final {
+    auto call = cast<toy::GenericCallOp>(op);
+    auto loc = op->getLoc();
+
+    // The caller allocates the buffers of the results, which the callee fills
+    // in: they are released along with the other buffers of the caller.
+    SmallVector<Value, 4> buffers;
+    for (Type type : op->getResultTypes())
+      buffers.push_back(insertAllocAndDealloc(
+          convertTensorToMemRef(type.cast<TensorType>()), loc, rewriter));
+
+    SmallVector<Value, 4> callOperands(operands.begin(), operands.end());
+    callOperands.append(buffers.begin(), buffers.end());
+    rewriter.create<CallOp>(loc, call.callee(), TypeRange(), callOperands);
+    rewriter.replaceOp(op, buffers);
+    return success();
+  }
+};
+
 //===----------------------------------------------------------------------===//
 // ToyToAffine RewritePatterns: Transpose operations
 //===----------------------------------------------------------------------===//
@@ -279,35 +647,164 @@ namespace {
 struct ToyToAffineLoweringPass
     : public PassWrapper<ToyToAffineLoweringPass, OperationPass<FuncOp>> {
   void getDependentDialects(DialectRegistry &registry) const override {
-    registry.insert<AffineDialect, memref::MemRefDialect, StandardOpsDialect>();
+    registry.insert<AffineDialect, math::MathDialect, memref::MemRefDialect,
+                    scf::SCFDialect, StandardOpsDialect>();
   }
   void runOnOperation() final;
 };
 } // namespace
 
+//===----------------------------------------------------------------------===//
+// ToyToAffine Lowering: Control flow operations
+//===----------------------------------------------------------------------===//
+
+// Loops and conditionals are lowered once the operations of their regions
+// have been. The values they carry then live in buffers, which are updated
+// with the yielded values at the end of the regions.
+
+/// Move the operations of `body`, a region of "toy.for" or "toy.if", before
+/// the terminator of `dest`, and copy the values it yields into `buffers`.
+static void inlineBody(Block &body, Block *dest, ArrayRef<Value> buffers) {
+  auto yield = cast<toy::YieldOp>(body.getTerminator());
+  Operation *terminator = dest->getTerminator();
+  dest->getOperations().splice(Block::iterator(terminator),
+                               body.getOperations(), body.begin(),
+                               Block::iterator(yield));
+
+  // A yielded value may be the buffer of another carried value, which is
+  // overwritten below: copy it first so that all the values are updated at
+  // once, e.g. when swapping two values.
+  OpBuilder builder(terminator);
+  Location loc = yield.getLoc();
+  SmallVector<Value, 4> sources;
+  for (auto it : llvm::zip(yield.getOperands(), buffers)) {
+    Value value = std::get<0>(it);
+    if (value != std::get<1>(it) && llvm::is_contained(buffers, value)) {
+      auto copy = insertAllocAndDealloc(value.getType().cast<MemRefType>(),
+                                        loc, builder);
+      copyMemRef(builder, loc, value, copy);
+      value = copy;
+    }
+    sources.push_back(value);
+  }
+
+  for (auto it : llvm::zip(sources, buffers))
+    if (std::get<0>(it) != std::get<1>(it))
+      copyMemRef(builder, loc, std::get<0>(it), std::get<1>(it));
+  yield.erase();
+}
+
+/// Lower a "toy.for" to an affine loop.
+static void lowerForOp(toy::ForOp op) {
+  OpBuilder builder(op);
+  Location loc = op.getLoc();
+  Block &body = op.body().front();
+
+  // The carried values start with the initial ones.
+  SmallVector<Value, 4> buffers;
+  for (Value init : op.initArgs()) {
+    auto buffer = insertAllocAndDealloc(init.getType().cast<MemRefType>(),
+                                        loc, builder);
+    copyMemRef(builder, loc, init, buffer);
+    buffers.push_back(buffer);
+  }
+
+  // The induction variable is given to the body as a memref<f64>.
+  auto inductionVar = insertAllocAndDealloc(
+      body.getArgument(0).getType().cast<MemRefType>(), loc, builder);
+  auto loop = builder.create<AffineForOp>(
+      loc, static_cast<int64_t>(op.lowerBound()),
+      static_cast<int64_t>(op.upperBound()));
+  builder.setInsertionPointToStart(loop.getBody());
+  auto index = builder.create<arith::IndexCastOp>(loc, builder.getI64Type(),
+                                                  loop.getInductionVar());
+  auto element =
+      builder.create<arith::SIToFPOp>(loc, builder.getF64Type(), index);
+  builder.create<memref::StoreOp>(loc, element, inductionVar);
+
+  body.getArgument(0).replaceAllUsesWith(inductionVar);
+  for (auto it : llvm::enumerate(buffers))
+    body.getArgument(it.index() + 1).replaceAllUsesWith(it.value());
+  inlineBody(body, loop.getBody(), buffers);
+
+  op->replaceAllUsesWith(buffers);
+  op.erase();
+}
+
+/// Lower a "toy.if" to a conditional of the SCF dialect.
+static void lowerIfOp(toy::IfOp op) {
+  OpBuilder builder(op);
+  Location loc = op.getLoc();
+
+  SmallVector<Value, 4> buffers;
+  for (Value result : op->getResults())
+    buffers.push_back(insertAllocAndDealloc(
+        result.getType().cast<MemRefType>(), loc, builder));
+
+  // The condition holds when its first element is not zero.
+  auto conditionType = op.condition().getType().cast<MemRefType>();
+  Value zero = builder.create<arith::ConstantIndexOp>(loc, 0);
+  SmallVector<Value, 4> indices(conditionType.getRank(), zero);
+  Value element = builder.create<memref::LoadOp>(loc, op.condition(), indices);
+  Value zeroElement = builder.create<arith::ConstantOp>(
+      loc, builder.getZeroAttr(conditionType.getElementType()));
+  Value condition;
+  if (conditionType.getElementType().isa<IntegerType>())
+    condition = builder.create<arith::CmpIOp>(loc, arith::CmpIPredicate::ne,
+                                              element, zeroElement);
+  else
+    condition = builder.create<arith::CmpFOp>(loc, arith::CmpFPredicate::UNE,
+                                              element, zeroElement);
+
+  auto ifOp = builder.create<scf::IfOp>(loc, condition,
+                                        /*withElseRegion=*/true);
+  inlineBody(op.thenRegion().front(), ifOp.thenBlock(), buffers);
+  inlineBody(op.elseRegion().front(), ifOp.elseBlock(), buffers);
+
+  op->replaceAllUsesWith(buffers);
+  op.erase();
+}
+
 void ToyToAffineLoweringPass::runOnOperation() {
   FuncOp function = getOperation();
 
-  // We only lower the main function as we expect that all other functions have
-  // been inlined.
-  if (function.getName() != "main")
-    return;
-
   // Verify that the given main has no inputs and results.
-  if (function.getNumArguments() || function.getType().getNumResults()) {
+  if (function.getName() == "main" &&
+      (function.getNumArguments() || function.getType().getNumResults())) {
     function.emitError("expected 'main' to have 0 inputs and 0 results");
     return signalPassFailure();
   }
 
+  // The other functions take their arguments as memrefs, followed by the
+  // buffers their callers allocate for their results, and return nothing.
+  SmallVector<Type, 4> argTypes;
+  for (Type type : llvm::concat<const Type>(function.getType().getInputs(),
+                                            function.getType().getResults())) {
+    auto tensorType = type.dyn_cast<RankedTensorType>();
+    if (!tensorType) {
+      function.emitError("expected the arguments and results of '")
+          << function.getName() << "' to be ranked tensors";
+      return signalPassFailure();
+    }
+    argTypes.push_back(convertTensorToMemRef(tensorType));
+  }
+  Block &entryBlock = function.getBody().front();
+  for (auto it : llvm::zip(entryBlock.getArguments(), argTypes))
+    std::get<0>(it).setType(std::get<1>(it));
+  for (Type type : llvm::drop_begin(argTypes, entryBlock.getNumArguments()))
+    entryBlock.addArgument(type, function.getLoc());
+  function.setType(FunctionType::get(&getContext(), argTypes, {}));
+
   // The first thing to define is the conversion target. This will define the
   // final target for this lowering.
   ConversionTarget target(getContext());
 
   // We define the specific operations, or dialects, that are legal targets for
   // this lowering. In our case, we are lowering to a combination of the
-  // `Affine`, `Arithmetic`, `MemRef`, and `Standard` dialects.
+  // `Affine`, `Arithmetic`, `Math`, `MemRef`, and `Standard` dialects.
   target.addLegalDialect<AffineDialect, arith::ArithmeticDialect,
-                         memref::MemRefDialect, StandardOpsDialect>();
+                         math::MathDialect, memref::MemRefDialect,
+                         StandardOpsDialect>();
 
   // We also define the Toy dialect as Illegal so that the conversion will fail
   // if any of these operations are *not* converted. Given that we actually want
@@ -321,19 +818,52 @@ void ToyToAffineLoweringPass::runOnOperation() {
                          [](Type type) { return type.isa<TensorType>(); });
   });
 
+  // Loops and conditionals are lowered after their regions, see below. The
+  // values they carry are typed as memrefs beforehand, so that the lowered
+  // operations of and after their regions can use them.
+  target.addLegalOp<toy::ForOp, toy::IfOp, toy::YieldOp>();
+  function.walk([](Operation *op) {
+    if (!isa<toy::ForOp, toy::IfOp>(op))
+      return;
+    for (Value result : op->getResults())
+      result.setType(
+          convertTensorToMemRef(result.getType().cast<TensorType>()));
+    for (Region &region : op->getRegions())
+      for (BlockArgument arg : region.getArguments())
+        arg.setType(convertTensorToMemRef(arg.getType().cast<TensorType>()));
+  });
+
   // Now that the conversion target has been defined, we just need to provide
   // the set of patterns that will lower the Toy operations.
   RewritePatternSet patterns(&getContext());
-  patterns.add<AddOpLowering, ConstantOpLowering, MulOpLowering,
-               PrintOpLowering, ReturnOpLowering, TransposeOpLowering>(
-      &getContext());
+  patterns.add<AbsOpLowering, AddOpLowering, ConstantOpLowering,
+               DivOpLowering, ExpOpLowering, ExtractOpLowering,
+               GenericCallOpLowering, LogOpLowering, LtOpLowering,
+               MatMulOpLowering, MaxOpLowering, MeanOpLowering, MulOpLowering,
+               NegOpLowering, PrintOpLowering, ReturnOpLowering,
+               SliceOpLowering, SqrtOpLowering, SubOpLowering, SumOpLowering,
+               TanhOpLowering, TransposeOpLowering>(&getContext());
 
   // With the target and rewrite patterns defined, we can now attempt the
   // conversion. The conversion will signal failure if any of our `illegal`
   // operations were not converted successfully.
   if (failed(
           applyPartialConversion(getOperation(), target, std::move(patterns))))
-    signalPassFailure();
+    return signalPassFailure();
+
+  // Now lower the loops and conditionals, innermost first as the walk is in
+  // post-order.
+  SmallVector<Operation *, 4> controlFlowOps;
+  function.walk([&](Operation *op) {
+    if (isa<toy::ForOp, toy::IfOp>(op))
+      controlFlowOps.push_back(op);
+  });
+  for (Operation *op : controlFlowOps) {
+    if (auto forOp = dyn_cast<toy::ForOp>(op))
+      lowerForOp(forOp);
+    else
+      lowerIfOp(cast<toy::IfOp>(op));
+  }
 }
 
 /// Create a pass for lowering operations in the `Affine` and `Std` dialects,
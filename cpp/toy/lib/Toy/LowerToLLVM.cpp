@@ -28,6 +28,8 @@
 #include "mlir/Conversion/ArithmeticToLLVM/ArithmeticToLLVM.h"
 #include "mlir/Conversion/LLVMCommon/ConversionTarget.h"
 #include "mlir/Conversion/LLVMCommon/TypeConverter.h"
+#include "mlir/Conversion/MathToLLVM/MathToLLVM.h"
+#include "mlir/Conversion/MathToLibm/MathToLibm.h"
 #include "mlir/Conversion/MemRefToLLVM/MemRefToLLVM.h"
 #include "mlir/Conversion/SCFToStandard/SCFToStandard.h"
 #include "mlir/Conversion/StandardToLLVM/ConvertStandardToLLVM.h"
@@ -35,6 +37,7 @@
 #include "mlir/Dialect/Affine/IR/AffineOps.h"
 #include "mlir/Dialect/Arithmetic/IR/Arithmetic.h"
 #include "mlir/Dialect/LLVMIR/LLVMDialect.h"
+#include "mlir/Dialect/Math/IR/Math.h"
 #include "mlir/Dialect/MemRef/IR/MemRef.h"
 #include "mlir/Dialect/SCF/SCF.h"
 #include "mlir/Dialect/StandardOps/IR/Ops.h"
@@ -66,9 +69,16 @@ public:
     ModuleOp parentModule = op->getParentOfType<ModuleOp>();
 
     // Get a symbol reference to the printf function, inserting it if necessary.
+    // Integers are printed as such, and floats as doubles.
     auto printfRef = getOrInsertPrintf(rewriter, parentModule);
-    Value formatSpecifierCst = getOrCreateGlobalString(
-        loc, rewriter, "frmt_spec", StringRef("%f \0", 4), parentModule);
+    bool isInteger = memRefType.getElementType().isa<IntegerType>();
+    Value formatSpecifierCst =
+        isInteger ? getOrCreateGlobalString(loc, rewriter, "frmt_spec_int",
+                                            StringRef("%ld \0", 5),
+                                            parentModule)
+                  : getOrCreateGlobalString(loc, rewriter, "frmt_spec",
+                                            StringRef("%f \0", 4),
+                                            parentModule);
     Value newLineCst = getOrCreateGlobalString(
         loc, rewriter, "nl", StringRef("\n\0", 2), parentModule);
 
@@ -98,8 +108,12 @@ public:
 
     // Generate a call to printf for the current element of the loop.
     auto printOp = cast<toy::PrintOp>(op);
-    auto elementLoad =
+    Value elementLoad =
         rewriter.create<memref::LoadOp>(loc, printOp.input(), loopIvs);
+    // NB: variadic arguments of type float are promoted to double
+    if (memRefType.getElementType().isF32())
+      elementLoad = rewriter.create<arith::ExtFOp>(loc, rewriter.getF64Type(),
+                                                   elementLoad);
     rewriter.create<CallOp>(loc, printfRef, rewriter.getIntegerType(32),
                             ArrayRef<Value>({formatSpecifierCst, elementLoad}));
 
@@ -203,6 +217,10 @@ void ToyToLLVMLoweringPass::runOnOperation() {
   populateLoopToStdConversionPatterns(patterns);
   mlir::arith::populateArithmeticToLLVMConversionPatterns(typeConverter,
                                                           patterns);
+  // The `math` operations without an LLVM intrinsic, like `math.tanh`, become
+  // calls to their libm function.
+  populateMathToLibmConversionPatterns(patterns, /*benefit=*/1);
+  populateMathToLLVMConversionPatterns(typeConverter, patterns);
   populateMemRefToLLVMConversionPatterns(typeConverter, patterns);
   populateStdToLLVMConversionPatterns(typeConverter, patterns);
 
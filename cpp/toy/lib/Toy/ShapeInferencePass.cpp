@@ -6,7 +6,8 @@
 //
 //===----------------------------------------------------------------------===//
 //
-// This file implements a Function level pass performing interprocedural
+// This file implements a Function level pass performing intraprocedural
+// shape inference, and a Module level pass performing interprocedural
 // propagation of array shapes through function specialization.
 //
 //===----------------------------------------------------------------------===//
@@ -27,9 +28,22 @@ using namespace toy;
 /// Include the auto-generated definitions for the shape inference interfaces.
 #include "Toy/ShapeInferenceOpInterfaces.cpp.inc"
 
-namespace {
-/// The ShapeInferencePass is a pass that performs intra-procedural
-/// shape inference.
+/// Returns whether the given operation has all of its operands inferred.
+static bool allOperandsInferred(Operation *op) {
+  return llvm::all_of(op->getOperandTypes(), [](Type operandType) {
+    return operandType.isa<RankedTensorType>();
+  });
+}
+
+/// Returns whether the given operation has a dynamically shaped result.
+static bool returnsDynamicShape(Operation *op) {
+  return llvm::any_of(op->getResultTypes(), [](Type resultType) {
+    return !resultType.isa<RankedTensorType>();
+  });
+}
+
+/// Infers the shapes of the operations of `f`, as described below. The calls
+/// are handed to `inferCall` once their arguments are inferred, if given.
 ///
 ///    Algorithm:
 ///
@@ -44,65 +58,185 @@ namespace {
 ///     d) infer the shape of its output from the argument types.
 ///   3) If the worklist is empty, the algorithm succeeded.
 ///
+static LogicalResult
+inferShapes(FuncOp f,
+            llvm::function_ref<LogicalResult(GenericCallOp)> inferCall) {
+  // Populate the worklist with the operations that need shape inference:
+  // these are operations that return a dynamic shape.
+  llvm::SmallPtrSet<mlir::Operation *, 16> opWorklist;
+  f.walk([&](mlir::Operation *op) {
+    if (returnsDynamicShape(op))
+      opWorklist.insert(op);
+  });
+
+  // Iterate on the operations in the worklist until all operations have been
+  // inferred or no change happened (fix point).
+  while (!opWorklist.empty()) {
+    // Find the next operation ready for inference, that is an operation
+    // with all operands already resolved (non-generic).
+    auto nextop = llvm::find_if(opWorklist, allOperandsInferred);
+    if (nextop == opWorklist.end())
+      break;
+
+    Operation *op = *nextop;
+    opWorklist.erase(op);
+
+    // Ask the operation to infer its output shapes.
+    LLVM_DEBUG(llvm::dbgs() << "Inferring shape for: " << *op << "\n");
+    auto call = dyn_cast<GenericCallOp>(op);
+    if (call && inferCall) {
+      if (failed(inferCall(call)))
+        return failure();
+    } else if (auto shapeOp = dyn_cast<ShapeInference>(op)) {
+      shapeOp.inferShapes();
+    } else {
+      return op->emitError("unable to infer shape of operation without shape "
+                           "inference interface");
+    }
+  }
+
+  // If the operation worklist isn't empty, this indicates a failure.
+  if (!opWorklist.empty())
+    return f.emitError("Shape inference failed, ")
+           << opWorklist.size() << " operations couldn't be inferred\n";
+  return success();
+}
+
+namespace {
+/// The ShapeInferencePass is a pass that performs intra-procedural
+/// shape inference.
 class ShapeInferencePass
     : public mlir::PassWrapper<ShapeInferencePass, OperationPass<FuncOp>> {
 public:
   void runOnOperation() override {
-    auto f = getOperation();
-
-    // Populate the worklist with the operations that need shape inference:
-    // these are operations that return a dynamic shape.
-    llvm::SmallPtrSet<mlir::Operation *, 16> opWorklist;
-    f.walk([&](mlir::Operation *op) {
-      if (returnsDynamicShape(op))
-        opWorklist.insert(op);
-    });
+    if (failed(inferShapes(getOperation(), nullptr)))
+      signalPassFailure();
+  }
+};
 
-    // Iterate on the operations in the worklist until all operations have been
-    // inferred or no change happened (fix point).
-    while (!opWorklist.empty()) {
-      // Find the next operation ready for inference, that is an operation
-      // with all operands already resolved (non-generic).
-      auto nextop = llvm::find_if(opWorklist, allOperandsInferred);
-      if (nextop == opWorklist.end())
-        break;
-
-      Operation *op = *nextop;
-      opWorklist.erase(op);
-
-      // Ask the operation to infer its output shapes.
-      LLVM_DEBUG(llvm::dbgs() << "Inferring shape for: " << *op << "\n");
-      if (auto shapeOp = dyn_cast<ShapeInference>(op)) {
-        shapeOp.inferShapes();
-      } else {
-        op->emitError("unable to infer shape of operation without shape "
-                      "inference interface");
-        return signalPassFailure();
-      }
-    }
+/// The SpecializationPass is a pass that performs inter-procedural shape
+/// inference, for the calls the inliner left, e.g. recursive ones.
+///
+/// Starting from `main`, the functions are inferred like by the
+/// ShapeInferencePass, but each call is redirected to a clone of its callee
+/// specialized for the types of its arguments, which is inferred first so
+/// that the call gets the types of its results. The private functions left
+/// unreachable from `main`, like the generic ones, are erased then.
+class SpecializationPass
+    : public mlir::PassWrapper<SpecializationPass, OperationPass<ModuleOp>> {
+public:
+  void runOnOperation() override {
+    ModuleOp module = getOperation();
+    SymbolTable symbolTable(module);
+    auto main = symbolTable.lookup<FuncOp>("main");
+    if (!main)
+      return;
 
-    // If the operation worklist isn't empty, this indicates a failure.
-    if (!opWorklist.empty()) {
-      f.emitError("Shape inference failed, ")
-          << opWorklist.size() << " operations couldn't be inferred\n";
-      signalPassFailure();
+    if (failed(inferFunction(main, symbolTable)))
+      return signalPassFailure();
+
+    // NB: the generic functions may still call each other, e.g. recursively,
+    // so they are erased unless they are reachable rather than called.
+    llvm::SmallPtrSet<Operation *, 16> reachable =
+        reachableFunctions(main, symbolTable);
+    for (FuncOp function : llvm::make_early_inc_range(module.getOps<FuncOp>()))
+      if (function.isPrivate() && !reachable.contains(function))
+        symbolTable.erase(function);
+  }
+
+private:
+  /// A clone of `callee` specialized for arguments of types `argTypes`.
+  struct Specialization {
+    FuncOp callee;
+    SmallVector<Type, 4> argTypes;
+    FuncOp function;
+  };
+
+  /// The specializations created so far, in order of creation.
+  SmallVector<Specialization, 8> specializations;
+
+  /// Returns the functions called from `main`, directly or not, along with
+  /// it.
+  llvm::SmallPtrSet<Operation *, 16>
+  reachableFunctions(FuncOp main, SymbolTable &symbolTable) {
+    llvm::SmallPtrSet<Operation *, 16> reachable;
+    SmallVector<FuncOp, 8> worklist;
+    reachable.insert(main);
+    worklist.push_back(main);
+    while (!worklist.empty()) {
+      FuncOp function = worklist.pop_back_val();
+      function.walk([&](GenericCallOp call) {
+        auto callee = symbolTable.lookup<FuncOp>(call.callee());
+        if (callee && reachable.insert(callee).second)
+          worklist.push_back(callee);
+      });
     }
+    return reachable;
   }
 
-  /// A utility method that returns if the given operation has all of its
-  /// operands inferred.
-  static bool allOperandsInferred(Operation *op) {
-    return llvm::all_of(op->getOperandTypes(), [](Type operandType) {
-      return operandType.isa<RankedTensorType>();
-    });
+  /// Infers the shapes of the operations of `function`, then the types of its
+  /// results from the values it returns.
+  LogicalResult inferFunction(FuncOp function, SymbolTable &symbolTable) {
+    auto inferCall = [&](GenericCallOp call) {
+      return specialize(call, symbolTable);
+    };
+    if (failed(inferShapes(function, inferCall)))
+      return failure();
+
+    auto returnOp =
+        cast<toy::ReturnOp>(function.getBody().back().getTerminator());
+    function.setType(FunctionType::get(function.getContext(),
+                                       function.getType().getInputs(),
+                                       returnOp.getOperandTypes()));
+    return success();
   }
 
-  /// A utility method that returns if the given operation has a dynamically
-  /// shaped result.
-  static bool returnsDynamicShape(Operation *op) {
-    return llvm::any_of(op->getResultTypes(), [](Type resultType) {
-      return !resultType.isa<RankedTensorType>();
+  /// Redirects `call` to the specialization of its callee for the types of
+  /// its arguments, creating and inferring it if it doesn't exist yet.
+  LogicalResult specialize(GenericCallOp call, SymbolTable &symbolTable) {
+    auto callee = symbolTable.lookup<FuncOp>(call.callee());
+    if (!callee)
+      return call.emitError("unable to find callee ") << call.callee();
+    SmallVector<Type, 4> argTypes(call.getOperandTypes());
+
+    auto it = llvm::find_if(specializations, [&](const Specialization &s) {
+      return s.callee == callee && ArrayRef<Type>(s.argTypes) == argTypes;
     });
+    FuncOp function;
+    if (it != specializations.end()) {
+      // NB: a recursive call is to a function still being inferred, so its
+      // results are only typed once the function is, see below. They can't be
+      // used meanwhile, e.g. they can only be yielded by a branch.
+      function = it->function;
+    } else {
+      function = callee.clone();
+      function.setType(FunctionType::get(callee.getContext(), argTypes,
+                                         callee.getType().getResults()));
+      for (auto arg : llvm::zip(function.getArguments(), argTypes))
+        std::get<0>(arg).setType(std::get<1>(arg));
+      function.setPrivate();
+      // NB: the name is made unique by the symbol table, e.g. `f_0`
+      symbolTable.insert(function);
+      specializations.push_back({callee, argTypes, function});
+      if (failed(inferFunction(function, symbolTable)))
+        return failure();
+
+      // Type the results of the recursive calls to the specialization.
+      ArrayRef<Type> resultTypes = function.getType().getResults();
+      symbolTable.getOp()->walk([&](GenericCallOp recursiveCall) {
+        if (recursiveCall.callee() != function.getName())
+          return;
+        for (auto result : llvm::zip(recursiveCall.getResults(), resultTypes))
+          std::get<0>(result).setType(std::get<1>(result));
+      });
+    }
+
+    call->setAttr("callee", SymbolRefAttr::get(call.getContext(),
+                                               function.getName()));
+    for (auto result :
+         llvm::zip(call.getResults(), function.getType().getResults()))
+      std::get<0>(result).setType(std::get<1>(result));
+    return success();
   }
 };
 } // namespace
@@ -111,3 +245,8 @@ public:
 std::unique_ptr<mlir::Pass> mlir::toy::createShapeInferencePass() {
   return std::make_unique<ShapeInferencePass>();
 }
+
+/// Create a Specialization pass.
+std::unique_ptr<mlir::Pass> mlir::toy::createSpecializationPass() {
+  return std::make_unique<SpecializationPass>();
+}
@@ -4,9 +4,12 @@ use rsml::execution_engine::ExecutionEngine;
 use rsml::operation::ModuleOp;
 use rsml::pass_manager::PassManager;
 use rsml::toy;
+use rsml::toy::diagnostic::Diagnostic;
+use rsml::toy::loader::Loader;
 use rsml::toy::mlir_gen::MLIRGen;
 use rsml::toy::toy_dialect::ToyDialect;
 use std::collections::HashMap;
+use std::process;
 use std::rc::Rc;
 
 /// a compiler for a language called Toy
@@ -22,6 +25,12 @@ struct Args {
     /// Enable optimizations
     #[clap(short, long, value_parser, default_value_t = false)]
     opt: bool,
+    /// Print source locations of operations when emitting MLIR
+    #[clap(short, long, value_parser, default_value_t = false)]
+    debug_info: bool,
+    /// Keep function calls instead of inlining them
+    #[clap(short, long, value_parser, default_value_t = false)]
+    no_inline: bool,
 }
 
 #[derive(PartialOrd, Ord, PartialEq, Eq)]
@@ -34,6 +43,11 @@ enum Mode {
     Jit(u32),
 }
 
+/// Renders `diagnostic` along with the source of the loaded file it points to.
+fn render(loader: &Loader, diagnostic: &Diagnostic) -> String {
+    diagnostic.render(loader.source(&diagnostic.location.filename).unwrap_or(""))
+}
+
 fn main() {
     let args = Args::parse();
 
@@ -41,7 +55,13 @@ fn main() {
         panic!("Only .toy and .mlir supported!");
     }
 
-    let content = std::fs::read_to_string(args.filename.clone()).unwrap();
+    let content = match std::fs::read_to_string(args.filename.clone()) {
+        Ok(content) => content,
+        Err(error) => {
+            eprintln!("error: cannot read {}: {}", args.filename, error);
+            process::exit(1);
+        }
+    };
     let mut prec = HashMap::with_capacity(6);
 
     prec.insert('=', 2);
@@ -61,20 +81,50 @@ fn main() {
     };
 
     let ast_module = if args.filename.contains(".toy") {
-        Some(
-            toy::parser::Parser::new(content.clone(), &mut prec)
-                .parse_module()
-                .unwrap(),
-        )
+        let mut loader = Loader::new(&mut prec);
+        let mut module = match loader.load(&args.filename) {
+            Ok(module) => module,
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    eprintln!("{}", render(&loader, diagnostic));
+                }
+                let errors = toy::sema::error_count(&diagnostics);
+                eprintln!("error: aborting due to {} previous error(s)", errors);
+                process::exit(1);
+            }
+        };
+        // NB: the AST is dumped as parsed, even if it doesn't make sense
+        if Some(Mode::Ast(0)) == mode {
+            // Only the definitions of the file itself, the imported ones are dumped as imports.
+            module
+                .structs
+                .retain(|s| s.location.filename == args.filename);
+            module
+                .functions
+                .retain(|f| f.location.filename == args.filename);
+        } else {
+            let mut diagnostics = toy::sema::check(&module, &args.filename);
+            // NB: shapes can only be inferred for programs that make sense
+            if toy::sema::error_count(&diagnostics) == 0 {
+                diagnostics = toy::shapes::infer(&module).diagnostics;
+            }
+            for diagnostic in &diagnostics {
+                eprintln!("{}", render(&loader, diagnostic));
+            }
+            let errors = toy::sema::error_count(&diagnostics);
+            if errors > 0 {
+                eprintln!("error: aborting due to {} previous error(s)", errors);
+                process::exit(1);
+            }
+        }
+        Some(module)
     } else {
         None
     };
 
     if let Some(mode) = mode {
         if Mode::Ast(0) == mode {
-            for fun in ast_module.unwrap().functions {
-                println!("-> Function parsed: \n{:#?}\n", fun);
-            }
+            print!("{}", toy::ast_dump::dump(&ast_module.unwrap()));
         } else if Mode::Mlir(1) <= mode {
             let context = Rc::new(Context::default());
             let dialect = ToyDialect::new(&context);
@@ -85,15 +135,23 @@ fn main() {
                 ModuleOp::new_parsed(&context, &content)
             };
             let pass_manager = PassManager::new(Rc::clone(&context));
-            let pass = PassManager::create_inliner_pass();
-            pass_manager.add_owned_pass(pass);
-            if args.opt {
+            if !args.no_inline {
+                let pass = PassManager::create_inliner_pass();
+                pass_manager.add_owned_pass(pass);
+            }
+            // NB: lowering needs shapes to be inferred and struct accesses to be folded
+            if args.opt || Mode::MlirAffine(2) <= mode {
                 let pass = PassManager::create_canonicalizer_pass();
                 pass_manager.add_nested_pass(pass, "builtin.func");
 
                 let pass = PassManager::create_cse_pass();
                 pass_manager.add_nested_pass(pass, "builtin.func");
 
+                // Calls left by the inliner get clones of their callees for
+                // the shapes of their arguments
+                let pass = PassManager::create_specialization_pass();
+                pass_manager.add_owned_pass(pass);
+
                 let pass = PassManager::create_shape_inference_pass();
                 pass_manager.add_nested_pass(pass, "builtin.func");
             }
@@ -111,11 +169,19 @@ fn main() {
             if Mode::Llvm(4) == mode {
                 panic!("Sorry, this option is not implemented yet.")
             }
-            pass_manager.run(&module);
+            if let Err(error) = pass_manager.run(&module) {
+                eprintln!("error: {}", error);
+                process::exit(1);
+            }
 
             if Mode::Jit(5) <= mode {
                 let engine = ExecutionEngine::new(&context, &module);
-                engine.run("main");
+                if let Err(error) = engine.run("main") {
+                    eprintln!("error: {}", error);
+                    process::exit(1);
+                }
+            } else if args.debug_info {
+                module.dump_with_debug_info();
             } else {
                 module.dump();
             }
@@ -0,0 +1,82 @@
+use clap::Parser;
+use rsml::toy;
+use rsml::toy::loader::Loader;
+use std::collections::HashMap;
+use std::process;
+
+/// a formatter for the Toy language, printing the formatted files by default
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// paths to the files to format
+    #[clap(value_parser, required = true)]
+    files: Vec<String>,
+    /// Rewrite the files in place
+    #[clap(short, long, value_parser, default_value_t = false)]
+    write: bool,
+    /// Only list the files that aren't formatted, failing if there is any
+    #[clap(short, long, value_parser, default_value_t = false)]
+    check: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut failed = false;
+    for filename in &args.files {
+        let content = match std::fs::read_to_string(filename) {
+            Ok(content) => content,
+            Err(error) => {
+                eprintln!("error: cannot read {}: {}", filename, error);
+                failed = true;
+                continue;
+            }
+        };
+        let mut prec = HashMap::with_capacity(6);
+
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+        // NB: the imports are loaded for the operators they define, but aren't formatted
+        let mut loader = Loader::new(&mut prec);
+        let mut module = match loader.load(filename) {
+            Ok(module) => module,
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    let source = loader.source(&diagnostic.location.filename);
+                    eprintln!("{}", diagnostic.render(source.unwrap_or("")));
+                }
+                failed = true;
+                continue;
+            }
+        };
+        module.structs.retain(|s| &s.location.filename == filename);
+        module
+            .functions
+            .retain(|f| &f.location.filename == filename);
+        let formatted = toy::format::format(&module, &prec);
+
+        if args.check {
+            if formatted != content {
+                println!("{}", filename);
+                failed = true;
+            }
+        } else if args.write {
+            if formatted != content {
+                if let Err(error) = std::fs::write(filename, formatted) {
+                    eprintln!("error: cannot write {}: {}", filename, error);
+                    failed = true;
+                }
+            }
+        } else {
+            print!("{}", formatted);
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
@@ -1,5 +1,10 @@
+use crate::location::Location;
+use crate::misc::{Type, Value};
 use crate::operation::Operation;
-use mlir_sys::{mlirBlockGetFirstOperation, mlirOperationGetNextInBlock, MlirBlock, MlirRegion};
+use mlir_sys::{
+    mlirBlockCreate, mlirBlockGetArgument, mlirBlockGetFirstOperation, mlirOperationGetNextInBlock,
+    mlirRegionAppendOwnedBlock, mlirRegionCreate, MlirBlock, MlirLocation, MlirRegion, MlirType,
+};
 
 #[derive(Clone)]
 pub struct Block {
@@ -8,6 +13,22 @@ pub struct Block {
 }
 
 impl Block {
+    /// Creates a detached block with one argument of each of `arg_types`.
+    pub fn new(arg_types: Vec<Type>, location: &Location) -> Self {
+        let args: Vec<MlirType> = arg_types.into_iter().map(|x| x.instance).collect();
+        let locs: Vec<MlirLocation> = vec![location.instance; args.len()];
+        let instance =
+            unsafe { mlirBlockCreate(args.len() as isize, args.as_ptr(), locs.as_ptr()) };
+
+        Self::from(instance)
+    }
+
+    pub fn argument(&self, pos: usize) -> Value {
+        let instance = unsafe { mlirBlockGetArgument(self.instance, pos as isize) };
+
+        Value::new(instance)
+    }
+
     // TODO: better way is to implement iterator
     pub fn back(&self) -> Operation {
         unsafe {
@@ -37,6 +58,25 @@ pub struct Region {
     pub instance: MlirRegion,
 }
 
+impl Region {
+    pub fn new() -> Self {
+        let instance = unsafe { mlirRegionCreate() };
+
+        Self { instance }
+    }
+
+    /// Transfers the ownership of `block` to this region.
+    pub fn append_owned_block(&self, block: &Block) {
+        unsafe { mlirRegionAppendOwnedBlock(self.instance, block.instance) };
+    }
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl From<MlirRegion> for Region {
     fn from(mlir_region: MlirRegion) -> Self {
         Self {
@@ -31,16 +31,22 @@ impl ExecutionEngine {
         }
     }
 
-    pub fn run(&self, func_name: &str) {
+    /// Runs the function `func_name`, which takes no arguments, failing if it can't be
+    /// invoked.
+    pub fn run(&self, func_name: &str) -> Result<(), &'static str> {
         let name = CString::new(func_name).unwrap();
-        unsafe {
+        let result = unsafe {
             let mut args: *mut c_void = std::ptr::null_mut();
             let args_ptr: *mut *mut c_void = &mut args;
-            let _ = mlirExecutionEngineInvokePacked(
+            mlirExecutionEngineInvokePacked(
                 self.instance,
                 mlirStringRefCreateFromCString(name.as_ptr()),
                 args_ptr,
-            );
+            )
+        };
+        if result.value == 0 {
+            return Err("failed to invoke the function");
         }
+        Ok(())
     }
 }
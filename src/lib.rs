@@ -12,11 +12,14 @@ pub mod toy;
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::os::unix::io::AsRawFd;
     use std::rc::Rc;
+    use std::sync::{Mutex, PoisonError};
 
     use crate::context::Context;
     use crate::dialect::StandardDialect;
     use crate::execution_engine::ExecutionEngine;
+    use crate::location::Location;
     use crate::operation::ModuleOp;
     use crate::pass_manager::PassManager;
     use crate::toy::mlir_gen::MLIRGen;
@@ -30,6 +33,29 @@ mod tests {
         let _context = Context::default();
     }
 
+    #[test]
+    fn create_locations() {
+        let context = Rc::new(Context::default());
+        let callee = Location::new_file_line_col(Rc::clone(&context), "test.toy", 2, 3);
+        let caller = Location::new_file_line_col(Rc::clone(&context), "test.toy", 8, 11);
+        let call_site = Location::new_call_site(&callee, &caller);
+        let name = Location::new_name("multiply_transpose", &callee);
+        assert_eq!(callee.to_string(), r#"loc("test.toy":2:3)"#);
+        assert_eq!(
+            call_site.to_string(),
+            r#"loc(callsite("test.toy":2:3 at "test.toy":8:11))"#
+        );
+        assert_eq!(
+            name.to_string(),
+            r#"loc("multiply_transpose"("test.toy":2:3))"#
+        );
+        let fused = Location::new_fused(context, &[callee, caller]);
+        assert_eq!(
+            fused.to_string(),
+            r#"loc(fused["test.toy":2:3, "test.toy":8:11])"#
+        );
+    }
+
     #[test]
     fn create_dialect() {
         let context = Context::default();
@@ -53,12 +79,29 @@ mod tests {
     #[test_case("transpose_transpose_opt", true; "when optimizing transpose")]
     #[test_case("ast_tensor", true; "when inlining")]
     #[test_case("print_transposed", true; "complex")]
+    #[test_case("struct", false; "when generate MLIR with structs")]
+    #[test_case("struct", true; "when folding struct accesses")]
+    #[test_case("arith", true; "when generate MLIR with sub, div and lt")]
+    #[test_case("control_flow", false; "when generate MLIR with if and for")]
+    #[test_case("control_flow", true; "when inferring shapes of carried values")]
+    #[test_case("custom_ops", true; "when inlining custom operators")]
+    #[test_case("signatures", true; "when inlining functions with ranked signatures")]
+    #[test_case("uninitialized", false; "when generate MLIR for uninitialized variables")]
+    #[test_case("broadcast", true; "when inferring shapes of broadcasts")]
+    #[test_case("indexing", true; "when inferring shapes of subscripts")]
+    #[test_case("constructors", false; "when generate MLIR with constructors")]
+    #[test_case("reductions", true; "when inferring shapes of reductions")]
+    #[test_case("math", true; "when inferring shapes of math functions")]
+    #[test_case("element_types", true; "when inferring shapes of typed tensors")]
+    #[test_case("multiple_results", false; "when generate MLIR with multiple results")]
+    #[test_case("multiple_results", true; "when inlining calls with multiple results")]
+    #[test_case("recursion", true; "when specializing recursive functions")]
     fn generate_mlir(filename: &str, is_opt: bool) {
         let filename = format!("testdata/{}.toy", filename);
         if filename.is_empty() {
             panic!("Cannot find file to read");
         }
-        let content = std::fs::read_to_string(filename).unwrap();
+        let content = std::fs::read_to_string(&filename).unwrap();
         let mut prec = HashMap::with_capacity(6);
 
         prec.insert('=', 2);
@@ -71,7 +114,7 @@ mod tests {
         let context = Rc::new(Context::default());
         let dialect = ToyDialect::new(&context);
         context.load_dialect(Box::new(dialect));
-        let module = parser::Parser::new(content, &mut prec)
+        let module = parser::Parser::new(content, &filename, &mut prec)
             .parse_module()
             .unwrap();
         let module = MLIRGen::new(Rc::clone(&context)).mlir_gen(module);
@@ -84,16 +127,67 @@ mod tests {
             let pass = PassManager::create_canonicalizer_pass();
             pass_manager.add_nested_pass(pass, "builtin.func");
 
+            let pass = PassManager::create_specialization_pass();
+            pass_manager.add_owned_pass(pass);
+
             let pass = PassManager::create_shape_inference_pass();
             pass_manager.add_nested_pass(pass, "builtin.func");
 
-            pass_manager.run(&module);
+            pass_manager.run(&module).unwrap();
         }
         println!("");
         module.dump();
         assert!(!module.block.operations.is_empty());
     }
 
+    #[test]
+    fn generate_mlir_with_locations() {
+        let filename = "testdata/ast_tensor.toy";
+        let content = std::fs::read_to_string(filename).unwrap();
+        let mut prec = HashMap::with_capacity(6);
+
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+
+        let context = Rc::new(Context::default());
+        let dialect = ToyDialect::new(&context);
+        context.load_dialect(Box::new(dialect));
+        let module = parser::Parser::new(content, filename, &mut prec)
+            .parse_module()
+            .unwrap();
+        let module = MLIRGen::new(Rc::clone(&context)).mlir_gen(module);
+
+        // `print(d);` is on line 11
+        let output = module.print_with_debug_info();
+        assert!(output.contains(r#"loc("testdata/ast_tensor.toy":11:3)"#));
+    }
+
+    #[test]
+    #[should_panic(expected = "Return is not allowed inside if and for")]
+    fn generate_mlir_fails_on_errors_in_regions() {
+        let content = "def main() {\n  var a = [1];\n  if (a) {\n    return;\n  }\n}";
+        let mut prec = HashMap::with_capacity(6);
+
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+
+        let context = Rc::new(Context::default());
+        let dialect = ToyDialect::new(&context);
+        context.load_dialect(Box::new(dialect));
+        let module = parser::Parser::new(content.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap();
+        MLIRGen::new(Rc::clone(&context)).mlir_gen(module);
+    }
+
     #[test]
     fn optimize_mlir() {
         let filename = "test_inliner";
@@ -133,7 +227,7 @@ mod tests {
         let pass = PassManager::create_shape_inference_pass();
         pass_manager.add_nested_pass(pass, "builtin.func");
 
-        pass_manager.run(&module);
+        pass_manager.run(&module).unwrap();
         println!("after");
         module.dump();
     }
@@ -182,7 +276,7 @@ mod tests {
         // TODO: in original mlir tutorial, they add LoopFusion and MemRefDataFlowOpt
         // but those are not available currently in mlir-c api
 
-        pass_manager.run(&module);
+        pass_manager.run(&module).unwrap();
         println!("after");
         module.dump();
     }
@@ -233,7 +327,7 @@ mod tests {
         let pass = PassManager::create_lower_to_llvm_pass();
         pass_manager.add_owned_pass(pass);
 
-        pass_manager.run(&module);
+        pass_manager.run(&module).unwrap();
         println!("after");
         module.dump();
     }
@@ -280,9 +374,103 @@ mod tests {
         let pass = PassManager::create_lower_to_llvm_pass();
         pass_manager.add_owned_pass(pass);
 
-        pass_manager.run(&module);
+        pass_manager.run(&module).unwrap();
 
         let engine = ExecutionEngine::new(&context, &module);
-        engine.run("main");
+        engine.run("main").unwrap();
+    }
+
+    #[test_case("arith", true; "when running sub, div and lt")]
+    #[test_case("struct", true; "when running structs")]
+    #[test_case("control_flow", true; "when running if and for")]
+    #[test_case("custom_ops", true; "when running custom operators")]
+    #[test_case("uninitialized", true; "when running uninitialized variables")]
+    #[test_case("broadcast", true; "when running broadcasts")]
+    #[test_case("indexing", true; "when running subscripts")]
+    #[test_case("constructors", true; "when running constructors")]
+    #[test_case("reductions", true; "when running matmul and reductions")]
+    #[test_case("math", true; "when running math functions")]
+    #[test_case("element_types", true; "when running typed tensors")]
+    #[test_case("multiple_results", true; "when running calls with multiple results")]
+    #[test_case("recursion", true; "when running recursive functions")]
+    #[test_case("calls", false; "when running calls without inlining")]
+    #[test_case("call_chain", false; "when running chained calls without inlining")]
+    #[test_case("multiple_results", false; "when running calls with multiple results without inlining")]
+    #[test_case("recursion", false; "when running recursive functions without inlining")]
+    fn execute_toy(name: &str, is_inlined: bool) {
+        let filename = format!("testdata/{}.toy", name);
+        let content = std::fs::read_to_string(&filename).unwrap();
+        let mut prec = HashMap::with_capacity(6);
+
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+
+        let context = Rc::new(Context::default());
+        let dialect = ToyDialect::new(&context);
+        context.load_dialect(Box::new(dialect));
+        let module = parser::Parser::new(content, &filename, &mut prec)
+            .parse_module()
+            .unwrap();
+        let module = MLIRGen::new(Rc::clone(&context)).mlir_gen(module);
+
+        let pass_manager = PassManager::new(Rc::clone(&context));
+        if is_inlined {
+            let pass = PassManager::create_inliner_pass();
+            pass_manager.add_owned_pass(pass);
+        }
+
+        let pass = PassManager::create_canonicalizer_pass();
+        pass_manager.add_nested_pass(pass, "builtin.func");
+
+        let pass = PassManager::create_cse_pass();
+        pass_manager.add_nested_pass(pass, "builtin.func");
+
+        let pass = PassManager::create_specialization_pass();
+        pass_manager.add_owned_pass(pass);
+
+        let pass = PassManager::create_shape_inference_pass();
+        pass_manager.add_nested_pass(pass, "builtin.func");
+
+        let pass = PassManager::create_lower_to_affine_pass();
+        pass_manager.add_nested_pass(pass, "builtin.func");
+
+        let pass = PassManager::create_lower_to_llvm_pass();
+        pass_manager.add_owned_pass(pass);
+
+        pass_manager.run(&module).unwrap();
+
+        let engine = ExecutionEngine::new(&context, &module);
+        let (result, output) = capture_stdout(|| engine.run("main"));
+        result.unwrap();
+        let expected = std::fs::read_to_string(format!("testdata/{}.out", name)).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    /// Runs `f`, returning its result along with what it prints to the standard output, like
+    /// the code run by the execution engine does with `printf`.
+    fn capture_stdout<T>(f: impl FnOnce() -> T) -> (T, String) {
+        // NB: the tests running in parallel share the standard output
+        static STDOUT: Mutex<()> = Mutex::new(());
+        let _guard = STDOUT.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let path = std::env::temp_dir().join(format!("rsml-{}.out", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        let result = unsafe {
+            libc::fflush(std::ptr::null_mut());
+            let stdout = libc::dup(libc::STDOUT_FILENO);
+            libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO);
+            let result = f();
+            libc::fflush(std::ptr::null_mut());
+            libc::dup2(stdout, libc::STDOUT_FILENO);
+            libc::close(stdout);
+            result
+        };
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (result, output)
     }
 }
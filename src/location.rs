@@ -1,7 +1,15 @@
+use std::ffi::CString;
+use std::fmt;
+use std::os::raw::c_void;
 use std::rc::Rc;
 
 use crate::context::Context;
-use mlir_sys::{mlirLocationUnknownGet, MlirLocation};
+use crate::misc::print_to_string;
+use mlir_sys::{
+    mlirAttributeGetNull, mlirLocationCallSiteGet, mlirLocationFileLineColGet,
+    mlirLocationFusedGet, mlirLocationNameGet, mlirLocationPrint, mlirLocationUnknownGet,
+    mlirStringRefCreateFromCString, MlirLocation,
+};
 
 #[derive(Clone)]
 pub struct Location {
@@ -15,7 +23,78 @@ impl Location {
         Self { instance, context }
     }
 
+    pub fn new_file_line_col(
+        context: Rc<Context>,
+        filename: &str,
+        line: usize,
+        col: usize,
+    ) -> Self {
+        let filename = CString::new(filename).unwrap();
+        let instance = unsafe {
+            mlirLocationFileLineColGet(
+                context.instance,
+                mlirStringRefCreateFromCString(filename.as_ptr()),
+                line as u32,
+                col as u32,
+            )
+        };
+        Self { instance, context }
+    }
+
+    /// Creates a location that attaches `name` to the `child` location.
+    pub fn new_name(name: &str, child: &Location) -> Self {
+        let name = CString::new(name).unwrap();
+        let instance = unsafe {
+            mlirLocationNameGet(
+                child.context.instance,
+                mlirStringRefCreateFromCString(name.as_ptr()),
+                child.instance,
+            )
+        };
+        Self {
+            instance,
+            context: Rc::clone(&child.context),
+        }
+    }
+
+    /// Creates a location describing `callee` being reached from the `caller` location.
+    pub fn new_call_site(callee: &Location, caller: &Location) -> Self {
+        let instance = unsafe { mlirLocationCallSiteGet(callee.instance, caller.instance) };
+        Self {
+            instance,
+            context: Rc::clone(&callee.context),
+        }
+    }
+
+    /// Creates a single location standing for all of the given `locations`.
+    pub fn new_fused(context: Rc<Context>, locations: &[Location]) -> Self {
+        let locations: Vec<MlirLocation> = locations.iter().map(|x| x.instance).collect();
+        let instance = unsafe {
+            mlirLocationFusedGet(
+                context.instance,
+                locations.len() as isize,
+                locations.as_ptr(),
+                mlirAttributeGetNull(),
+            )
+        };
+        Self { instance, context }
+    }
+
     pub fn context(&self) -> &Context {
         &self.context
     }
 }
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut output = String::new();
+        unsafe {
+            mlirLocationPrint(
+                self.instance,
+                Some(print_to_string),
+                &mut output as *mut String as *mut c_void,
+            )
+        };
+        f.write_str(&output)
+    }
+}
@@ -1,10 +1,13 @@
 use std::ffi::CString;
+use std::os::raw::c_void;
 
 use crate::context::Context;
 use mlir_sys::{
-    mlirAttributeGetContext, mlirAttributeParseGet, mlirFlatSymbolRefAttrGet, mlirIdentifierGet,
-    mlirNamedAttributeGet, mlirStringRefCreateFromCString, mlirUnitAttrGet, MlirAttribute,
-    MlirNamedAttribute, MlirType, MlirValue,
+    mlirArrayAttrGet, mlirAttributeGetContext, mlirAttributeParseGet, mlirFlatSymbolRefAttrGet,
+    mlirIdentifierGet, mlirIntegerAttrGet, mlirIntegerTypeGet, mlirNamedAttributeGet,
+    mlirShapedTypeGetElementType, mlirStringRefCreateFromCString, mlirTypeAttrGet, mlirTypeIsAF32,
+    mlirTypeIsAInteger, mlirUnitAttrGet, mlirValueGetType, MlirAttribute, MlirNamedAttribute,
+    MlirStringRef, MlirType, MlirValue,
 };
 
 #[derive(Clone)]
@@ -18,6 +21,21 @@ impl From<MlirType> for Type {
     }
 }
 
+impl Type {
+    /// Returns the type of the elements of a shaped type, like a tensor.
+    pub fn element_type(&self) -> Type {
+        unsafe { Type::from(mlirShapedTypeGetElementType(self.instance)) }
+    }
+
+    pub fn is_f32(&self) -> bool {
+        unsafe { mlirTypeIsAF32(self.instance) }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        unsafe { mlirTypeIsAInteger(self.instance) }
+    }
+}
+
 #[derive(Clone)]
 pub struct Attribute {
     pub(crate) instance: MlirAttribute,
@@ -38,6 +56,19 @@ impl Value {
     pub(crate) fn new(instance: MlirValue) -> Value {
         Self { instance }
     }
+
+    /// Returns a value standing for none, e.g. the one of a block of statements.
+    pub(crate) fn null() -> Value {
+        Self {
+            instance: MlirValue {
+                ptr: std::ptr::null(),
+            },
+        }
+    }
+
+    pub fn get_type(&self) -> Type {
+        unsafe { Type::from(mlirValueGetType(self.instance)) }
+    }
 }
 
 #[derive(Clone)]
@@ -82,6 +113,29 @@ impl Attribute {
         Self { instance }
     }
 
+    pub fn new_array(context: &Context, elements: Vec<Attribute>) -> Attribute {
+        let elements: Vec<MlirAttribute> = elements.into_iter().map(|x| x.instance).collect();
+        let instance = unsafe {
+            mlirArrayAttrGet(context.instance, elements.len() as isize, elements.as_ptr())
+        };
+
+        Self { instance }
+    }
+
+    pub fn new_i64(context: &Context, value: i64) -> Attribute {
+        let instance = unsafe {
+            let i64_type = mlirIntegerTypeGet(context.instance, 64);
+            mlirIntegerAttrGet(i64_type, value)
+        };
+
+        Self { instance }
+    }
+
+    pub fn new_type(ty: &Type) -> Attribute {
+        let instance = unsafe { mlirTypeAttrGet(ty.instance) };
+        Self { instance }
+    }
+
     pub fn new_unit(context: &Context) -> Attribute {
         let instance = unsafe { mlirUnitAttrGet(context.instance) };
         Self { instance }
@@ -99,3 +153,11 @@ impl Attribute {
         Self { instance }
     }
 }
+
+/// Appends the printed `string` to the `String` pointed to by `user_data`, as a callback of
+/// the MLIR printing functions.
+pub(crate) unsafe extern "C" fn print_to_string(string: MlirStringRef, user_data: *mut c_void) {
+    let bytes = std::slice::from_raw_parts(string.data as *const u8, string.length);
+    let output = &mut *(user_data as *mut String);
+    output.push_str(&String::from_utf8_lossy(bytes));
+}
@@ -1,7 +1,10 @@
 use mlir_sys::{
     mlirAttributeGetNull, mlirBlockInsertOwnedOperation, mlirDenseElementsAttrDoubleGet,
-    mlirF64TypeGet, mlirFloatAttrDoubleGet, mlirFunctionTypeGet, mlirRankedTensorTypeGet,
-    mlirUnrankedTensorTypeGet, MlirType,
+    mlirDenseElementsAttrDoubleSplatGet, mlirDenseElementsAttrFloatGet,
+    mlirDenseElementsAttrFloatSplatGet, mlirDenseElementsAttrInt64Get,
+    mlirDenseElementsAttrInt64SplatGet, mlirF32TypeGet, mlirF64TypeGet, mlirFloatAttrDoubleGet,
+    mlirFunctionTypeGet, mlirIntegerTypeGet, mlirRankedTensorTypeGet, mlirUnrankedTensorTypeGet,
+    MlirType,
 };
 
 use crate::block::Block;
@@ -22,6 +25,11 @@ impl<'ctx> OpBuilder {
         self.pos = pos;
     }
 
+    /// Returns the block and position operations are currently inserted at.
+    pub fn insertion_point(&self) -> (Rc<Block>, isize) {
+        (Rc::clone(self.block.as_ref().unwrap()), self.pos)
+    }
+
     pub fn insert(&mut self, operation: Operation) {
         unsafe {
             let block: &Block = self.block.as_ref().unwrap();
@@ -34,6 +42,14 @@ impl<'ctx> OpBuilder {
         unsafe { Type::from(mlirF64TypeGet(self.context.instance)) }
     }
 
+    pub fn get_f32_type(&self) -> Type {
+        unsafe { Type::from(mlirF32TypeGet(self.context.instance)) }
+    }
+
+    pub fn get_i64_type(&self) -> Type {
+        unsafe { Type::from(mlirIntegerTypeGet(self.context.instance, 64)) }
+    }
+
     // TODO: redundant copies of dims
     pub fn get_ranked_tensor_type(&self, dims: Vec<usize>, elem_ty: Type) -> Type {
         let rank: isize = dims.len() as isize;
@@ -50,13 +66,52 @@ impl<'ctx> OpBuilder {
         }
     }
 
+    /// Returns an attribute of type `data_ty` holding `data`, converted to the element type
+    /// of `data_ty`: f64, f32 or a 64-bit integer.
     pub fn get_dense_elements_attr(&self, data_ty: Type, data: Vec<f64>) -> Attribute {
+        let elem_ty = data_ty.element_type();
         unsafe {
-            Attribute::from(mlirDenseElementsAttrDoubleGet(
-                data_ty.instance,
-                data.len() as isize,
-                data.as_ptr(),
-            ))
+            if elem_ty.is_integer() {
+                let data: Vec<i64> = data.into_iter().map(|x| x as i64).collect();
+                Attribute::from(mlirDenseElementsAttrInt64Get(
+                    data_ty.instance,
+                    data.len() as isize,
+                    data.as_ptr(),
+                ))
+            } else if elem_ty.is_f32() {
+                let data: Vec<f32> = data.into_iter().map(|x| x as f32).collect();
+                Attribute::from(mlirDenseElementsAttrFloatGet(
+                    data_ty.instance,
+                    data.len() as isize,
+                    data.as_ptr(),
+                ))
+            } else {
+                Attribute::from(mlirDenseElementsAttrDoubleGet(
+                    data_ty.instance,
+                    data.len() as isize,
+                    data.as_ptr(),
+                ))
+            }
+        }
+    }
+
+    /// Returns an attribute of type `data_ty` whose elements are all `value`.
+    pub fn get_dense_elements_attr_splat(&self, data_ty: Type, value: f64) -> Attribute {
+        let elem_ty = data_ty.element_type();
+        unsafe {
+            if elem_ty.is_integer() {
+                Attribute::from(mlirDenseElementsAttrInt64SplatGet(
+                    data_ty.instance,
+                    value as i64,
+                ))
+            } else if elem_ty.is_f32() {
+                Attribute::from(mlirDenseElementsAttrFloatSplatGet(
+                    data_ty.instance,
+                    value as f32,
+                ))
+            } else {
+                Attribute::from(mlirDenseElementsAttrDoubleSplatGet(data_ty.instance, value))
+            }
         }
     }
 
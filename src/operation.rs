@@ -1,25 +1,25 @@
 use crate::block::{Block, Region};
 use crate::context::Context;
 use crate::location::Location;
-use crate::misc::{Attribute, NamedAttribute, Type, Value};
+use crate::misc::{print_to_string, Attribute, NamedAttribute, Type, Value};
 
 use mlir_sys::{
-    mlirAttributeParseGet, mlirBlockCreate, mlirBlockInsertOwnedOperation,
-    mlirFunctionTypeGetInput, mlirFunctionTypeGetNumInputs, mlirFunctionTypeGetNumResults,
-    mlirFunctionTypeGetResult, mlirIdentifierStr, mlirLocationGetContext, mlirModuleCreateEmpty,
-    mlirModuleCreateParse, mlirModuleGetBody, mlirModuleGetOperation, mlirOperationCreate,
+    mlirBlockCreate, mlirBlockInsertOwnedOperation, mlirFunctionTypeGetInput,
+    mlirFunctionTypeGetNumInputs, mlirIdentifierStr, mlirModuleCreateEmpty, mlirModuleCreateParse,
+    mlirModuleGetBody, mlirModuleGetOperation, mlirOpPrintingFlagsCreate,
+    mlirOpPrintingFlagsDestroy, mlirOpPrintingFlagsEnableDebugInfo, mlirOperationCreate,
     mlirOperationDump, mlirOperationGetAttributeByName, mlirOperationGetContext,
-    mlirOperationGetName, mlirOperationGetNumOperands, mlirOperationGetResult,
+    mlirOperationGetName, mlirOperationGetNumOperands, mlirOperationGetNumResults,
+    mlirOperationGetOperand, mlirOperationGetResult, mlirOperationPrintWithFlags,
     mlirOperationSetAttributeByName, mlirOperationStateAddAttributes,
     mlirOperationStateAddOperands, mlirOperationStateAddOwnedRegions, mlirOperationStateAddResults,
-    mlirOperationStateGet, mlirRegionAppendOwnedBlock, mlirRegionCreate, mlirShapedTypeGetDimSize,
-    mlirShapedTypeGetRank, mlirStringAttrGet, mlirStringRefCreateFromCString,
-    mlirSymbolTableCreate, mlirSymbolTableGetVisibilityAttributeName, mlirTypeIsAFunction,
-    mlirTypeIsARankedTensor, mlirTypeIsATensor, mlirTypeIsAUnrankedTensor, mlirTypeParseGet,
-    MlirModule, MlirNamedAttribute, MlirOperation, MlirOperationState, MlirRegion, MlirType,
-    MlirValue,
+    mlirOperationStateGet, mlirRegionAppendOwnedBlock, mlirRegionCreate, mlirStringAttrGet,
+    mlirStringRefCreateFromCString, mlirSymbolTableCreate,
+    mlirSymbolTableGetVisibilityAttributeName, MlirLocation, MlirModule, MlirNamedAttribute,
+    MlirOperation, MlirOperationState, MlirRegion, MlirType, MlirValue,
 };
 use std::ffi::{CStr, CString};
+use std::os::raw::c_void;
 use std::rc::Rc;
 
 #[derive(Clone)]
@@ -64,7 +64,7 @@ impl OperationState {
         }
     }
 
-    fn add_owned_regions(&mut self, regions: Vec<Region>) {
+    pub fn add_owned_regions(&mut self, regions: Vec<Region>) {
         let regions: Vec<MlirRegion> = regions.into_iter().map(|x| x.instance).collect();
 
         let p_state: *mut MlirOperationState = &mut self.instance;
@@ -103,6 +103,25 @@ impl Operation {
 
         num as usize
     }
+
+    pub(crate) fn operand(&self, pos: usize) -> Value {
+        let instance = unsafe { mlirOperationGetOperand(self.instance, pos as isize) };
+
+        Value::new(instance)
+    }
+
+    pub fn num_results(&self) -> usize {
+        let num = unsafe { mlirOperationGetNumResults(self.instance) };
+
+        num as usize
+    }
+
+    /// Returns the result at `pos`, e.g. one of the values returned by a call.
+    pub fn result(&self, pos: usize) -> Value {
+        let instance = unsafe { mlirOperationGetResult(self.instance, pos as isize) };
+
+        Value::new(instance)
+    }
 }
 
 impl From<MlirOperation> for Operation {
@@ -161,6 +180,30 @@ impl ModuleOp {
         };
     }
 
+    /// Same as `dump`, but also prints the location attached to every operation.
+    pub fn dump_with_debug_info(&self) {
+        // NB: mirrors mlirOperationDump, which writes to stderr as well
+        eprintln!("{}", self.print_with_debug_info());
+    }
+
+    /// Returns the textual form of the module, with the location attached to every operation.
+    pub fn print_with_debug_info(&self) -> String {
+        let mut output = String::new();
+        unsafe {
+            let mlir_operation = mlirModuleGetOperation(self.instance);
+            let flags = mlirOpPrintingFlagsCreate();
+            mlirOpPrintingFlagsEnableDebugInfo(flags, false);
+            mlirOperationPrintWithFlags(
+                mlir_operation,
+                flags,
+                Some(print_to_string),
+                &mut output as *mut String as *mut c_void,
+            );
+            mlirOpPrintingFlagsDestroy(flags);
+        };
+        output
+    }
+
     pub fn new_parsed(context: &Context, content: &str) -> Self {
         unsafe {
             let content = CString::new(content).unwrap();
@@ -199,39 +242,24 @@ pub struct FuncOp {
 }
 
 impl FuncOp {
-    pub fn new(mut location: Location, name: &str, func_type: Type, exported: bool) -> Self {
+    pub fn new(location: Location, name: &str, func_type: Type, exported: bool) -> Self {
         unsafe {
-            let mlir_context = mlirLocationGetContext(location.instance);
             let mlir_region = mlirRegionCreate();
 
-            let arg_string_types: Vec<String> = Self::extract_arg_types(&func_type).unwrap();
-            let arg_cstring_types: Vec<CString> = arg_string_types
-                .into_iter()
-                .map(|x| CString::new(x).unwrap())
-                .collect();
-            let num_args = arg_cstring_types.len() as isize;
-
-            let args: Vec<MlirType> = arg_cstring_types
-                .into_iter()
-                .map(|x| mlirTypeParseGet(mlir_context, mlirStringRefCreateFromCString(x.as_ptr())))
+            let num_args = mlirFunctionTypeGetNumInputs(func_type.instance);
+            let args: Vec<MlirType> = (0..num_args)
+                .map(|pos| mlirFunctionTypeGetInput(func_type.instance, pos))
                 .collect();
+            let locs: Vec<MlirLocation> = vec![location.instance; args.len()];
 
-            let p_args: *const MlirType = args.as_ptr();
-            let p_locs = &mut location.instance;
-
-            let mlir_block = mlirBlockCreate(num_args, p_args, p_locs);
+            let mlir_block = mlirBlockCreate(num_args, args.as_ptr(), locs.as_ptr());
             mlirRegionAppendOwnedBlock(mlir_region, mlir_block);
 
-            let func_type_string: String = Self::serialize_func_type(&func_type).unwrap();
-
             let mut string = String::from("\"");
             string += name;
             string += "\"";
 
-            let type_attr = NamedAttribute::new(
-                "type",
-                Attribute::new_parsed(location.context(), &func_type_string),
-            );
+            let type_attr = NamedAttribute::new("type", Attribute::new_type(&func_type));
             let func_name_attr = NamedAttribute::new(
                 "sym_name",
                 Attribute::new_parsed(location.context(), &string),
@@ -270,96 +298,6 @@ impl FuncOp {
         }
     }
 
-    fn serialize_func_type(func_type: &Type) -> Result<String, &'static str> {
-        unsafe {
-            if !mlirTypeIsAFunction(func_type.instance) {
-                return Err("Provided type is not a function type");
-            }
-
-            let mut result = String::new();
-            result.push('(');
-            let num_inputs = mlirFunctionTypeGetNumInputs(func_type.instance);
-            for pos in 0..num_inputs {
-                let arg = mlirFunctionTypeGetInput(func_type.instance, pos);
-                if mlirTypeIsATensor(arg) {
-                    result.push_str("tensor<");
-                    if mlirTypeIsARankedTensor(arg) {
-                        let rank = mlirShapedTypeGetRank(arg);
-                        for r in 0..rank {
-                            let dim = mlirShapedTypeGetDimSize(arg, r as isize);
-                            result.push_str(&dim.to_string());
-                            result.push(',');
-                        }
-                    } else if mlirTypeIsAUnrankedTensor(arg) {
-                        // TODO: parse elem type
-                        result.push_str("*xf64");
-                    }
-                    result.push('>');
-                }
-                if pos < num_inputs - 1 {
-                    result.push(',')
-                }
-            }
-            result.push(')');
-            result.push_str(" -> ");
-            result.push('(');
-            let num_results = mlirFunctionTypeGetNumResults(func_type.instance);
-            for pos in 0..num_results {
-                let arg = mlirFunctionTypeGetResult(func_type.instance, pos);
-                if mlirTypeIsATensor(arg) {
-                    result.push_str("tensor<");
-                    if mlirTypeIsARankedTensor(arg) {
-                        let rank = mlirShapedTypeGetRank(arg);
-                        for r in 0..rank {
-                            let dim = mlirShapedTypeGetDimSize(arg, r as isize);
-                            result.push_str(&dim.to_string());
-                            result.push(',');
-                        }
-                    } else if mlirTypeIsAUnrankedTensor(arg) {
-                        // TODO: parse elem type
-                        result.push_str("*xf64");
-                    }
-                    result.push('>');
-                }
-            }
-            result.push(')');
-            Ok(result)
-        }
-    }
-
-    fn extract_arg_types(func_type: &Type) -> Result<Vec<String>, &'static str> {
-        unsafe {
-            if !mlirTypeIsAFunction(func_type.instance) {
-                return Err("Provided type is not a function type");
-            }
-
-            let mut result = Vec::new();
-            let num_inputs = mlirFunctionTypeGetNumInputs(func_type.instance);
-            for pos in 0..num_inputs {
-                let mut arg_string = String::new();
-                let arg = mlirFunctionTypeGetInput(func_type.instance, pos);
-                if mlirTypeIsATensor(arg) {
-                    arg_string.push_str("tensor<");
-                    if mlirTypeIsARankedTensor(arg) {
-                        let rank = mlirShapedTypeGetRank(arg);
-                        for r in 0..rank {
-                            let dim = mlirShapedTypeGetDimSize(arg, r as isize);
-                            arg_string.push_str(&dim.to_string());
-                            arg_string.push(',');
-                        }
-                    } else if mlirTypeIsAUnrankedTensor(arg) {
-                        // TODO: parse elem type
-                        arg_string.push_str("*xf64");
-                    }
-                    arg_string.push('>');
-                }
-                result.push(arg_string);
-            }
-
-            Ok(result)
-        }
-    }
-
     // FIXME: should be handled by symbol table once introduced
     // it should be mutable ref since we actually change object
     pub fn set_private(&self) {
@@ -382,20 +320,14 @@ impl FuncOp {
 
     pub fn set_type(&self, func_type: &Type) {
         let type_attr_name = CString::new("type").unwrap();
-        let func_type_string: String = Self::serialize_func_type(&func_type).unwrap();
-        let func_type_string = CString::new(&*func_type_string).unwrap();
+        let type_attr = Attribute::new_type(func_type);
 
         unsafe {
-            let mlir_context = mlirOperationGetContext(self.operation.instance);
-            let mlir_new_type_attr = mlirAttributeParseGet(
-                mlir_context,
-                mlirStringRefCreateFromCString(func_type_string.as_ptr()),
-            );
             let mlir_attr_name = mlirStringRefCreateFromCString(type_attr_name.as_ptr());
             mlirOperationSetAttributeByName(
                 self.operation.instance,
                 mlir_attr_name,
-                mlir_new_type_attr,
+                type_attr.instance,
             );
         }
     }
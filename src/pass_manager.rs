@@ -12,6 +12,7 @@ use crate::toy;
 use crate::toy::ffi::mlirToyCreateLowerToAffine;
 use crate::toy::ffi::mlirToyCreateLowerToLLVM;
 use crate::toy::ffi::mlirToyCreateShapeInference;
+use crate::toy::ffi::mlirToyCreateSpecialization;
 
 impl From<toy::ffi::MlirPass> for mlir_sys::MlirPass {
     fn from(dialect: toy::ffi::MlirPass) -> Self {
@@ -59,6 +60,13 @@ impl PassManager {
         }
     }
 
+    pub fn create_specialization_pass() -> Pass {
+        let mlir_pass = unsafe { mlirToyCreateSpecialization() };
+        Pass {
+            instance: mlir_sys::MlirPass::from(mlir_pass),
+        }
+    }
+
     pub fn create_lower_to_affine_pass() -> Pass {
         let mlir_pass = unsafe { mlirToyCreateLowerToAffine() };
         Pass {
@@ -90,9 +98,14 @@ impl PassManager {
         }
     }
 
-    pub fn run(&self, module: &crate::operation::ModuleOp) {
-        // TODO: check returned value
-        unsafe { mlirPassManagerRun(self.instance, module.instance) };
+    /// Runs the passes on `module`, failing if any of them does. The reasons are reported
+    /// as MLIR diagnostics.
+    pub fn run(&self, module: &crate::operation::ModuleOp) -> Result<(), &'static str> {
+        let result = unsafe { mlirPassManagerRun(self.instance, module.instance) };
+        if result.value == 0 {
+            return Err("failed to run the passes");
+        }
+        Ok(())
     }
 }
 
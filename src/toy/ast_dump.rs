@@ -0,0 +1,388 @@
+//! Dumps the AST in the format of `toyc -emit=ast` from the MLIR Toy tutorial, e.g.
+//!
+//! ```text
+//!   Module:
+//!     Function
+//!       Proto 'main' @ast.toy:1:1
+//!       Params: []
+//!       Block {
+//!         VarDecl a<> @ast.toy:2:3
+//!           Literal: <2>[ 1.000000e+00, 2.000000e+00] @ast.toy:2:11
+//!       } // Block
+//! ```
+//!
+//! Constructs that the tutorial doesn't have, like loops or conditionals, are dumped
+//! in the same style.
+
+use std::fmt::Write;
+
+use crate::toy::parser::{Expr, Function, Location, Module, Struct, Subscript, VarType};
+
+/// Returns the dump of `module`, whose structs and functions are listed in source order.
+pub fn dump(module: &Module) -> String {
+    let mut dumper = AstDumper {
+        out: String::new(),
+        indent: 0,
+    };
+    dumper.dump_module(module);
+    dumper.out
+}
+
+struct AstDumper {
+    out: String,
+    indent: usize,
+}
+
+/// Formats a location like `@file:line:col`.
+fn loc(location: &Location) -> String {
+    format!("@{}:{}:{}", location.filename, location.line, location.col)
+}
+
+/// Formats a number like C's `%e`, e.g. `1.000000e+00`.
+fn number(value: f64) -> String {
+    let formatted = format!("{:.6e}", value);
+    match formatted.split_once('e') {
+        Some((mantissa, exponent)) => {
+            let exponent: i32 = exponent.parse().unwrap_or(0);
+            let sign = if exponent < 0 { '-' } else { '+' };
+            format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+        }
+        // NB: infinities and NaNs have no exponent
+        None => formatted,
+    }
+}
+
+fn var_type(var_type: &VarType) -> String {
+    match (&var_type.struct_name, var_type.element_type) {
+        (Some(name), _) => format!("<{}>", name),
+        (None, Some(element_type)) => format!("<{}>:{}", join(&var_type.shape), element_type),
+        (None, None) => format!("<{}>", join(&var_type.shape)),
+    }
+}
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Formats the content of a tensor literal, recursing on its elements, e.g.
+/// `<2>[ 1.000000e+00, 2.000000e+00]`.
+fn literal(expr: &Expr) -> String {
+    match expr {
+        Expr::Tensor { values, dims, .. } => {
+            let values: Vec<String> = values.iter().map(literal).collect();
+            format!("<{}>[ {}]", join(dims), values.join(", "))
+        }
+        Expr::Number { value, .. } => number(*value),
+        _ => String::from("<not a literal>"),
+    }
+}
+
+impl AstDumper {
+    /// Writes a line indented one level deeper than the enclosing construct.
+    fn line(&mut self, text: &str) {
+        let _ = writeln!(self.out, "{}{}", "  ".repeat(self.indent), text);
+    }
+
+    /// Runs `dump` one indentation level deeper.
+    fn nested(&mut self, dump: impl FnOnce(&mut Self)) {
+        self.indent += 1;
+        dump(self);
+        self.indent -= 1;
+    }
+
+    fn dump_module(&mut self, module: &Module) {
+        self.nested(|this| {
+            this.line("Module:");
+            for import in &module.imports {
+                this.nested(|this| {
+                    this.line(&format!(
+                        "Import \"{}\" {}",
+                        import.path,
+                        loc(&import.location)
+                    ))
+                });
+            }
+
+            let mut structs = module.structs.iter().peekable();
+            let mut functions = module.functions.iter().peekable();
+            loop {
+                let next_struct = structs.peek().map(|s| s.location.span.start);
+                let next_function = functions.peek().map(|f| f.location.span.start);
+                match (next_struct, next_function) {
+                    (Some(s), Some(f)) if s < f => this.dump_struct(structs.next().unwrap()),
+                    (Some(_), None) => this.dump_struct(structs.next().unwrap()),
+                    (_, Some(_)) => this.dump_function(functions.next().unwrap()),
+                    (None, None) => break,
+                }
+            }
+        });
+    }
+
+    fn dump_struct(&mut self, struct_ast: &Struct) {
+        self.nested(|this| {
+            this.line(&format!(
+                "Struct: {} {}",
+                struct_ast.name,
+                loc(&struct_ast.location)
+            ));
+            this.nested(|this| {
+                this.line("Variables: [");
+                for field in &struct_ast.fields {
+                    this.nested(|this| {
+                        this.line(&format!(
+                            "VarDecl {}{} {}",
+                            field.name,
+                            var_type(&field.var_type),
+                            loc(&field.location)
+                        ))
+                    });
+                }
+                this.line("]");
+            });
+        });
+    }
+
+    fn dump_function(&mut self, function: &Function) {
+        self.nested(|this| {
+            this.line("Function ");
+            this.nested(|this| {
+                let prototype = &function.prototype;
+                this.line(&format!(
+                    "Proto '{}' {}",
+                    prototype.name,
+                    loc(&function.location)
+                ));
+                let params: Vec<String> = prototype
+                    .args
+                    .iter()
+                    .zip(&prototype.arg_types)
+                    .map(|(arg, arg_type)| {
+                        if arg_type.shape.is_empty() && arg_type.element_type.is_none() {
+                            arg.clone()
+                        } else {
+                            format!("{}{}", arg, var_type(arg_type))
+                        }
+                    })
+                    .collect();
+                this.line(&format!("Params: [{}]", params.join(", ")));
+                if !prototype.return_type.shape.is_empty()
+                    || prototype.return_type.element_type.is_some()
+                {
+                    this.line(&format!("Result: {}", var_type(&prototype.return_type)));
+                }
+            });
+            if let Some(body) = &function.body {
+                this.dump_expr(body);
+            }
+        });
+    }
+
+    fn dump_expr(&mut self, expr: &Expr) {
+        self.nested(|this| match expr {
+            Expr::ExprList { expressions, .. } => {
+                this.line("Block {");
+                for expr in expressions {
+                    this.dump_expr(expr);
+                }
+                this.line("} // Block");
+            }
+            // NB: like in the tutorial, a binary operation is located at its right-hand side
+            Expr::Binary {
+                op, left, right, ..
+            } => {
+                this.line(&format!("BinOp: {} {}", op, loc(right.location())));
+                this.dump_expr(left);
+                this.dump_expr(right);
+            }
+            Expr::MemberAccess {
+                value,
+                field,
+                field_location,
+                ..
+            } => {
+                this.line(&format!("BinOp: . {}", loc(field_location)));
+                this.dump_expr(value);
+                this.nested(|this| this.line(&format!("var: {} {}", field, loc(field_location))));
+            }
+            Expr::Index {
+                location,
+                value,
+                subscripts,
+            } => {
+                this.line(&format!("Index {}", loc(location)));
+                this.dump_expr(value);
+                for subscript in subscripts {
+                    match subscript {
+                        Subscript::Index(index) => this.dump_expr(index),
+                        Subscript::Slice { start, end } => this.nested(|this| {
+                            let bound = |bound: &Option<usize>| {
+                                bound.map(|bound| bound.to_string()).unwrap_or_default()
+                            };
+                            this.line(&format!("Slice {}:{}", bound(start), bound(end)))
+                        }),
+                    }
+                }
+            }
+            Expr::Unary {
+                location,
+                op,
+                operand,
+            } => {
+                this.line(&format!("UnaryOp: {} {}", op, loc(location)));
+                this.dump_expr(operand);
+            }
+            Expr::Call {
+                location,
+                fn_name,
+                args,
+            } => {
+                this.line(&format!("Call '{}' [ {}", fn_name, loc(location)));
+                for arg in args {
+                    this.dump_expr(arg);
+                }
+                this.line("]");
+            }
+            Expr::Print {
+                location,
+                expression,
+            } => {
+                this.line(&format!("Print [ {}", loc(location)));
+                this.dump_expr(expression);
+                this.line("]");
+            }
+            Expr::Return { expression, .. } => {
+                this.line("Return");
+                match expression {
+                    Some(expression) => this.dump_expr(expression),
+                    None => this.nested(|this| this.line("(void)")),
+                }
+            }
+            Expr::VarDecl {
+                location,
+                name,
+                var_type: ty,
+                value,
+            } => {
+                this.line(&format!(
+                    "VarDecl {}{} {}",
+                    name,
+                    var_type(ty),
+                    loc(location)
+                ));
+                if let Some(value) = value {
+                    this.dump_expr(value);
+                }
+            }
+            Expr::Destructure {
+                location,
+                names,
+                value,
+            } => {
+                this.line(&format!("VarDecl ({}) {}", names.join(", "), loc(location)));
+                this.dump_expr(value);
+            }
+            Expr::Tuple { location, values } => {
+                this.line(&format!("Tuple [ {}", loc(location)));
+                for value in values {
+                    this.dump_expr(value);
+                }
+                this.line("]");
+            }
+            Expr::Variable { location, name } => {
+                this.line(&format!("var: {} {}", name, loc(location)));
+            }
+            Expr::Number { location, value } => {
+                this.line(&format!("{} {}", number(*value), loc(location)));
+            }
+            Expr::Tensor { location, .. } => {
+                this.line(&format!("Literal: {} {}", literal(expr), loc(location)));
+            }
+            // NB: the tutorial prints the first value on the same line as the header, which
+            // isn't worth replicating
+            Expr::StructLiteral { location, values } => {
+                this.line(&format!("Struct Literal: {}", loc(location)));
+                for value in values {
+                    this.dump_expr(value);
+                }
+            }
+            Expr::If {
+                location,
+                condition,
+                then_block,
+                else_block,
+            } => {
+                this.line(&format!("If {}", loc(location)));
+                this.dump_expr(condition);
+                this.dump_expr(then_block);
+                if let Some(else_block) = else_block {
+                    this.line("Else");
+                    this.dump_expr(else_block);
+                }
+            }
+            Expr::For {
+                location,
+                var,
+                start,
+                end,
+                body,
+            } => {
+                this.line(&format!(
+                    "For {} in {}..{} {}",
+                    var,
+                    start,
+                    end,
+                    loc(location)
+                ));
+                this.dump_expr(body);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::toy::parser::Parser;
+
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test]
+    fn numbers_are_printed_like_c() {
+        assert_eq!(number(1.0), "1.000000e+00");
+        assert_eq!(number(0.5), "5.000000e-01");
+        assert_eq!(number(123456.0), "1.234560e+05");
+        assert_eq!(number(0.0), "0.000000e+00");
+    }
+
+    #[test_case("ast")]
+    #[test_case("ast_tensor")]
+    #[test_case("control_flow")]
+    #[test_case("custom_ops")]
+    #[test_case("indexing")]
+    #[test_case("multiple_results")]
+    #[test_case("struct")]
+    fn dump_matches_golden_file(name: &str) {
+        let filename = format!("testdata/{}.toy", name);
+        let content = std::fs::read_to_string(&filename).unwrap();
+        let mut prec = HashMap::new();
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+        let module = Parser::new(content, &filename, &mut prec)
+            .parse_module()
+            .unwrap();
+
+        let expected = std::fs::read_to_string(format!("testdata/{}.ast", name)).unwrap();
+        assert_eq!(dump(&module), expected);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+
+use crate::toy::parser::Location;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A message about a piece of Toy source, which can be rendered together with
+/// the offending source line in the style of rustc.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Location,
+    /// Short text printed next to the carets underlining the location.
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, location: Location) -> Self {
+        Self::new(Severity::Error, message.into(), location)
+    }
+
+    pub fn warning(message: impl Into<String>, location: Location) -> Self {
+        Self::new(Severity::Warning, message.into(), location)
+    }
+
+    fn new(severity: Severity, message: String, location: Location) -> Self {
+        Self {
+            severity,
+            message,
+            location,
+            label: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Renders the diagnostic along with the line of `source` it points to, e.g.
+    ///
+    /// ```text
+    /// error: expected ';' after variable declaration, found 'var'
+    ///  --> example.toy:3:3
+    ///   |
+    /// 3 |   var b = 2;
+    ///   |   ^^^ unexpected 'var'
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let location = &self.location;
+        let line_number = location.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let line = source
+            .lines()
+            .nth(location.line.saturating_sub(1))
+            .unwrap_or("");
+
+        // Keep tabs so that the carets line up with the source line.
+        let indent: String = line
+            .chars()
+            .take(location.col.saturating_sub(1))
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let available = line.chars().count().saturating_sub(indent.chars().count());
+        let width = source
+            .get(location.span.clone())
+            .map_or(0, |text| text.lines().next().unwrap_or("").chars().count())
+            .min(available)
+            .max(1);
+
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+        out += &format!("{}--> {}\n", gutter, location);
+        out += &format!("{} |\n", gutter);
+        out += &format!("{} | {}\n", line_number, line);
+        out += &format!("{} | {}{}", gutter, indent, "^".repeat(width));
+        if let Some(label) = &self.label {
+            out += &format!(" {}", label);
+        }
+        out.push('\n');
+
+        if !self.notes.is_empty() || self.help.is_some() {
+            out += &format!("{} |\n", gutter);
+        }
+        for note in &self.notes {
+            out += &format!("{} = note: {}\n", gutter, note);
+        }
+        if let Some(help) = &self.help {
+            out += &format!("{} = help: {}\n", gutter, help);
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.location, self.severity, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_points_at_span() {
+        let source = "def main() {\n  var a = 1;\n  var b = 2 3;\n}\n";
+        let location = Location {
+            filename: "test.toy".to_string(),
+            line: 3,
+            col: 13,
+            span: 37..38,
+        };
+        let diagnostic = Diagnostic::error("expected ';', found number `3`", location)
+            .with_label("unexpected number")
+            .with_help("add ';' to end the statement");
+
+        let expected = "\
+error: expected ';', found number `3`
+ --> test.toy:3:13
+  |
+3 |   var b = 2 3;
+  |             ^ unexpected number
+  |
+  = help: add ';' to end the statement
+";
+        assert_eq!(diagnostic.render(source), expected);
+    }
+}
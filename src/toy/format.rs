@@ -0,0 +1,519 @@
+//! Pretty printing of a parsed module as canonical Toy source.
+//!
+//! Statements are indented by two spaces, binary operators are surrounded by spaces and
+//! only the parentheses precedence requires are kept. Comments are kept where they were,
+//! as are single blank lines between statements; definitions are separated by exactly one.
+
+use std::collections::HashMap;
+
+use crate::toy::parser::{Expr, Function, Location, Module, Prototype, Struct, Subscript, VarType};
+
+/// Formats `module`, whose binary operators have the precedences of `op_precedence`, e.g.
+/// the ones the parser was given and filled in with custom operators.
+pub fn format(module: &Module, op_precedence: &HashMap<char, i32>) -> String {
+    let mut formatter = Formatter {
+        out: String::new(),
+        indent: 0,
+        module,
+        next_comment: 0,
+        last_line: 0,
+        op_precedence,
+    };
+    formatter.format_module();
+    formatter.out
+}
+
+enum Definition<'a> {
+    Struct(&'a Struct),
+    Function(&'a Function),
+}
+
+impl Definition<'_> {
+    fn location(&self) -> &Location {
+        match self {
+            Definition::Struct(struct_ast) => &struct_ast.location,
+            Definition::Function(function) => &function.location,
+        }
+    }
+}
+
+/// What was last written at the current level of indentation.
+#[derive(Clone, Copy)]
+enum Last {
+    Nothing,
+    Comment,
+    Definition,
+    Statement,
+}
+
+struct Formatter<'a> {
+    out: String,
+    indent: usize,
+    module: &'a Module,
+    // NB: index of the first comment that wasn't written yet
+    next_comment: usize,
+    // NB: line of the source on which what was written last starts
+    last_line: usize,
+    op_precedence: &'a HashMap<char, i32>,
+}
+
+impl Formatter<'_> {
+    fn line(&mut self, text: &str) {
+        self.out += &"  ".repeat(self.indent);
+        self.out += text;
+        self.out.push('\n');
+    }
+
+    /// Writes a blank line before what starts on `line`, if there was one in the source.
+    /// A definition is always followed by one.
+    fn separate(&mut self, line: usize, last: Last) {
+        let blank = match last {
+            Last::Nothing => false,
+            Last::Definition => true,
+            Last::Comment | Last::Statement => {
+                line > self.last_line && self.module.blank_lines.contains(&(line - 1))
+            }
+        };
+        if blank && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+        self.last_line = line;
+    }
+
+    /// Writes the comments starting before the byte `end` of the source, and returns what
+    /// was written last. A trailing comment goes at the end of the line written last.
+    fn comments_before(&mut self, end: usize, mut last: Last) -> Last {
+        while let Some(comment) = self.module.comments.get(self.next_comment) {
+            if comment.location.span.start >= end {
+                break;
+            }
+            self.next_comment += 1;
+
+            if comment.trailing && self.out.ends_with('\n') {
+                self.out.pop();
+                self.out += &format!(" #{}\n", comment.text);
+            } else {
+                self.separate(comment.location.line, last);
+                self.line(&format!("#{}", comment.text));
+                last = Last::Comment;
+            }
+        }
+        last
+    }
+
+    fn format_module(&mut self) {
+        let mut definitions: Vec<Definition> = Vec::new();
+        definitions.extend(self.module.structs.iter().map(Definition::Struct));
+        definitions.extend(self.module.functions.iter().map(Definition::Function));
+        definitions.sort_by_key(|definition| definition.location().span.start);
+
+        let mut last = Last::Nothing;
+        for import in &self.module.imports {
+            last = self.comments_before(import.location.span.start, last);
+            self.separate(import.location.line, last);
+            self.line(&format!("import \"{}\";", import.path));
+            last = Last::Statement;
+        }
+        // NB: the imports are set apart from the definitions like a definition is
+        if !self.module.imports.is_empty() {
+            last = Last::Definition;
+        }
+        for definition in definitions {
+            let location = definition.location();
+            last = self.comments_before(location.span.start, last);
+            self.separate(location.line, last);
+            match definition {
+                Definition::Struct(struct_ast) => self.format_struct(struct_ast),
+                Definition::Function(function) => self.format_function(function),
+            }
+            last = Last::Definition;
+        }
+        self.comments_before(usize::MAX, last);
+    }
+
+    fn format_struct(&mut self, struct_ast: &Struct) {
+        self.line(&format!("struct {} {{", struct_ast.name));
+        self.indent += 1;
+        let mut last = Last::Nothing;
+        for field in &struct_ast.fields {
+            last = self.comments_before(field.location.span.start, last);
+            self.separate(field.location.line, last);
+            self.line(&format!("{};", declaration(&field.var_type, &field.name)));
+            last = Last::Statement;
+        }
+        self.comments_before(struct_ast.location.span.end, last);
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn format_function(&mut self, function: &Function) {
+        self.line(&format!("def {} {{", prototype(&function.prototype)));
+        if let Some(body) = &function.body {
+            self.format_block(body);
+        }
+        self.line("}");
+    }
+
+    /// Writes the statements of `block`, without its braces.
+    fn format_block(&mut self, block: &Expr) {
+        self.indent += 1;
+        let mut last = Last::Nothing;
+        if let Expr::ExprList { expressions, .. } = block {
+            for statement in expressions {
+                let location = statement.location();
+                last = self.comments_before(location.span.start, last);
+                self.separate(location.line, last);
+                self.format_statement(statement);
+                last = Last::Statement;
+            }
+        }
+        self.comments_before(block.location().span.end, last);
+        self.indent -= 1;
+    }
+
+    fn format_statement(&mut self, statement: &Expr) {
+        match statement {
+            Expr::If { .. } => {
+                self.format_if(statement, "");
+                self.line("}");
+            }
+            Expr::For {
+                var,
+                start,
+                end,
+                body,
+                ..
+            } => {
+                self.line(&format!("for {} in {}..{} {{", var, start, end));
+                self.format_block(body);
+                self.line("}");
+            }
+            _ => {
+                let statement = self.expr(statement);
+                self.line(&format!("{};", statement));
+            }
+        }
+    }
+
+    /// Writes an `if` up to its closing brace, which is preceded by `prefix`, e.g. `} else `.
+    fn format_if(&mut self, expr: &Expr, prefix: &str) {
+        let (condition, then_block, else_block) = match expr {
+            Expr::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => (condition, then_block, else_block),
+            _ => return,
+        };
+
+        self.line(&format!("{}if ({}) {{", prefix, self.expr(condition)));
+        self.format_block(then_block);
+        match else_block.as_deref() {
+            Some(Expr::ExprList { expressions, .. })
+                if expressions.len() == 1 && matches!(*expressions[0], Expr::If { .. }) =>
+            {
+                self.format_if(&expressions[0], "} else ");
+            }
+            Some(else_block) => {
+                self.line("} else {");
+                self.format_block(else_block);
+            }
+            None => (),
+        }
+    }
+
+    fn precedence(&self, op: char) -> i32 {
+        self.op_precedence.get(&op).copied().unwrap_or(-1)
+    }
+
+    /// Formats an operand of a binary operator of precedence `prec`, parenthesizing it if it
+    /// binds less tightly, or as tightly on the right-hand side since operators are
+    /// left-associative.
+    fn operand(&self, expr: &Expr, prec: i32, is_right: bool) -> String {
+        match expr {
+            Expr::Binary { op, .. } => {
+                let operand_prec = self.precedence(*op);
+                if operand_prec < prec || is_right && operand_prec == prec {
+                    format!("({})", self.expr(expr))
+                } else {
+                    self.expr(expr)
+                }
+            }
+            _ => self.expr(expr),
+        }
+    }
+
+    /// Formats an expression to which a postfix or prefix operator applies.
+    fn atom(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Binary { .. } | Expr::Unary { .. } => format!("({})", self.expr(expr)),
+            _ => self.expr(expr),
+        }
+    }
+
+    fn exprs(&self, exprs: &[Expr]) -> String {
+        exprs
+            .iter()
+            .map(|expr| self.expr(expr))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Binary {
+                op, left, right, ..
+            } => {
+                let prec = self.precedence(*op);
+                format!(
+                    "{} {} {}",
+                    self.operand(left, prec, false),
+                    op,
+                    self.operand(right, prec, true)
+                )
+            }
+            Expr::Unary { op, operand, .. } => match operand.as_ref() {
+                Expr::Unary { .. } => format!("{}{}", op, self.expr(operand)),
+                _ => format!("{}{}", op, self.atom(operand)),
+            },
+            Expr::Call { fn_name, args, .. } => format!("{}({})", fn_name, self.exprs(args)),
+            Expr::Print { expression, .. } => format!("print({})", self.expr(expression)),
+            Expr::Number { value, .. } => format!("{}", value),
+            Expr::Variable { name, .. } => name.clone(),
+            Expr::VarDecl {
+                name,
+                var_type,
+                value,
+                ..
+            } => match value {
+                Some(value) => format!("{} = {}", declaration(var_type, name), self.expr(value)),
+                None => declaration(var_type, name),
+            },
+            Expr::Destructure { names, value, .. } => {
+                format!("var ({}) = {}", names.join(", "), self.expr(value))
+            }
+            Expr::Tuple { values, .. } => format!("({})", self.exprs(values)),
+            Expr::Tensor { values, .. } => format!("[{}]", self.exprs(values)),
+            Expr::StructLiteral { values, .. } => format!("{{{}}}", self.exprs(values)),
+            Expr::MemberAccess { value, field, .. } => format!("{}.{}", self.atom(value), field),
+            Expr::Index {
+                value, subscripts, ..
+            } => {
+                let subscripts: Vec<String> = subscripts
+                    .iter()
+                    .map(|subscript| match subscript {
+                        Subscript::Index(index) => self.expr(index),
+                        Subscript::Slice { start, end } => {
+                            format!("{}:{}", bound(start), bound(end))
+                        }
+                    })
+                    .collect();
+                format!("{}[{}]", self.atom(value), subscripts.join(", "))
+            }
+            Expr::Return {
+                expression: Some(expression),
+                ..
+            } => format!("return {}", self.expr(expression)),
+            Expr::Return {
+                expression: None, ..
+            } => String::from("return"),
+            // NB: blocks, conditionals and loops are statements, formatted by the caller
+            Expr::ExprList { .. } | Expr::If { .. } | Expr::For { .. } => String::new(),
+        }
+    }
+}
+
+fn prototype(prototype: &Prototype) -> String {
+    let params: Vec<String> = prototype
+        .args
+        .iter()
+        .zip(&prototype.arg_types)
+        .map(|(arg, arg_type)| match &arg_type.struct_name {
+            Some(struct_name) => format!("{} {}", struct_name, arg),
+            None => format!("{}{}", arg, shape(arg_type)),
+        })
+        .collect();
+    let params = params.join(", ");
+
+    let signature = if !prototype.is_op {
+        format!("{}({})", prototype.name, params)
+    } else if prototype.name.starts_with("binary") {
+        format!("{} {} ({})", prototype.name, prototype.prec, params)
+    } else {
+        format!("{} ({})", prototype.name, params)
+    };
+    match shape(&prototype.return_type).as_str() {
+        "" => signature,
+        result => format!("{} -> {}", signature, result),
+    }
+}
+
+/// Formats a bound of a slice, which is left out if omitted in the source.
+fn bound(bound: &Option<usize>) -> String {
+    bound.map(|bound| bound.to_string()).unwrap_or_default()
+}
+
+/// Formats the declared shape and element type of `var_type`, e.g. `<2, 3>:f32`, or
+/// nothing if it has none.
+fn shape(var_type: &VarType) -> String {
+    let mut formatted = String::new();
+    if !var_type.shape.is_empty() {
+        let dims: Vec<String> = var_type.shape.iter().map(ToString::to_string).collect();
+        formatted = format!("<{}>", dims.join(", "));
+    }
+    if let Some(element_type) = var_type.element_type {
+        formatted = format!("{}:{}", formatted, element_type);
+    }
+    formatted
+}
+
+/// Formats the declaration of `name`, e.g. `var a<2, 3>` or `Pair p`.
+fn declaration(var_type: &VarType, name: &str) -> String {
+    match &var_type.struct_name {
+        Some(struct_name) => format!("{} {}", struct_name, name),
+        None => format!("var {}{}", name, shape(var_type)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::toy::ast_dump;
+    use crate::toy::parser::Parser;
+
+    use super::*;
+
+    use test_case::test_case;
+
+    fn precedences() -> HashMap<char, i32> {
+        let mut prec = HashMap::new();
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+        prec
+    }
+
+    /// Parses and formats `input`, returning the module along with its formatted source.
+    fn reformat(input: &str) -> (Module, String) {
+        let mut prec = precedences();
+        let module = Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap();
+        let formatted = format(&module, &prec);
+        (module, formatted)
+    }
+
+    /// Dumps `module` without locations, which formatting changes.
+    fn dump_without_locations(module: &Module) -> String {
+        ast_dump::dump(module)
+            .lines()
+            .map(|line| match line.find(" @") {
+                Some(index) => &line[..index],
+                None => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test_case("arith")]
+    #[test_case("ast")]
+    #[test_case("ast_empty")]
+    #[test_case("ast_tensor")]
+    #[test_case("broadcast")]
+    #[test_case("call_chain")]
+    #[test_case("calls")]
+    #[test_case("constructors")]
+    #[test_case("control_flow")]
+    #[test_case("custom_ops")]
+    #[test_case("element_types")]
+    #[test_case("indexing")]
+    #[test_case("math")]
+    #[test_case("multiple_results")]
+    #[test_case("print")]
+    #[test_case("print_transposed")]
+    #[test_case("recursion")]
+    #[test_case("reductions")]
+    #[test_case("reshape_opt")]
+    #[test_case("signatures")]
+    #[test_case("struct")]
+    #[test_case("uninitialized")]
+    #[test_case("transpose_transpose_opt")]
+    fn round_trip_is_stable(name: &str) {
+        let content = std::fs::read_to_string(format!("testdata/{}.toy", name)).unwrap();
+        let (module, formatted) = reformat(&content);
+        let (reparsed, reformatted) = reformat(&formatted);
+
+        assert_eq!(formatted, reformatted);
+        assert_eq!(
+            dump_without_locations(&module),
+            dump_without_locations(&reparsed)
+        );
+    }
+
+    #[test]
+    fn normalizes_layout() {
+        let input = "# leading comment
+struct   Pair{var a;
+  var b;}
+def binary|  5(a,b){return a+b;}   # trailing comment
+def main ( ) {
+    var x<2,1>=[ 1.0 ,2 ];  # x
+
+
+    var y = (x + x) * (x - (x - x)) * x ; Pair p = {x, y};
+    if (p.a < 1) { print(x); } else { if (x) { print (y); } }
+    for i in 0..2 {x = x | (y | x);}
+    # dangling comment
+}
+";
+        let expected = "# leading comment
+struct Pair {
+  var a;
+  var b;
+}
+
+def binary| 5 (a, b) {
+  return a + b;
+} # trailing comment
+
+def main() {
+  var x<2, 1> = [1, 2]; # x
+
+  var y = (x + x) * (x - (x - x)) * x;
+  Pair p = {x, y};
+  if (p.a < 1) {
+    print(x);
+  } else if (x) {
+    print(y);
+  }
+  for i in 0..2 {
+    x = x | (y | x);
+  }
+  # dangling comment
+}
+";
+        let (_, formatted) = reformat(input);
+        assert_eq!(formatted, expected);
+    }
+
+    #[test]
+    fn imports_come_first() {
+        let input =
+            "# imports\nimport   \"lib.toy\" ;\n\n\nimport \"ops.toy\";# ops\ndef main() {}\n";
+        let expected = "# imports
+import \"lib.toy\";
+
+import \"ops.toy\"; # ops
+
+def main() {
+}
+";
+        let (_, formatted) = reformat(input);
+        assert_eq!(formatted, expected);
+    }
+}
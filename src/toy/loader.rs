@@ -0,0 +1,296 @@
+//! Loading of programs spread over several files.
+//!
+//! The `Loader` parses a file along with the files it imports, transitively, and merges
+//! their definitions into a single `Module`, so that later passes don't have to know
+//! about imports at all.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::toy::diagnostic::Diagnostic;
+use crate::toy::parser::{Import, Location, Module, Parser};
+
+/// Loads a Toy file and everything it imports.
+pub struct Loader<'a> {
+    op_precedence: &'a mut HashMap<char, i32>,
+    /// The content of every file read so far, by the name used in its locations.
+    sources: HashMap<String, String>,
+    /// The canonical paths of the files already merged.
+    loaded: HashSet<PathBuf>,
+    /// The operator functions each loaded file defines or imports, by canonical path.
+    operators: HashMap<PathBuf, Vec<String>>,
+    /// The files being loaded, each importing the next one: the canonical path and the
+    /// name of each.
+    stack: Vec<(PathBuf, String)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Loader<'a> {
+    /// Creates a new loader, given a `HashMap` binding an operator and its precedence in
+    /// binary expressions, which gets the operators defined by every loaded file.
+    pub fn new(op_precedence: &'a mut HashMap<char, i32>) -> Self {
+        Self {
+            op_precedence,
+            sources: HashMap::new(),
+            loaded: HashSet::new(),
+            operators: HashMap::new(),
+            stack: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Loads `filename` and the files it imports, returning their definitions merged into
+    /// one module, or every error found in any of them.
+    ///
+    /// The imports, comments and blank lines of the module are those of `filename`.
+    pub fn load(&mut self, filename: &str) -> Result<Module, Vec<Diagnostic>> {
+        let mut module = Module::default();
+        if let Some(root) = self.load_file(Path::new(filename), None, &mut module) {
+            module.imports = root.imports;
+            module.comments = root.comments;
+            module.blank_lines = root.blank_lines;
+        }
+
+        if self.diagnostics.is_empty() {
+            Ok(module)
+        } else {
+            Err(std::mem::take(&mut self.diagnostics))
+        }
+    }
+
+    /// Returns the content of a loaded file, to render the diagnostics pointing into it.
+    pub fn source(&self, filename: &str) -> Option<&str> {
+        self.sources.get(filename).map(String::as_str)
+    }
+
+    /// Loads the file at `path`, imported by `import` if it isn't the root file, and merges
+    /// its definitions into `merged` after those of its imports. Returns the parsed file,
+    /// unless it couldn't be read or has already been merged.
+    fn load_file(
+        &mut self,
+        path: &Path,
+        import: Option<&Import>,
+        merged: &mut Module,
+    ) -> Option<Module> {
+        let filename = path.to_string_lossy().into_owned();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) => {
+                let message = format!("cannot read `{}`: {}", filename, error);
+                self.diagnostics.push(match import {
+                    Some(import) => Diagnostic::error(message, import.location.clone()),
+                    None => Diagnostic::error(message, Location::new(&filename, 1, 1)),
+                });
+                return None;
+            }
+        };
+        let canonical = canonical_path(path);
+
+        if let Some(start) = self.stack.iter().position(|(path, _)| *path == canonical) {
+            let mut cycle: Vec<&str> = self.stack[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect();
+            cycle.push(&filename);
+            // NB: only the root file has no import, and it is always at the bottom of the stack
+            let import = import.unwrap();
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("import cycle: {}", cycle.join(" -> ")),
+                    import.location.clone(),
+                )
+                .with_label("imported again here"),
+            );
+            return None;
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            // Imported through another path already, e.g. by two files importing a common one.
+            return None;
+        }
+        self.sources.insert(filename.clone(), content.clone());
+        self.stack.push((canonical.clone(), filename.clone()));
+
+        // The imports are loaded first, since the file may use the operators they define.
+        let imports = Parser::new(content.clone(), &filename, self.op_precedence).parse_imports();
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut operators = Vec::new();
+        for import in &imports {
+            let path = directory.join(&import.path);
+            self.load_file(&path, Some(import), merged);
+            if let Some(imported) = self.operators.get(&canonical_path(&path)) {
+                operators.extend(imported.iter().cloned());
+            }
+        }
+
+        let (module, errors) = Parser::new(content, &filename, self.op_precedence)
+            .with_imported_operators(operators.clone())
+            .parse_module_partial();
+        self.diagnostics
+            .extend(errors.iter().map(|error| error.to_diagnostic()));
+        self.merge(merged, &module);
+
+        operators.extend(
+            module
+                .functions
+                .iter()
+                .filter(|function| function.prototype.is_op)
+                .map(|function| function.prototype.name.clone()),
+        );
+        self.operators.insert(canonical, operators);
+        self.stack.pop();
+        Some(module)
+    }
+
+    /// Adds the definitions of `module` to `merged`, reporting those already defined by
+    /// another file. Duplicates within `module` are left for semantic analysis to report.
+    fn merge(&mut self, merged: &mut Module, module: &Module) {
+        let structs: HashMap<&str, &Location> = merged
+            .structs
+            .iter()
+            .map(|s| (s.name.as_str(), &s.location))
+            .collect();
+        let mut new_structs = Vec::new();
+        for s in &module.structs {
+            match structs.get(s.name.as_str()) {
+                Some(previous) => {
+                    self.diagnostics
+                        .push(duplicate("struct", &s.name, &s.location, previous))
+                }
+                None => new_structs.push(s.clone()),
+            }
+        }
+
+        let functions: HashMap<&str, &Location> = merged
+            .functions
+            .iter()
+            .map(|f| (f.prototype.name.as_str(), &f.location))
+            .collect();
+        let mut new_functions = Vec::new();
+        for function in &module.functions {
+            let name = &function.prototype.name;
+            match functions.get(name.as_str()) {
+                Some(previous) => {
+                    self.diagnostics
+                        .push(duplicate("function", name, &function.location, previous))
+                }
+                None => new_functions.push(function.clone()),
+            }
+        }
+
+        merged.structs.extend(new_structs);
+        merged.functions.extend(new_functions);
+    }
+}
+
+/// Returns the canonical form of `path`, or `path` itself if it doesn't exist.
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Reports the definition of `name` at `location`, already defined in another file at
+/// `previous`.
+fn duplicate(kind: &str, name: &str, location: &Location, previous: &Location) -> Diagnostic {
+    Diagnostic::error(
+        format!(
+            "{} `{}` is already defined in `{}`",
+            kind, name, previous.filename
+        ),
+        location.clone(),
+    )
+    .with_label(format!("`{}` redefined here", name))
+    .with_note(format!("previous definition at {}", previous))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(filename: &str) -> Result<Module, Vec<Diagnostic>> {
+        let mut prec = HashMap::with_capacity(6);
+
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+        Loader::new(&mut prec).load(filename)
+    }
+
+    fn function_names(module: &Module) -> Vec<&str> {
+        module
+            .functions
+            .iter()
+            .map(|f| f.prototype.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn merges_imported_functions_first() {
+        let module = load("testdata/imports/main.toy").unwrap_or_else(|errors| {
+            panic!("{}", errors[0]);
+        });
+
+        assert_eq!(
+            function_names(&module),
+            vec!["binary|", "multiply_transpose", "main"]
+        );
+        assert_eq!(module.imports.len(), 1);
+        assert_eq!(module.imports[0].path, "lib/linalg.toy");
+    }
+
+    #[test]
+    fn loads_shared_imports_once() {
+        let module = load("testdata/imports/diamond.toy").unwrap_or_else(|errors| {
+            panic!("{}", errors[0]);
+        });
+
+        assert_eq!(
+            function_names(&module),
+            vec!["binary|", "multiply_transpose", "main"]
+        );
+    }
+
+    #[test]
+    fn reports_import_cycles() {
+        let errors = load("testdata/imports/cycle_a.toy").unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "import cycle: testdata/imports/cycle_a.toy -> testdata/imports/cycle_b.toy \
+             -> testdata/imports/cycle_a.toy"
+        );
+        assert_eq!(errors[0].location.filename, "testdata/imports/cycle_b.toy");
+    }
+
+    #[test]
+    fn reports_duplicates_across_files() {
+        let errors = load("testdata/imports/duplicate.toy").unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "function `multiply_transpose` is already defined in \
+             `testdata/imports/lib/linalg.toy`"
+        );
+        assert_eq!(
+            errors[0].location.filename,
+            "testdata/imports/duplicate.toy"
+        );
+        assert_eq!(errors[0].location.line, 4);
+    }
+
+    #[test]
+    fn reports_missing_files_at_the_import() {
+        let errors = load("testdata/imports/missing.toy").unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .message
+            .starts_with("cannot read `testdata/imports/lib/missing.toy`"));
+        assert_eq!(errors[0].location.filename, "testdata/imports/missing.toy");
+        assert_eq!(errors[0].location.line, 1);
+    }
+}
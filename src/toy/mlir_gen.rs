@@ -3,43 +3,96 @@ use std::rc::Rc;
 
 use mlir_sys::mlirBlockGetArgument;
 
+use crate::block::{Block, Region};
 use crate::context::Context;
 use crate::location::Location;
 use crate::misc::{Attribute, Type, Value};
 use crate::op_builder::OpBuilder;
-use crate::operation::{FuncOp, ModuleOp, OneRegion};
+use crate::operation::{FuncOp, ModuleOp, OneRegion, Operation};
 
 use crate::toy::parser::Expr::{
-    Binary, Call, ExprList, Number, Print, Return, Tensor, VarDecl, Variable,
+    Binary, Call, Destructure, ExprList, For, If, Index, MemberAccess, Number, Print, Return,
+    StructLiteral, Tensor, Tuple, Unary, VarDecl, Variable,
 };
 
-use crate::toy::parser::{Expr, Function, Module, Prototype};
+use crate::toy::parser;
+use crate::toy::parser::{ElementType, Expr, Function, Module, Prototype, Subscript, VarType};
+use crate::toy::sema::{
+    constructor_shape, is_literal, reduction_axis, result_count, struct_order, MATH_FUNCTIONS,
+    REDUCTIONS, TENSOR_CONSTRUCTORS,
+};
+use crate::toy::visit::Visitor;
 
 use super::toy_dialect::{
-    AddOpBuilder, ConstantOpBuilder, GenericCallOpBuilder, MulOpBuilder, PrintOpBuilder,
-    ReshapeOpBuilder, ReturnOpBuilder, TransposeOpBuilder,
+    get_struct_type, AbsOpBuilder, AddOpBuilder, ConstantOpBuilder, DivOpBuilder, ExpOpBuilder,
+    ExtractOpBuilder, ForOpBuilder, GenericCallOpBuilder, IfOpBuilder, LogOpBuilder, LtOpBuilder,
+    MatMulOpBuilder, MaxOpBuilder, MeanOpBuilder, MulOpBuilder, NegOpBuilder, PrintOpBuilder,
+    ReshapeOpBuilder, ReturnOpBuilder, SliceOpBuilder, SqrtOpBuilder, StructAccessOpBuilder,
+    StructConstantOpBuilder, SubOpBuilder, SumOpBuilder, TanhOpBuilder, TransposeOpBuilder,
+    YieldOpBuilder,
 };
 
 pub struct MLIRGen {
     module: ModuleOp,
     symbol_table: HashMap<String, Value>,
+    // NB: maps the name of a struct to its MLIR type and its declaration
+    structs: HashMap<String, (Type, parser::Struct)>,
+    // NB: maps the name of a struct variable to the name of its struct
+    var_structs: HashMap<String, String>,
+    // NB: maps the name of a function to the type of its result declared in the source
+    result_types: HashMap<String, VarType>,
+    // NB: maps the name of a function to the types of its parameters declared in the source
+    param_types: HashMap<String, Vec<VarType>>,
+    // NB: maps the name of a function to the number of values it returns
+    result_counts: HashMap<String, usize>,
+    // NB: the element type of the literals being generated, that of the values they are
+    // combined with
+    literal_type: Type,
+    // NB: the element type of the result of the function being generated
+    result_element_type: Type,
+    // NB: number of toy.if and toy.for the generated code is nested in
+    region_depth: usize,
     context: Rc<Context>,
     builder: OpBuilder,
 }
 
 impl<'ctx> MLIRGen {
     pub fn new(context: Rc<Context>) -> Self {
+        let builder = OpBuilder::new(Option::None, 0, Rc::clone(&context));
         Self {
             module: ModuleOp::new(Location::new(Rc::clone(&context))),
             symbol_table: HashMap::new(),
+            structs: HashMap::new(),
+            var_structs: HashMap::new(),
+            result_types: HashMap::new(),
+            param_types: HashMap::new(),
+            result_counts: HashMap::new(),
+            literal_type: builder.get_f64_type(),
+            result_element_type: builder.get_f64_type(),
+            region_depth: 0,
             context: Rc::clone(&context),
-            builder: OpBuilder::new(Option::None, 0, Rc::clone(&context)),
+            builder,
         }
     }
 
     pub fn mlir_gen(&mut self, module_ast: Module) -> ModuleOp {
         self.module = ModuleOp::new(Location::new(Rc::clone(&self.context)));
 
+        // NB: the types of the structs held by the fields of a struct are built first
+        for s in struct_order(&module_ast.structs) {
+            self.mlir_gen_struct(s.clone());
+        }
+
+        // NB: functions may be called before they are defined
+        for f in &module_ast.functions {
+            self.result_types
+                .insert(f.prototype.name.clone(), f.prototype.return_type.clone());
+            self.param_types
+                .insert(f.prototype.name.clone(), f.prototype.arg_types.clone());
+            self.result_counts
+                .insert(f.prototype.name.clone(), result_count(f));
+        }
+
         // TODO: implement Iterator for Module?
         for f in module_ast.functions {
             let func = self.mlir_gen_function(f);
@@ -49,11 +102,46 @@ impl<'ctx> MLIRGen {
         self.module.clone()
     }
 
+    fn mlir_gen_struct(&mut self, struct_ast: parser::Struct) {
+        if self.structs.contains_key(&struct_ast.name) {
+            panic!(
+                "MLIR codegen encountered an error: struct {} is defined more than once",
+                struct_ast.name
+            );
+        }
+        if struct_ast.fields.is_empty() {
+            panic!(
+                "MLIR codegen encountered an error: struct {} has no fields",
+                struct_ast.name
+            );
+        }
+
+        let element_types = struct_ast
+            .fields
+            .iter()
+            .map(|field| self.get_var_type(&field.var_type))
+            .collect();
+        let struct_type = get_struct_type(&self.context, element_types);
+        self.structs
+            .insert(struct_ast.name.clone(), (struct_type, struct_ast));
+    }
+
     fn mlir_gen_function(&mut self, function_ast: Function) -> FuncOp {
         let function: FuncOp = self.mlir_gen_prototype(function_ast.prototype.clone());
 
+        // NB: every function has its own scope
+        self.symbol_table.clear();
+        self.var_structs.clear();
+        self.result_element_type =
+            self.get_element_type(function_ast.prototype.return_type.element_type);
+
         let entry_block = function.block.clone();
         let proto_args = function_ast.prototype.args.clone();
+        for (arg, arg_type) in proto_args.iter().zip(&function_ast.prototype.arg_types) {
+            if let Some(struct_name) = &arg_type.struct_name {
+                self.var_structs.insert(arg.clone(), struct_name.clone());
+            }
+        }
         let mut pos = 0;
         for arg in proto_args.clone() {
             // TODO: unsafe code
@@ -66,22 +154,27 @@ impl<'ctx> MLIRGen {
         self.builder
             .set_insertion_point(Rc::clone(&function.block), 0);
 
-        let _ = self.mlir_gen_expression(function_ast.body.unwrap());
+        if let Err(error) = self.mlir_gen_expression(function_ast.body.unwrap()) {
+            panic!("MLIR codegen encountered an error: {}", error);
+        }
 
         if function_ast.prototype.name != String::from("main") {
             function.set_private();
         }
         let last_operation = entry_block.back();
         if last_operation.name() != String::from("toy.return") {
-            // TODO: construct location from AST location
-            let location = Location::new(Rc::clone(&self.context));
+            let location = self.loc(&function_ast.location);
             let op = ReturnOpBuilder::new(location).build();
             self.builder.insert(op.clone());
-        } else if last_operation.num_operands() != 0 {
-            let arg_types = vec![self.get_type(Vec::new()); proto_args.len()];
-            let elem_type = self.builder.get_f64_type();
-            let result_type = self.builder.get_unranked_tensor_type(elem_type);
-            let func_type = self.builder.get_function_type(arg_types, vec![result_type]);
+        } else if last_operation.num_operands() != 0
+            && function_ast.prototype.return_type.shape.is_empty()
+        {
+            let arg_types = self.get_arg_types(&function_ast.prototype);
+            // NB: the function returns whatever its return operation returns, e.g. a struct
+            let result_types = (0..last_operation.num_operands())
+                .map(|pos| last_operation.operand(pos).get_type())
+                .collect();
+            let func_type = self.builder.get_function_type(arg_types, result_types);
             function.set_type(&func_type);
         }
 
@@ -89,11 +182,15 @@ impl<'ctx> MLIRGen {
     }
 
     fn mlir_gen_prototype(&mut self, prototype_ast: Prototype) -> FuncOp {
-        // TODO: construct location from AST location
-        let location = Location::new(Rc::clone(&self.context));
-        let arg_types = vec![self.get_type(Vec::new()); prototype_ast.args.len()];
-        // NB: by default expect that there is no return value
-        let func_type = self.builder.get_function_type(arg_types, Vec::new());
+        let location = self.loc(&prototype_ast.location);
+        let arg_types = self.get_arg_types(&prototype_ast);
+        // NB: by default expect that there is no return value, unless one is declared
+        let result_types = if prototype_ast.return_type.shape.is_empty() {
+            Vec::new()
+        } else {
+            vec![self.get_var_type(&prototype_ast.return_type)]
+        };
+        let func_type = self.builder.get_function_type(arg_types, result_types);
         // NB: only main function is exported to outside
         let exported = if prototype_ast.name == String::from("main") {
             true
@@ -104,6 +201,16 @@ impl<'ctx> MLIRGen {
         FuncOp::new(location, &prototype_ast.name, func_type, exported)
     }
 
+    /// Converts a location of the Toy source into an MLIR one.
+    fn loc(&self, location: &parser::Location) -> Location {
+        Location::new_file_line_col(
+            Rc::clone(&self.context),
+            &location.filename,
+            location.line,
+            location.col,
+        )
+    }
+
     fn declare(&mut self, name: String, value: Value) {
         self.symbol_table.insert(name, value);
     }
@@ -113,21 +220,52 @@ impl<'ctx> MLIRGen {
         // there should be a way to avoid this
         let clone_expr = expr.clone();
         match expr {
-            ExprList { expressions } => {
+            ExprList { expressions, .. } => {
                 for expr in expressions {
-                    let _value = self.mlir_gen_expression(*expr.clone());
+                    self.mlir_gen_expression(*expr)?;
                 }
-                Err("ExprList not implemented")
+                // NB: a block of statements has no value
+                Ok(Value::null())
             }
             VarDecl {
+                location,
                 name,
                 var_type,
                 value,
             } => {
-                let mut value = self.mlir_gen_expression(*value).unwrap();
-                if !var_type.shape.is_empty() {
-                    let location = Location::new(Rc::clone(&self.context));
-                    let var_type = self.get_type(var_type.shape);
+                let element_type = self.get_element_type(var_type.element_type);
+                let mut value = match value {
+                    Some(value) if var_type.element_type.is_some() => {
+                        self.mlir_gen_typed(*value, element_type).unwrap()
+                    }
+                    Some(value) => self.mlir_gen_expression(*value).unwrap(),
+                    // NB: a declaration without initializer is a tensor of zeros of its shape
+                    None => {
+                        let data_ty = self
+                            .builder
+                            .get_ranked_tensor_type(var_type.shape, element_type);
+                        let data_attr = self
+                            .builder
+                            .get_dense_elements_attr_splat(data_ty.clone(), 0.0);
+                        let op = ConstantOpBuilder::new(self.loc(&location))
+                            .result(data_ty)
+                            .attribute(data_attr)
+                            .build();
+                        self.builder.insert(op.clone());
+                        self.declare(name, Value::from(op.clone()));
+                        return Ok(Value::from(op));
+                    }
+                };
+                if let Some(struct_name) = &var_type.struct_name {
+                    if !self.structs.contains_key(struct_name) {
+                        return Err("Unknown struct type");
+                    }
+                    self.var_structs.insert(name.clone(), struct_name.clone());
+                } else if !var_type.shape.is_empty() {
+                    let location = self.loc(&location);
+                    let var_type = self
+                        .builder
+                        .get_ranked_tensor_type(var_type.shape, value.get_type().element_type());
                     let op = ReshapeOpBuilder::new(location)
                         .result(var_type)
                         .input(value.clone())
@@ -139,7 +277,7 @@ impl<'ctx> MLIRGen {
                 self.declare(name, value.clone());
                 Ok(value)
             }
-            Variable(name) => {
+            Variable { name, .. } => {
                 if self.symbol_table.contains_key(&name) {
                     let value = (*self.symbol_table.get(&name).unwrap()).clone();
                     return Ok(value);
@@ -148,7 +286,7 @@ impl<'ctx> MLIRGen {
                 // extract variable from symbol table
             }
             Tensor {
-                location: _,
+                location,
                 values: _, // read by collect_data method
                 dims,
             } => {
@@ -157,23 +295,25 @@ impl<'ctx> MLIRGen {
                 data.reserve(size);
                 self.collect_data(clone_expr, &mut data);
 
-                let elem_ty = self.builder.get_f64_type();
+                let elem_ty = self.literal_type.clone();
                 let data_ty = self.builder.get_ranked_tensor_type(dims, elem_ty);
                 let data_attr: Attribute =
                     self.builder.get_dense_elements_attr(data_ty.clone(), data);
-                let op = ConstantOpBuilder::new(Location::new(Rc::clone(&self.context)))
+                let op = ConstantOpBuilder::new(self.loc(&location))
                     .result(data_ty)
                     .attribute(data_attr)
                     .build();
                 self.builder.insert(op.clone());
                 Ok(Value::from(op))
             }
-            Number(num) => {
-                let location = Location::new(Rc::clone(&self.context));
-                // FIXME: consider constant as a tensor with shape 1
-                // otherwise, getting a conversion error
-                let elem_ty = self.builder.get_f64_type();
-                let elem_ty = self.builder.get_ranked_tensor_type(vec![1], elem_ty);
+            Number {
+                location,
+                value: num,
+            } => {
+                let location = self.loc(&location);
+                // A number is a 0-d tensor, broadcast by the element-wise operations
+                let elem_ty = self.literal_type.clone();
+                let elem_ty = self.builder.get_ranked_tensor_type(Vec::new(), elem_ty);
                 let elem_attr: Attribute = self
                     .builder
                     .get_dense_elements_attr(elem_ty.clone(), vec![num]);
@@ -186,66 +326,174 @@ impl<'ctx> MLIRGen {
                 self.builder.insert(op.clone());
                 Ok(Value::from(op))
             }
-            Call { fn_name, args } => {
-                let location = Location::new(Rc::clone(&self.context));
-                let mut operands: Vec<Value> = Vec::new();
-                for arg in &args {
-                    let arg = self.mlir_gen_expression(arg.clone()).unwrap();
-                    operands.push(arg);
+            Call {
+                location,
+                fn_name,
+                args,
+            } => {
+                let location = self.loc(&location);
+                if TENSOR_CONSTRUCTORS.contains(&fn_name.as_str()) {
+                    return self.mlir_gen_constructor(location, &fn_name, &args);
                 }
+                if REDUCTIONS.contains(&fn_name.as_str()) {
+                    return self.mlir_gen_reduction(location, &fn_name, &args);
+                }
+                let operands = match (self.param_types.get(&fn_name).cloned(), &args[..]) {
+                    (Some(param_types), _) => {
+                        self.mlir_gen_arguments(args.clone(), &param_types)?
+                    }
+                    (None, [lhs, rhs]) if fn_name == "matmul" => {
+                        let (lhs, rhs) = self.mlir_gen_operands(lhs.clone(), rhs.clone())?;
+                        vec![lhs, rhs]
+                    }
+                    (None, _) => {
+                        let mut operands: Vec<Value> = Vec::new();
+                        for arg in &args {
+                            let arg = self.mlir_gen_expression(arg.clone()).unwrap();
+                            operands.push(arg);
+                        }
+                        operands
+                    }
+                };
                 if fn_name == "transpose" {
                     if args.len() != 1 {
                         panic!("MLIR codegen encountered an error: toy.transpose does not accept multiple args");
                     }
+                    let result_type = self.get_result_like(&operands[0]);
                     let op = TransposeOpBuilder::new(location)
                         .input(operands[0].clone())
-                        .result(
-                            self.builder
-                                .get_unranked_tensor_type(self.builder.get_f64_type()),
-                        )
+                        .result(result_type)
                         .build();
                     self.builder.insert(op.clone());
                     let value = Value::from(op);
 
                     return Ok(value);
                 }
+                if MATH_FUNCTIONS.contains(&fn_name.as_str()) {
+                    if args.len() != 1 {
+                        panic!(
+                            "MLIR codegen encountered an error: toy.{} expects a single arg",
+                            fn_name
+                        );
+                    }
+                    return Ok(self.mlir_gen_math(location, &fn_name, operands[0].clone()));
+                }
+                if fn_name == "matmul" {
+                    if args.len() != 2 {
+                        panic!("MLIR codegen encountered an error: toy.matmul expects two args");
+                    }
+                    let result_type = self.get_result_like(&operands[0]);
+                    let op = MatMulOpBuilder::new(location)
+                        .operands(operands[0].clone(), operands[1].clone())
+                        .result(result_type)
+                        .build();
+                    self.builder.insert(op.clone());
+                    return Ok(Value::from(op));
+                }
 
-                let result_type = self
-                    .builder
-                    .get_unranked_tensor_type(self.builder.get_f64_type());
-                let op = GenericCallOpBuilder::new(location)
-                    .callee(&fn_name)
-                    .operands(operands)
-                    .result(result_type)
-                    .build();
-                self.builder.insert(op.clone());
-                let value = Value::from(op);
-                Ok(value)
+                let op = self.mlir_gen_generic_call(location, &fn_name, operands);
+                Ok(Value::from(op))
+            }
+            // NB: the values are taken from the results of the call, one per name
+            Destructure { names, value, .. } => {
+                let values: Vec<Value> = match *value {
+                    Call {
+                        location,
+                        fn_name,
+                        args,
+                    } if self.param_types.contains_key(&fn_name) => {
+                        let location = self.loc(&location);
+                        let param_types = self.param_types[&fn_name].clone();
+                        let operands = self.mlir_gen_arguments(args, &param_types)?;
+                        let op = self.mlir_gen_generic_call(location, &fn_name, operands);
+                        (0..names.len()).map(|pos| op.result(pos)).collect()
+                    }
+                    value => vec![self.mlir_gen_expression(value)?],
+                };
+                for (name, value) in names.into_iter().zip(values.clone()) {
+                    self.declare(name, value);
+                }
+                values.into_iter().next().ok_or("Nothing to destructure")
             }
+            Tuple { .. } => Err("Tuples can only be returned"),
             Return {
-                location: _,
+                location,
                 expression,
             } => {
-                let location = Location::new(Rc::clone(&self.context));
-                if let Some(expr) = expression {
-                    let value = self.mlir_gen_expression(*expr).unwrap();
-                    let op = ReturnOpBuilder::new(location).input(value).build();
-                    self.builder.insert(op.clone());
-                    return Ok(Value::from(op));
-                } else {
-                    let op = ReturnOpBuilder::new(location).build();
-                    self.builder.insert(op.clone());
-                    return Ok(Value::from(op));
+                if self.region_depth > 0 {
+                    return Err("Return is not allowed inside if and for");
+                }
+                let location = self.loc(&location);
+                // NB: a tuple returns each of its values
+                let values = match expression.map(|expr| *expr) {
+                    Some(Tuple { values, .. }) => values,
+                    Some(expr) => vec![expr],
+                    None => Vec::new(),
+                };
+                let mut inputs = Vec::new();
+                for value in values {
+                    let element_type = self.result_element_type.clone();
+                    inputs.push(self.mlir_gen_typed(value, element_type)?);
                 }
+                let op = ReturnOpBuilder::new(location).inputs(inputs).build();
+                self.builder.insert(op.clone());
+                Ok(Value::from(op))
             }
 
-            Binary { op, left, right } => {
-                let lhs = self.mlir_gen_expression(*left).unwrap();
-                let rhs = self.mlir_gen_expression(*right).unwrap();
-                let result_type = self
-                    .builder
-                    .get_unranked_tensor_type(self.builder.get_f64_type());
-                let location = Location::new(Rc::clone(&self.context));
+            // NB: assignments rebind the variable to the new value
+            Binary {
+                op: '=',
+                left,
+                right,
+                ..
+            } => {
+                let name = match *left {
+                    Variable { name, .. } => name,
+                    _ => return Err("Left-hand side of '=' must be a variable"),
+                };
+                let value = match self.symbol_table.get(&name) {
+                    Some(_) if self.var_structs.contains_key(&name) => {
+                        self.mlir_gen_expression(*right)?
+                    }
+                    // NB: the new value holds elements of the same type as the previous one
+                    Some(previous) => {
+                        let element_type = previous.get_type().element_type();
+                        self.mlir_gen_typed(*right, element_type)?
+                    }
+                    None => return Err("Variable is not found"),
+                };
+                self.declare(name, value.clone());
+                Ok(value)
+            }
+
+            // NB: custom operators are calls of the function defining them
+            Binary {
+                location,
+                op,
+                left,
+                right,
+            } if !parser::BUILTIN_BINARY_OPS.contains(&op) => {
+                let callee = format!("binary{}", op);
+                let param_types = self.param_types.get(&callee).cloned().unwrap_or_default();
+                let operands = self.mlir_gen_arguments(vec![*left, *right], &param_types)?;
+                let op = GenericCallOpBuilder::new(self.loc(&location))
+                    .callee(&callee)
+                    .operands(operands)
+                    .result(self.get_result_type(&callee))
+                    .build();
+                self.builder.insert(op.clone());
+                Ok(Value::from(op))
+            }
+
+            Binary {
+                location,
+                op,
+                left,
+                right,
+            } => {
+                let (lhs, rhs) = self.mlir_gen_operands(*left, *right)?;
+                let result_type = self.get_result_like(&lhs);
+                let location = self.loc(&location);
                 match op {
                     '+' => {
                         let op = AddOpBuilder::new(location)
@@ -255,6 +503,14 @@ impl<'ctx> MLIRGen {
                         self.builder.insert(op.clone());
                         Ok(Value::from(op))
                     }
+                    '-' => {
+                        let op = SubOpBuilder::new(location)
+                            .operands(lhs, rhs)
+                            .result(result_type)
+                            .build();
+                        self.builder.insert(op.clone());
+                        Ok(Value::from(op))
+                    }
                     '*' => {
                         let op = MulOpBuilder::new(location)
                             .operands(lhs, rhs)
@@ -263,20 +519,525 @@ impl<'ctx> MLIRGen {
                         self.builder.insert(op.clone());
                         Ok(Value::from(op))
                     }
-                    _ => Err("Invalid binary operation"),
+                    '/' => {
+                        let op = DivOpBuilder::new(location)
+                            .operands(lhs, rhs)
+                            .result(result_type)
+                            .build();
+                        self.builder.insert(op.clone());
+                        Ok(Value::from(op))
+                    }
+                    _ => {
+                        let op = LtOpBuilder::new(location)
+                            .operands(lhs, rhs)
+                            .result(result_type)
+                            .build();
+                        self.builder.insert(op.clone());
+                        Ok(Value::from(op))
+                    }
                 }
             }
 
+            Unary {
+                location,
+                op,
+                operand,
+            } => {
+                let callee = format!("unary{}", op);
+                let param_types = self.param_types.get(&callee).cloned().unwrap_or_default();
+                let operands = self.mlir_gen_arguments(vec![*operand], &param_types)?;
+                let op = GenericCallOpBuilder::new(self.loc(&location))
+                    .callee(&callee)
+                    .operands(operands)
+                    .result(self.get_result_type(&callee))
+                    .build();
+                self.builder.insert(op.clone());
+                Ok(Value::from(op))
+            }
+
             Print {
-                location: _,
+                location,
                 expression,
             } => {
-                let location = Location::new(Rc::clone(&self.context));
+                let location = self.loc(&location);
                 let value = self.mlir_gen_expression(*expression).unwrap();
                 let op = PrintOpBuilder::new(location).input(value).build();
                 self.builder.insert(op.clone());
                 Ok(Value::from(op))
             }
+
+            StructLiteral { location, .. } => {
+                let (data_attr, data_ty) = self.get_constant_attr(&clone_expr)?;
+                let op = StructConstantOpBuilder::new(self.loc(&location))
+                    .result(data_ty)
+                    .attribute(data_attr)
+                    .build();
+                self.builder.insert(op.clone());
+                Ok(Value::from(op))
+            }
+
+            MemberAccess {
+                location,
+                value,
+                field,
+                ..
+            } => {
+                let struct_ast = self
+                    .struct_for(&value)
+                    .ok_or("Member access into a value that is not a struct")?;
+                let index = struct_ast
+                    .fields
+                    .iter()
+                    .position(|f| f.name == field)
+                    .ok_or("Unknown struct field")?;
+                let field_type = struct_ast.fields[index].var_type.clone();
+
+                let result_type = self.get_var_type(&field_type);
+                let value = self.mlir_gen_expression(*value)?;
+                let op = StructAccessOpBuilder::new(self.loc(&location))
+                    .input(value)
+                    .index(index)
+                    .result(result_type)
+                    .build();
+                self.builder.insert(op.clone());
+                Ok(Value::from(op))
+            }
+
+            Index {
+                location,
+                value,
+                subscripts,
+            } => {
+                let location = self.loc(&location);
+                let mut value = self.mlir_gen_expression(*value)?;
+
+                // NB: the ranges are taken first, keeping the rank, then the indices drop
+                // their dimensions
+                let is_slice = |subscript: &Subscript| matches!(subscript, Subscript::Slice { start, end } if start.is_some() || end.is_some());
+                if subscripts.iter().any(is_slice) {
+                    let (starts, ends) = subscripts
+                        .iter()
+                        .map(|subscript| match subscript {
+                            Subscript::Slice { start, end } => {
+                                (start.unwrap_or(0) as i64, end.map_or(-1, |end| end as i64))
+                            }
+                            Subscript::Index(_) => (0, -1),
+                        })
+                        .unzip();
+                    let result_type = self.get_result_like(&value);
+                    let op = SliceOpBuilder::new(location.clone())
+                        .input(value)
+                        .bounds(starts, ends)
+                        .result(result_type)
+                        .build();
+                    self.builder.insert(op.clone());
+                    value = Value::from(op);
+                }
+
+                let mut dims = Vec::new();
+                let mut indices = Vec::new();
+                for (dim, subscript) in subscripts.into_iter().enumerate() {
+                    if let Subscript::Index(index) = subscript {
+                        dims.push(dim);
+                        // NB: indices are f64, like the induction variables of loops
+                        let index_type = self.builder.get_f64_type();
+                        indices.push(self.mlir_gen_typed(index, index_type)?);
+                    }
+                }
+                if !indices.is_empty() {
+                    let result_type = self.get_result_like(&value);
+                    let op = ExtractOpBuilder::new(location)
+                        .operands(value, indices)
+                        .dims(dims)
+                        .result(result_type)
+                        .build();
+                    self.builder.insert(op.clone());
+                    value = Value::from(op);
+                }
+                Ok(value)
+            }
+
+            If {
+                location,
+                condition,
+                then_block,
+                else_block,
+            } => {
+                let location = self.loc(&location);
+                let condition = self.mlir_gen_expression(*condition)?;
+                let mut branches = vec![*then_block];
+                branches.extend(else_block.map(|block| *block));
+
+                let carried = self.carried_variables(&branches);
+                let carried_values: Vec<Value> = carried
+                    .iter()
+                    .map(|name| self.symbol_table[name].clone())
+                    .collect();
+                let result_types = carried_values.iter().map(Value::get_type).collect();
+
+                let then_region = Region::new();
+                let then_entry = Block::new(Vec::new(), &location);
+                then_region.append_owned_block(&then_entry);
+                let else_region = Region::new();
+                let else_entry = Block::new(Vec::new(), &location);
+                else_region.append_owned_block(&else_entry);
+
+                let op = IfOpBuilder::new(location.clone())
+                    .condition(condition)
+                    .carried(carried_values)
+                    .results(result_types)
+                    .regions(then_region, else_region)
+                    .build();
+                self.builder.insert(op.clone());
+
+                let mut branches = branches.into_iter();
+                self.mlir_gen_region(then_entry, branches.next(), Vec::new(), &carried, &location)?;
+                self.mlir_gen_region(else_entry, branches.next(), Vec::new(), &carried, &location)?;
+
+                for (pos, name) in carried.into_iter().enumerate() {
+                    self.declare(name, op.result(pos));
+                }
+                Ok(Value::from(op))
+            }
+
+            For {
+                location,
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let location = self.loc(&location);
+                let carried: Vec<String> = self
+                    .carried_variables(std::slice::from_ref(&body))
+                    .into_iter()
+                    .filter(|name| *name != var)
+                    .collect();
+                let init_args: Vec<Value> = carried
+                    .iter()
+                    .map(|name| self.symbol_table[name].clone())
+                    .collect();
+                let result_types: Vec<Type> = init_args.iter().map(Value::get_type).collect();
+
+                // NB: the induction variable is given to the body as a 0-d tensor, like numbers are
+                let mut arg_types = vec![self
+                    .builder
+                    .get_ranked_tensor_type(Vec::new(), self.builder.get_f64_type())];
+                arg_types.extend(result_types.iter().cloned());
+                let region = Region::new();
+                let entry = Block::new(arg_types, &location);
+                region.append_owned_block(&entry);
+
+                let op = ForOpBuilder::new(location.clone())
+                    .bounds(start, end)
+                    .init_args(init_args)
+                    .results(result_types)
+                    .body(region)
+                    .build();
+                self.builder.insert(op.clone());
+
+                let mut bindings = vec![(var, entry.argument(0))];
+                for (pos, name) in carried.iter().enumerate() {
+                    bindings.push((name.clone(), entry.argument(pos + 1)));
+                }
+                self.mlir_gen_region(entry, Some(*body), bindings, &carried, &location)?;
+
+                for (pos, name) in carried.into_iter().enumerate() {
+                    self.declare(name, op.result(pos));
+                }
+                Ok(Value::from(op))
+            }
+        }
+    }
+
+    /// Generates `body` into `block`, the entry block of a region of toy.if or toy.for,
+    /// with `bindings` declared, and terminates it with a toy.yield of the values of the
+    /// `carried` variables. Variables declared by the body are local to it.
+    fn mlir_gen_region(
+        &mut self,
+        block: Block,
+        body: Option<Expr>,
+        bindings: Vec<(String, Value)>,
+        carried: &[String],
+        location: &Location,
+    ) -> Result<(), &'static str> {
+        let symbol_table = self.symbol_table.clone();
+        let var_structs = self.var_structs.clone();
+        let (parent_block, parent_pos) = self.builder.insertion_point();
+        self.builder.set_insertion_point(Rc::new(block), 0);
+        for (name, value) in bindings {
+            self.declare(name, value);
+        }
+
+        self.region_depth += 1;
+        let result = match body {
+            Some(body) => self.mlir_gen_expression(body).map(|_| ()),
+            None => Ok(()),
+        };
+        self.region_depth -= 1;
+        result?;
+
+        let values = carried
+            .iter()
+            .map(|name| self.symbol_table[name].clone())
+            .collect();
+        let op = YieldOpBuilder::new(location.clone()).inputs(values).build();
+        self.builder.insert(op);
+
+        self.symbol_table = symbol_table;
+        self.var_structs = var_structs;
+        self.builder.set_insertion_point(parent_block, parent_pos);
+        Ok(())
+    }
+
+    /// Returns the variables of the current scope that are assigned to in `blocks`, which
+    /// therefore have to be carried in and out of the regions generated for them.
+    fn carried_variables(&self, blocks: &[Expr]) -> Vec<String> {
+        let mut collector = AssignmentCollector::default();
+        for block in blocks {
+            collector.visit_expr(block);
+        }
+
+        let declared = collector.declared;
+        collector
+            .assigned
+            .into_iter()
+            .filter(|name| !declared.contains(name) && self.symbol_table.contains_key(name))
+            .collect()
+    }
+
+    /// Returns the declaration of the struct `expr` evaluates to, if it is a struct.
+    fn struct_for(&self, expr: &Expr) -> Option<&parser::Struct> {
+        let struct_name = match expr {
+            Variable { name, .. } => self.var_structs.get(name)?,
+            MemberAccess { value, field, .. } => {
+                let parent = self.struct_for(value)?;
+                let field = parent.fields.iter().find(|f| &f.name == field)?;
+                field.var_type.struct_name.as_ref()?
+            }
+            _ => return None,
+        };
+
+        self.structs
+            .get(struct_name)
+            .map(|(_, struct_ast)| struct_ast)
+    }
+
+    /// Generates `expr`, its literals holding elements of `element_type`.
+    fn mlir_gen_typed(&mut self, expr: Expr, element_type: Type) -> Result<Value, &'static str> {
+        let literal_type = std::mem::replace(&mut self.literal_type, element_type);
+        let value = self.mlir_gen_expression(expr);
+        self.literal_type = literal_type;
+        value
+    }
+
+    /// Generates the operands of an element-wise operation, the literal one, if any, holding
+    /// elements of the type of the other.
+    fn mlir_gen_operands(
+        &mut self,
+        left: Expr,
+        right: Expr,
+    ) -> Result<(Value, Value), &'static str> {
+        if is_literal(&left) && !is_literal(&right) {
+            let rhs = self.mlir_gen_expression(right)?;
+            let lhs = self.mlir_gen_typed(left, rhs.get_type().element_type())?;
+            Ok((lhs, rhs))
+        } else {
+            let lhs = self.mlir_gen_expression(left)?;
+            let rhs = self.mlir_gen_typed(right, lhs.get_type().element_type())?;
+            Ok((lhs, rhs))
+        }
+    }
+
+    /// Generates a call to the user-defined function `fn_name`, with a result per value it
+    /// returns.
+    fn mlir_gen_generic_call(
+        &mut self,
+        location: Location,
+        fn_name: &str,
+        operands: Vec<Value>,
+    ) -> Operation {
+        let count = self.result_counts.get(fn_name).copied().unwrap_or(1);
+        let result_types = vec![self.get_result_type(fn_name); count.max(1)];
+        let op = GenericCallOpBuilder::new(location)
+            .callee(fn_name)
+            .operands(operands)
+            .results(result_types)
+            .build();
+        self.builder.insert(op.clone());
+        op
+    }
+
+    /// Generates the arguments of a call to a user-defined function, given the types of its
+    /// parameters, the literal ones holding elements of the type of their parameter.
+    fn mlir_gen_arguments(
+        &mut self,
+        args: Vec<Expr>,
+        param_types: &[VarType],
+    ) -> Result<Vec<Value>, &'static str> {
+        let mut operands = Vec::new();
+        for (pos, arg) in args.into_iter().enumerate() {
+            let operand = match param_types.get(pos) {
+                Some(param_type) if param_type.struct_name.is_none() => {
+                    let element_type = self.get_element_type(param_type.element_type);
+                    self.mlir_gen_typed(arg, element_type)?
+                }
+                _ => self.mlir_gen_expression(arg)?,
+            };
+            operands.push(operand);
+        }
+        Ok(operands)
+    }
+
+    /// Emits the math function `fn_name` applied to each element of `input`.
+    fn mlir_gen_math(&mut self, location: Location, fn_name: &str, input: Value) -> Value {
+        let result_type = self.get_result_like(&input);
+        let op = match fn_name {
+            "abs" => AbsOpBuilder::new(location)
+                .input(input)
+                .result(result_type)
+                .build(),
+            "exp" => ExpOpBuilder::new(location)
+                .input(input)
+                .result(result_type)
+                .build(),
+            "log" => LogOpBuilder::new(location)
+                .input(input)
+                .result(result_type)
+                .build(),
+            "neg" => NegOpBuilder::new(location)
+                .input(input)
+                .result(result_type)
+                .build(),
+            "sqrt" => SqrtOpBuilder::new(location)
+                .input(input)
+                .result(result_type)
+                .build(),
+            _ => TanhOpBuilder::new(location)
+                .input(input)
+                .result(result_type)
+                .build(),
+        };
+        self.builder.insert(op.clone());
+        Value::from(op)
+    }
+
+    /// Emits the reduction `fn_name` of its first argument along the axis given by the
+    /// second one.
+    fn mlir_gen_reduction(
+        &mut self,
+        location: Location,
+        fn_name: &str,
+        args: &[Expr],
+    ) -> Result<Value, &'static str> {
+        let axis = reduction_axis(args).ok_or("The axis of a reduction must be a literal")?;
+        let input = self.mlir_gen_expression(args[0].clone())?;
+        let result_type = self.get_result_like(&input);
+        let op = match fn_name {
+            "max" => MaxOpBuilder::new(location)
+                .input(input)
+                .axis(axis)
+                .result(result_type)
+                .build(),
+            "mean" => MeanOpBuilder::new(location)
+                .input(input)
+                .axis(axis)
+                .result(result_type)
+                .build(),
+            _ => SumOpBuilder::new(location)
+                .input(input)
+                .axis(axis)
+                .result(result_type)
+                .build(),
+        };
+        self.builder.insert(op.clone());
+        Ok(Value::from(op))
+    }
+
+    /// Emits the constant built by the tensor constructor `fn_name`, as a splat for those
+    /// holding a single value.
+    fn mlir_gen_constructor(
+        &mut self,
+        location: Location,
+        fn_name: &str,
+        args: &[Expr],
+    ) -> Result<Value, &'static str> {
+        let shape =
+            constructor_shape(fn_name, args).ok_or("Invalid arguments of a tensor constructor")?;
+        let data_ty = self
+            .builder
+            .get_ranked_tensor_type(shape.clone(), self.literal_type.clone());
+        let data_attr = match fn_name {
+            "range" => {
+                let data = (0..shape[0]).map(|i| i as f64).collect();
+                self.builder.get_dense_elements_attr(data_ty.clone(), data)
+            }
+            "eye" => {
+                // NB: the diagonal holds every (n + 1)-th element of a row-major n x n matrix
+                let n = shape[0];
+                let data = (0..n * n)
+                    .map(|i| if i % (n + 1) == 0 { 1.0 } else { 0.0 })
+                    .collect();
+                self.builder.get_dense_elements_attr(data_ty.clone(), data)
+            }
+            "fill" => match &args[0] {
+                Number { value, .. } => self
+                    .builder
+                    .get_dense_elements_attr_splat(data_ty.clone(), *value),
+                _ => return Err("The value of fill must be a literal number"),
+            },
+            "ones" => self
+                .builder
+                .get_dense_elements_attr_splat(data_ty.clone(), 1.0),
+            _ => self
+                .builder
+                .get_dense_elements_attr_splat(data_ty.clone(), 0.0),
+        };
+
+        let op = ConstantOpBuilder::new(location)
+            .result(data_ty)
+            .attribute(data_attr)
+            .build();
+        self.builder.insert(op.clone());
+        Ok(Value::from(op))
+    }
+
+    /// Builds the attribute holding the value of a constant struct literal, along with its type.
+    /// Tensor fields are typed as unranked tensors, like struct fields are.
+    fn get_constant_attr(&self, expr: &Expr) -> Result<(Attribute, Type), &'static str> {
+        match expr {
+            StructLiteral { values, .. } => {
+                let mut attrs = Vec::new();
+                let mut types = Vec::new();
+                for value in values {
+                    let (attr, ty) = self.get_constant_attr(value)?;
+                    attrs.push(attr);
+                    types.push(ty);
+                }
+                let attr = Attribute::new_array(&self.context, attrs);
+
+                Ok((attr, get_struct_type(&self.context, types)))
+            }
+            Tensor { dims, .. } => {
+                let mut data: Vec<f64> = Vec::new();
+                self.collect_data(expr.clone(), &mut data);
+
+                let data_ty = self
+                    .builder
+                    .get_ranked_tensor_type(dims.clone(), self.builder.get_f64_type());
+                let attr = self.builder.get_dense_elements_attr(data_ty, data);
+
+                Ok((attr, self.get_type(Vec::new())))
+            }
+            Number { value, .. } => {
+                let data_ty = self
+                    .builder
+                    .get_ranked_tensor_type(Vec::new(), self.builder.get_f64_type());
+                let attr = self.builder.get_dense_elements_attr(data_ty, vec![*value]);
+
+                Ok((attr, self.get_type(Vec::new())))
+            }
+            _ => Err("Struct literals can only contain constants"),
         }
     }
 
@@ -291,8 +1052,8 @@ impl<'ctx> MLIRGen {
                     self.collect_data(v.clone(), data);
                 }
             }
-            Number(num) => {
-                data.push(num);
+            Number { value, .. } => {
+                data.push(value);
             }
             _ => {
                 panic!("Unexpected expression");
@@ -300,6 +1061,57 @@ impl<'ctx> MLIRGen {
         }
     }
 
+    fn get_var_type(&self, var_type: &VarType) -> Type {
+        match &var_type.struct_name {
+            Some(name) => match self.structs.get(name) {
+                Some((struct_type, _)) => struct_type.clone(),
+                None => panic!(
+                    "MLIR codegen encountered an error: unknown struct type {}",
+                    name
+                ),
+            },
+            None if var_type.shape.is_empty() => self
+                .builder
+                .get_unranked_tensor_type(self.get_element_type(var_type.element_type)),
+            None => self.builder.get_ranked_tensor_type(
+                var_type.shape.clone(),
+                self.get_element_type(var_type.element_type),
+            ),
+        }
+    }
+
+    fn get_arg_types(&self, prototype_ast: &Prototype) -> Vec<Type> {
+        prototype_ast
+            .arg_types
+            .iter()
+            .map(|arg_type| self.get_var_type(arg_type))
+            .collect()
+    }
+
+    /// Returns the type of the result of a call to `callee`, unranked unless it declares one.
+    fn get_result_type(&self, callee: &str) -> Type {
+        match self.result_types.get(callee) {
+            Some(var_type) => self.get_var_type(var_type),
+            None => self.get_type(Vec::new()),
+        }
+    }
+
+    /// Returns the type of the result of an element-wise operation on `operand`: an unranked
+    /// tensor of its element type, refined by shape inference.
+    fn get_result_like(&self, operand: &Value) -> Type {
+        self.builder
+            .get_unranked_tensor_type(operand.get_type().element_type())
+    }
+
+    /// Returns the MLIR type of elements of `element_type`, f64 unless one is declared.
+    fn get_element_type(&self, element_type: Option<ElementType>) -> Type {
+        match element_type.unwrap_or(ElementType::F64) {
+            ElementType::F64 => self.builder.get_f64_type(),
+            ElementType::F32 => self.builder.get_f32_type(),
+            ElementType::I64 => self.builder.get_i64_type(),
+        }
+    }
+
     fn get_type(&self, shape: Vec<usize>) -> Type {
         if shape.is_empty() {
             return self
@@ -311,3 +1123,43 @@ impl<'ctx> MLIRGen {
             .get_ranked_tensor_type(shape, self.builder.get_f64_type())
     }
 }
+
+/// Collects the names of the variables an expression assigns to, and of the ones it
+/// declares, in order of appearance.
+#[derive(Default)]
+struct AssignmentCollector {
+    assigned: Vec<String>,
+    declared: Vec<String>,
+}
+
+impl Visitor for AssignmentCollector {
+    fn visit_binary(&mut self, _location: &parser::Location, op: char, left: &Expr, right: &Expr) {
+        match left {
+            Variable { name, .. } if op == '=' => {
+                if !self.assigned.contains(name) {
+                    self.assigned.push(name.clone());
+                }
+            }
+            _ => self.visit_expr(left),
+        }
+        self.visit_expr(right);
+    }
+
+    fn visit_var_decl(
+        &mut self,
+        _location: &parser::Location,
+        name: &str,
+        _var_type: &VarType,
+        value: Option<&Expr>,
+    ) {
+        self.declared.push(name.to_string());
+        if let Some(value) = value {
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_for(&mut self, _location: &parser::Location, var: &str, _: i64, _: i64, body: &Expr) {
+        self.declared.push(var.to_string());
+        self.visit_expr(body);
+    }
+}
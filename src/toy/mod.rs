@@ -1,4 +1,11 @@
+pub mod ast_dump;
+pub mod diagnostic;
 pub mod ffi;
+pub mod format;
+pub mod loader;
 pub mod mlir_gen;
 pub mod parser;
+pub mod sema;
+pub mod shapes;
 pub mod toy_dialect;
+pub mod visit;
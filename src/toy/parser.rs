@@ -1,9 +1,12 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::iter::Peekable;
-use std::ops::DerefMut;
+use std::ops::{DerefMut, Range};
 use std::str::Chars;
 
+use crate::toy::diagnostic::Diagnostic;
 use crate::toy::parser::Token::*;
+use crate::toy::visit::Visitor;
 
 // ======================================================================================
 // LEXER ================================================================================
@@ -33,16 +36,114 @@ pub enum Token {
     AngleRight,
     Return,
     Print,
+    Struct,
+    If,
+    Else,
+    For,
+    In,
+    DotDot,
+    Import,
+    /// A string literal, without its quotes.
+    Str(String),
+    /// `->` introduces the result type of a function.
+    Arrow,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binary => write!(f, "'binary'"),
+            Comma => write!(f, "','"),
+            Comment => write!(f, "comment"),
+            Def => write!(f, "'def'"),
+            EOF => write!(f, "end of file"),
+            Ident(id) => write!(f, "identifier `{}`", id),
+            ParenLeft => write!(f, "'('"),
+            ParenRight => write!(f, "')'"),
+            Number(nb) => write!(f, "number `{}`", nb),
+            Op(op) => write!(f, "'{}'", op),
+            Unary => write!(f, "'unary'"),
+            Var => write!(f, "'var'"),
+            TensorBegin => write!(f, "'['"),
+            TensorEnd => write!(f, "']'"),
+            Semicolon => write!(f, "';'"),
+            BlockLeft => write!(f, "'{{'"),
+            BlockRight => write!(f, "'}}'"),
+            AngleLeft => write!(f, "'<'"),
+            AngleRight => write!(f, "'>'"),
+            Return => write!(f, "'return'"),
+            Print => write!(f, "'print'"),
+            Struct => write!(f, "'struct'"),
+            If => write!(f, "'if'"),
+            Else => write!(f, "'else'"),
+            For => write!(f, "'for'"),
+            In => write!(f, "'in'"),
+            DotDot => write!(f, "'..'"),
+            Import => write!(f, "'import'"),
+            Str(string) => write!(f, "string \"{}\"", string),
+            Arrow => write!(f, "'->'"),
+        }
+    }
+}
+
+/// Defines the type of the elements of a tensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementType {
+    F64,
+    F32,
+    I64,
+}
+
+impl ElementType {
+    /// Returns the element type called `name` in Toy sources, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "f64" => Some(ElementType::F64),
+            "f32" => Some(ElementType::F32),
+            "i64" => Some(ElementType::I64),
+            _ => None,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        self != ElementType::I64
+    }
+}
+
+impl fmt::Display for ElementType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElementType::F64 => write!(f, "f64"),
+            ElementType::F32 => write!(f, "f32"),
+            ElementType::I64 => write!(f, "i64"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct VarType {
     pub shape: Vec<usize>,
+    /// Name of the struct type of the variable, `None` for tensors.
+    pub struct_name: Option<String>,
+    /// Type of the elements of a tensor, `None` unless one is declared with `:f32`.
+    pub element_type: Option<ElementType>,
 }
 
 impl VarType {
     pub fn new() -> Self {
-        Self { shape: Vec::new() }
+        Self {
+            shape: Vec::new(),
+            struct_name: None,
+            element_type: None,
+        }
+    }
+
+    pub fn new_struct(name: String) -> Self {
+        Self {
+            shape: Vec::new(),
+            struct_name: Some(name),
+            element_type: None,
+        }
     }
 }
 
@@ -71,24 +172,43 @@ impl LexError {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Describes where a token or an AST node lives in the source: the file, the 1-based
+/// line and column of its first character and the byte range it covers.
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Location {
-    filename: String,
-    // TODO: one day location will be exposed in IR
-    // row: usize,
-    // col: usize,
+    pub filename: String,
+    pub line: usize,
+    pub col: usize,
+    pub span: Range<usize>,
 }
 
 impl Location {
-    pub fn new(filename: &str, _row: usize, _col: usize) -> Location {
+    pub fn new(filename: &str, line: usize, col: usize) -> Location {
         Location {
             filename: filename.to_string(),
-            // row,
-            // col,
+            line,
+            col,
+            span: 0..0,
+        }
+    }
+
+    /// Returns a location starting where `self` starts and ending where `end` ends.
+    pub fn to(&self, end: &Location) -> Location {
+        Location {
+            filename: self.filename.clone(),
+            line: self.line,
+            col: self.col,
+            span: self.span.start..end.span.end.max(self.span.start),
         }
     }
 }
 
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.filename, self.line, self.col)
+    }
+}
+
 /// Defines the result of a lexing operation; namely a
 /// `Token` on success, or a `LexError` on failure.
 pub type LexResult = Result<Token, LexError>;
@@ -97,23 +217,26 @@ pub type LexResult = Result<Token, LexError>;
 /// a `Token` stream.
 pub struct Lexer<'a> {
     input: &'a str,
+    filename: String,
     chars: Box<Peekable<Chars<'a>>>,
     pos: usize,
+    line_starts: Vec<usize>,
     last_location: Location,
 }
 
 impl<'a> Lexer<'a> {
-    /// Creates a new `Lexer`, given its source `input`.
-    pub fn new(input: &'a str) -> Lexer<'a> {
+    /// Creates a new `Lexer`, given its source `input` and the name of the file it comes from.
+    pub fn new(input: &'a str, filename: &str) -> Lexer<'a> {
+        let mut line_starts = vec![0];
+        line_starts.extend(input.match_indices('\n').map(|(i, _)| i + 1));
+
         Lexer {
             input,
+            filename: filename.to_string(),
             chars: Box::new(input.chars().peekable()),
             pos: 0,
-            last_location: Location {
-                filename: "filename.toy".to_string(),
-                // row: 0,
-                // col: 0,
-            },
+            line_starts,
+            last_location: Location::new(filename, 1, 1),
         }
     }
 
@@ -134,6 +257,7 @@ impl<'a> Lexer<'a> {
 
                 if ch.is_none() {
                     self.pos = pos;
+                    self.last_location = self.location(pos, pos);
 
                     return Ok(Token::EOF);
                 }
@@ -143,8 +267,7 @@ impl<'a> Lexer<'a> {
                 }
             }
 
-            chars.next();
-            pos += 1;
+            pos += chars.next().map_or(0, char::len_utf8);
         }
 
         let start = pos;
@@ -154,7 +277,7 @@ impl<'a> Lexer<'a> {
             return Ok(Token::EOF);
         }
 
-        pos += 1;
+        pos += next.unwrap().len_utf8();
 
         // Actually get the next token.
         let result = match next.unwrap() {
@@ -166,9 +289,9 @@ impl<'a> Lexer<'a> {
                 // Comment
                 loop {
                     let ch = chars.next();
-                    pos += 1;
+                    pos += ch.map_or(0, char::len_utf8);
 
-                    if ch == Some('\n') {
+                    if ch.is_none() || ch == Some('\n') {
                         break;
                     }
                 }
@@ -176,16 +299,52 @@ impl<'a> Lexer<'a> {
                 Ok(Token::Comment)
             }
 
+            // A string literal, which can't span several lines.
+            '"' => loop {
+                match chars.peek() {
+                    Some('"') => {
+                        chars.next();
+                        pos += 1;
+
+                        break Ok(Token::Str(src[start + 1..pos - 1].to_string()));
+                    }
+                    Some('\n') | None => {
+                        break Err(LexError::with_index("unterminated string literal", start))
+                    }
+                    Some(&ch) => {
+                        chars.next();
+                        pos += ch.len_utf8();
+                    }
+                }
+            },
+
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                pos += 1;
+
+                Ok(Token::Arrow)
+            }
+
+            // `..` separates the bounds of a range.
+            '.' if chars.peek() == Some(&'.') => {
+                chars.next();
+                pos += 1;
+
+                Ok(Token::DotDot)
+            }
+
+            // A dot not followed by a digit accesses a struct member.
+            '.' if !chars.peek().is_some_and(char::is_ascii_digit) => Ok(Token::Op('.')),
+
             '.' | '0'..='9' => {
                 // Parse number literal
-                loop {
-                    let ch = match chars.peek() {
-                        Some(ch) => *ch,
-                        None => return Ok(Token::EOF),
-                    };
-
+                while let Some(&ch) = chars.peek() {
                     // Parse float.
-                    if ch != '.' && !ch.is_digit(16) {
+                    if ch != '.' && !ch.is_ascii_digit() {
+                        break;
+                    }
+                    // The number is the start of a range, e.g. `0..3`.
+                    if src[pos..].starts_with("..") {
                         break;
                     }
 
@@ -193,24 +352,22 @@ impl<'a> Lexer<'a> {
                     pos += 1;
                 }
 
-                Ok(Token::Number(src[start..pos].parse().unwrap()))
+                match src[start..pos].parse() {
+                    Ok(number) => Ok(Token::Number(number)),
+                    Err(_) => Err(LexError::with_index("invalid number literal", start)),
+                }
             }
 
             'a'..='z' | 'A'..='Z' | '_' => {
                 // Parse identifier
-                loop {
-                    let ch = match chars.peek() {
-                        Some(ch) => *ch,
-                        None => return Ok(Token::EOF),
-                    };
-
+                while let Some(&ch) = chars.peek() {
                     // A word-like identifier only contains underscores and alphanumeric characters.
                     if ch != '_' && !ch.is_alphanumeric() {
                         break;
                     }
 
                     chars.next();
-                    pos += 1;
+                    pos += ch.len_utf8();
                 }
 
                 match &src[start..pos] {
@@ -219,6 +376,12 @@ impl<'a> Lexer<'a> {
                     "binary" => Ok(Token::Binary),
                     "var" => Ok(Token::Var),
                     "return" => Ok(Token::Return),
+                    "struct" => Ok(Token::Struct),
+                    "if" => Ok(Token::If),
+                    "else" => Ok(Token::Else),
+                    "for" => Ok(Token::For),
+                    "in" => Ok(Token::In),
+                    "import" => Ok(Token::Import),
 
                     ident => Ok(Token::Ident(ident.to_string())),
                 }
@@ -238,15 +401,34 @@ impl<'a> Lexer<'a> {
             }
         };
 
-        // Update stored position, and return
+        // Update stored position and location, and return
         self.pos = pos;
+        self.last_location = self.location(start, pos);
 
         result
     }
 
+    /// Returns the location of the last lexed `Token`.
     pub fn last_location(&self) -> Location {
         self.last_location.clone()
     }
+
+    /// Builds the location of the byte range `start..end` of the input.
+    fn location(&self, start: usize, end: usize) -> Location {
+        let line = match self.line_starts.binary_search(&start) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let line_start = self.line_starts[line];
+        let col = self.input[line_start..start].chars().count() + 1;
+
+        Location {
+            filename: self.filename.clone(),
+            line: line + 1,
+            col,
+            span: start..end,
+        }
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -270,23 +452,48 @@ impl<'a> Iterator for Lexer<'a> {
 #[derive(Debug, Clone)]
 pub enum Expr {
     Binary {
+        location: Location,
         op: char,
         left: Box<Expr>,
         right: Box<Expr>,
     },
 
     Call {
+        location: Location,
         fn_name: String,
         args: Vec<Expr>,
     },
 
-    Number(f64),
+    /// A use of a custom unary operator, e.g. `!a`.
+    Unary {
+        location: Location,
+        op: char,
+        operand: Box<Expr>,
+    },
+
+    Number {
+        location: Location,
+        value: f64,
+    },
 
-    Variable(String),
+    Variable {
+        location: Location,
+        name: String,
+    },
 
+    /// A variable declaration, whose value is zero-filled if it has no initializer.
     VarDecl {
+        location: Location,
         name: String,
         var_type: VarType,
+        value: Option<Box<Expr>>,
+    },
+
+    /// A declaration of several variables holding the values returned by a call, e.g.
+    /// `var (a, b) = f();`.
+    Destructure {
+        location: Location,
+        names: Vec<String>,
         value: Box<Expr>,
     },
 
@@ -296,16 +503,92 @@ pub enum Expr {
         dims: Vec<usize>,
     },
     ExprList {
+        location: Location,
         expressions: Vec<Box<Expr>>,
     },
     Return {
         location: Location,
         expression: Option<Box<Expr>>,
     },
+    /// Several values returned at once, e.g. `return (a, b);`.
+    Tuple {
+        location: Location,
+        values: Vec<Expr>,
+    },
     Print {
         location: Location,
         expression: Box<Expr>,
     },
+    StructLiteral {
+        location: Location,
+        values: Vec<Expr>,
+    },
+    MemberAccess {
+        location: Location,
+        value: Box<Expr>,
+        field: String,
+        field_location: Location,
+    },
+    /// An element or a sub-tensor of a tensor, e.g. `a[i]` or `a[0, 1:3]`.
+    Index {
+        location: Location,
+        value: Box<Expr>,
+        /// One per leading dimension of `value`, the others being taken whole.
+        subscripts: Vec<Subscript>,
+    },
+    If {
+        location: Location,
+        condition: Box<Expr>,
+        then_block: Box<Expr>,
+        /// An `else if` is an else block holding a single `If`.
+        else_block: Option<Box<Expr>>,
+    },
+    /// A loop running `body` for `var` going from `start` (inclusive) to `end` (exclusive).
+    For {
+        location: Location,
+        var: String,
+        start: i64,
+        end: i64,
+        body: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// Returns the location of the source text this expression was parsed from.
+    pub fn location(&self) -> &Location {
+        match self {
+            Expr::Binary { location, .. }
+            | Expr::Call { location, .. }
+            | Expr::Unary { location, .. }
+            | Expr::Number { location, .. }
+            | Expr::Variable { location, .. }
+            | Expr::VarDecl { location, .. }
+            | Expr::Destructure { location, .. }
+            | Expr::Tensor { location, .. }
+            | Expr::ExprList { location, .. }
+            | Expr::Return { location, .. }
+            | Expr::Tuple { location, .. }
+            | Expr::Print { location, .. }
+            | Expr::StructLiteral { location, .. }
+            | Expr::MemberAccess { location, .. }
+            | Expr::Index { location, .. }
+            | Expr::If { location, .. }
+            | Expr::For { location, .. } => location,
+        }
+    }
+}
+
+/// Selects a part of a dimension of a tensor in an `Index` expression.
+#[derive(Debug, Clone)]
+pub enum Subscript {
+    /// A single element, the dimension being dropped from the result.
+    Index(Expr),
+    /// The elements from `start` (0 if omitted) up to `end` (the size of the dimension if
+    /// omitted), excluded, e.g. `1:3` or `:`.
+    Slice {
+        start: Option<usize>,
+        end: Option<usize>,
+    },
 }
 
 /// Defines the prototype (name and parameters) of a function.
@@ -313,6 +596,10 @@ pub enum Expr {
 pub struct Prototype {
     pub name: String,
     pub args: Vec<String>,
+    /// Types of `args`, given by their position.
+    pub arg_types: Vec<VarType>,
+    /// Type of the returned value, without a shape unless one is declared with `-> <2, 3>`.
+    pub return_type: VarType,
     pub is_op: bool,
     pub prec: usize,
     pub location: Location,
@@ -323,87 +610,417 @@ pub struct Prototype {
 pub struct Function {
     pub prototype: Prototype,
     pub body: Option<Expr>,
+    pub location: Location,
+}
+
+/// Defines a field of a struct.
+#[derive(Debug, Clone)]
+pub struct StructField {
+    pub name: String,
+    pub var_type: VarType,
+    pub location: Location,
 }
 
-#[derive(Debug)]
+/// Defines a user-defined struct type.
+#[derive(Debug, Clone)]
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<StructField>,
+    pub location: Location,
+}
+
+/// An `import "path.toy";` of the definitions of another file.
+#[derive(Debug, Clone)]
+pub struct Import {
+    /// The path of the imported file, relative to the importing one.
+    pub path: String,
+    pub location: Location,
+}
+
+/// A `#` comment, which isn't part of the AST but is kept for tools like the formatter.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    /// The text following the `#`, up to the end of the line.
+    pub text: String,
+    pub location: Location,
+    /// Whether the comment follows code on its line, rather than standing on a line of its own.
+    pub trailing: bool,
+}
+
+#[derive(Debug, Default)]
 pub struct Module {
+    pub imports: Vec<Import>,
+    pub structs: Vec<Struct>,
     pub functions: Vec<Function>,
+    pub comments: Vec<Comment>,
+    /// The numbers of the lines of the source holding nothing but whitespace.
+    pub blank_lines: Vec<usize>,
+}
+
+/// Classifies the errors reported by the `Parser`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A token was found where some other token was expected.
+    UnexpectedToken,
+    /// The input ended in the middle of a construct.
+    UnexpectedEof,
+    /// The lexer could not make sense of a piece of the input.
+    InvalidToken,
+    /// A tensor literal is malformed.
+    InvalidTensor,
+    /// A builtin was called with a wrong number of arguments.
+    WrongArgumentCount,
+    /// An expression is nested deeper than `MAX_NESTING_DEPTH`.
+    TooDeeplyNested,
+    /// A bound of a `for` loop is not a non-negative integer.
+    InvalidLoopBound,
+    /// A bound of a slice is not a non-negative integer.
+    InvalidSliceBound,
+    /// A custom operator is used but not defined, or a builtin one is redefined.
+    UndefinedOperator,
+    /// An `import` follows a definition.
+    MisplacedImport,
+    /// A variable is declared with neither an initializer nor a shape.
+    MissingInitializer,
+    /// An element type annotation names an unknown type.
+    UnknownElementType,
+}
+
+/// Defines an error encountered by the `Parser`.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub message: String,
+    pub location: Location,
+    /// Descriptions of the tokens that would have been accepted.
+    pub expected: Vec<String>,
+    /// The token that was found instead, if any.
+    pub found: Option<Token>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, message: impl Into<String>, location: Location) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            location,
+            expected: Vec::new(),
+            found: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let label = match (&self.found, self.expected.is_empty()) {
+            (Some(EOF), false) | (None, false) => {
+                format!("expected {}", self.expected.join(" or "))
+            }
+            (Some(found), _) => format!("unexpected {}", found),
+            (None, true) => String::new(),
+        };
+
+        let mut diagnostic = Diagnostic::error(self.message.clone(), self.location.clone());
+        if !label.is_empty() {
+            diagnostic = diagnostic.with_label(label);
+        }
+        for note in &self.notes {
+            diagnostic = diagnostic.with_note(note.clone());
+        }
+        if let Some(help) = &self.help {
+            diagnostic = diagnostic.with_help(help.clone());
+        }
+        diagnostic
+    }
+
+    /// Renders the error along with the offending line of `source`.
+    pub fn render(&self, source: &str) -> String {
+        self.to_diagnostic().render(source)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
 }
 
+impl std::error::Error for ParseError {}
+
+/// Defines the result of a parsing operation.
+pub type ParseResult<T> = Result<T, ParseError>;
+
+/// Limits how deeply expressions and tensor literals can be nested.
+pub const MAX_NESTING_DEPTH: usize = 128;
+
+/// Binary operators with a meaning of their own, which custom operators can't redefine.
+pub const BUILTIN_BINARY_OPS: &[char] = &['=', '<', '+', '-', '*', '/'];
+
 /// Represents the `Expr` parser.
 pub struct Parser<'a> {
     tokens: Vec<Token>,
+    locations: Vec<Location>,
+    comments: Vec<Comment>,
+    blank_lines: Vec<usize>,
+    eof_location: Location,
+    lex_errors: Vec<ParseError>,
+    errors: Vec<ParseError>,
     pos: usize,
+    depth: usize,
     prec: &'a mut HashMap<char, i32>,
+    /// The names of the operator functions defined by the imported files.
+    imported_operators: Vec<String>,
 }
 
 // I'm ignoring the 'must_use' lint in order to call 'self.advance' without checking
 // the result when an EOF is acceptable.
-#[allow(unused_must_use)]
+// 'ParseError' is rather large, but it is only built once the input is known to be broken.
+#[allow(unused_must_use, clippy::result_large_err)]
 impl<'a> Parser<'a> {
-    /// Creates a new parser, given an input `str` and a `HashMap` binding
-    /// an operator and its precedence in binary expressions.
-    pub fn new(input: String, op_precedence: &'a mut HashMap<char, i32>) -> Self {
-        let mut lexer = Lexer::new(input.as_str());
-        let tokens = lexer.by_ref().collect();
+    /// Creates a new parser, given an input `str`, the name of the file it was read from
+    /// and a `HashMap` binding an operator and its precedence in binary expressions.
+    pub fn new(input: String, filename: &str, op_precedence: &'a mut HashMap<char, i32>) -> Self {
+        let mut lexer = Lexer::new(input.as_str(), filename);
+        let mut tokens = Vec::new();
+        let mut locations = Vec::new();
+        let mut lex_errors = Vec::new();
+        let mut comments = Vec::new();
+
+        loop {
+            match lexer.lex() {
+                Ok(EOF) => break,
+                Ok(token) => {
+                    let location = lexer.last_location();
+                    if let Comment = token {
+                        comments.push(Self::comment(&input, location.clone()));
+                    }
+                    tokens.push(token);
+                    locations.push(location);
+                }
+                Err(error) => lex_errors.push(ParseError::new(
+                    ParseErrorKind::InvalidToken,
+                    error.error,
+                    lexer.last_location(),
+                )),
+            }
+        }
+
+        let blank_lines = input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.trim().is_empty())
+            .map(|(index, _)| index + 1)
+            .collect();
 
         Parser {
             tokens,
+            locations,
+            comments,
+            blank_lines,
+            eof_location: lexer.last_location(),
+            lex_errors,
+            errors: Vec::new(),
             prec: op_precedence,
             pos: 0,
+            depth: 0,
+            imported_operators: Vec::new(),
         }
     }
 
-    pub fn parse_module(&mut self) -> Result<Module, &'static str> {
-        let mut functions = Vec::new();
+    /// Allows the input to use the operators whose functions, e.g. `binary|`, are defined
+    /// by the files it imports.
+    pub fn with_imported_operators(mut self, operators: Vec<String>) -> Self {
+        self.imported_operators = operators;
+        self
+    }
+
+    /// Builds the comment lexed at `location` of `input`.
+    fn comment(input: &str, location: Location) -> Comment {
+        let text = input.get(location.span.clone()).unwrap_or("");
+        let line_start = input[..location.span.start]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+
+        Comment {
+            text: text
+                .trim_start_matches('#')
+                .trim_end_matches(['\n', '\r'])
+                .to_string(),
+            trailing: !input[line_start..location.span.start].trim().is_empty(),
+            location,
+        }
+    }
+
+    /// Parses the imports at the start of the input, up to the first error if any, which
+    /// `parse_module` reports. They have to be loaded before the rest of the input can be
+    /// parsed, since the operators they define may be used in it.
+    pub fn parse_imports(&mut self) -> Vec<Import> {
+        let mut imports = Vec::new();
         loop {
-            match self.current()? {
-                Def => {
-                    functions.push(self.parse_def()?);
+            match self.tokens.get(self.pos) {
+                Some(Comment) => self.pos += 1,
+                Some(Import) => match self.parse_import() {
+                    Ok(import) => imports.push(import),
+                    Err(_) => break,
+                },
+                _ => break,
+            }
+        }
+        imports
+    }
+
+    /// Parses the whole input, returning every syntax error found in it on failure.
+    pub fn parse_module(&mut self) -> Result<Module, Vec<ParseError>> {
+        let (module, errors) = self.parse_module_partial();
+
+        if errors.is_empty() {
+            Ok(module)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses the whole input, recovering from syntax errors instead of stopping at the first one.
+    /// Returns the functions that could be parsed, along with the errors sorted by location.
+    pub fn parse_module_partial(&mut self) -> (Module, Vec<ParseError>) {
+        let mut imports = Vec::new();
+        let mut structs = Vec::new();
+        let mut functions = Vec::new();
+
+        while let Some(token) = self.tokens.get(self.pos) {
+            match token {
+                Import if !structs.is_empty() || !functions.is_empty() => {
+                    let error = ParseError::new(
+                        ParseErrorKind::MisplacedImport,
+                        "imports must come before any definition",
+                        self.last_location(),
+                    )
+                    .with_note("the operators of imported files must be known before their uses");
+                    self.report(error);
+                    self.pos += 1;
+                    self.skip_to_definition();
                 }
+                Import => match self.parse_import() {
+                    Ok(import) => imports.push(import),
+                    Err(error) => {
+                        self.report(error);
+                        self.skip_to_definition();
+                    }
+                },
+                Def => match self.parse_def() {
+                    Ok(function) => functions.push(function),
+                    Err(error) => {
+                        self.report(error);
+                        self.skip_to_definition();
+                    }
+                },
+                Struct => match self.parse_struct() {
+                    Ok(struct_ast) => structs.push(struct_ast),
+                    Err(error) => {
+                        self.report(error);
+                        self.skip_to_definition();
+                    }
+                },
                 Comment => {
                     self.advance();
-                    continue;
-                }
-                EOF => {
-                    break;
                 }
                 _ => {
-                    // TODO: error situation
-                    break;
+                    let error = self
+                        .unexpected(&["'def'", "'struct'"], "at top level")
+                        .with_note("only function and struct definitions are allowed at top level");
+                    self.report(error);
+                    self.skip_to_definition();
                 }
             }
-            if self.at_end() {
-                break;
-            }
         }
 
-        Ok(Module { functions })
+        self.check_operators(&functions);
+
+        let mut errors = std::mem::take(&mut self.lex_errors);
+        errors.append(&mut self.errors);
+        errors.sort_by_key(|error| error.location.span.start);
+
+        let module = Module {
+            imports,
+            structs,
+            functions,
+            comments: std::mem::take(&mut self.comments),
+            blank_lines: std::mem::take(&mut self.blank_lines),
+        };
+        (module, errors)
+    }
+
+    /// Reports the uses of custom operators that no `def binary` or `def unary` defines.
+    /// A definition may come after the uses, but they then have the default precedence.
+    fn check_operators(&mut self, functions: &[Function]) {
+        let mut checker = OperatorChecker {
+            defined: functions
+                .iter()
+                .filter(|function| function.prototype.is_op)
+                .map(|function| function.prototype.name.as_str())
+                .chain(self.imported_operators.iter().map(String::as_str))
+                .collect(),
+            errors: &mut self.errors,
+        };
+        for function in functions {
+            checker.visit_function(function);
+        }
     }
 
-    /// Returns the current `Token`, without performing safety checks beforehand.
+    /// Returns the current `Token`, or `EOF` if the end of the input has been reached.
     fn curr(&self) -> Token {
-        self.tokens[self.pos].clone()
+        self.tokens.get(self.pos).cloned().unwrap_or(EOF)
+    }
+
+    /// Returns the `Token` following the current one, or `EOF` if there is none.
+    fn peek(&self) -> Token {
+        self.tokens.get(self.pos + 1).cloned().unwrap_or(EOF)
     }
 
     /// Returns the current `Token`, or an error that
     /// indicates that the end of the file has been unexpectedly reached if it is the case.
-    fn current(&self) -> Result<Token, &'static str> {
+    fn current(&self) -> ParseResult<Token> {
         if self.pos >= self.tokens.len() {
-            Err("Unexpected end of file.")
+            Err(self.eof_error())
         } else {
             Ok(self.tokens[self.pos].clone())
         }
     }
 
+    /// Returns the location of the current `Token`, or the end of the input if it has been reached.
     fn last_location(&self) -> Location {
-        Location::new("filename", 0, 0)
+        match self.locations.get(self.pos) {
+            Some(location) => location.clone(),
+            None => self.eof_location.clone(),
+        }
+    }
+
+    /// Returns a location spanning from `start` to the end of the last consumed `Token`.
+    fn location_from(&self, start: &Location) -> Location {
+        let last = self.pos.min(self.locations.len()).saturating_sub(1);
+
+        match self.locations.get(last) {
+            Some(end) => start.to(end),
+            None => start.clone(),
+        }
     }
 
     /// Advances the position, and returns an empty `Result` whose error
     /// indicates that the end of the file has been unexpectedly reached.
     /// This allows to use the `self.advance()?;` syntax.
-    fn advance(&mut self) -> Result<(), &'static str> {
+    fn advance(&mut self) -> ParseResult<()> {
         let npos = self.pos + 1;
 
         self.pos = npos;
@@ -411,10 +1028,101 @@ impl<'a> Parser<'a> {
         if npos < self.tokens.len() {
             Ok(())
         } else {
-            Err("Unexpected end of file.")
+            Err(self.eof_error())
+        }
+    }
+
+    /// Returns an error telling that the end of the input has been unexpectedly reached.
+    fn eof_error(&self) -> ParseError {
+        let mut error = ParseError::new(
+            ParseErrorKind::UnexpectedEof,
+            "unexpected end of file",
+            self.eof_location.clone(),
+        );
+        error.found = Some(EOF);
+        error.with_help("check for a missing '}' or ';'")
+    }
+
+    /// Returns an error telling that the current `Token` is not one of `expected`.
+    /// `context` describes the construct being parsed, e.g. "in function call".
+    fn unexpected(&self, expected: &[&str], context: &str) -> ParseError {
+        let found = self.tokens.get(self.pos).cloned().unwrap_or(EOF);
+        let kind = match found {
+            EOF => ParseErrorKind::UnexpectedEof,
+            _ => ParseErrorKind::UnexpectedToken,
+        };
+        let mut message = format!("expected {}", expected.join(" or "));
+        if !context.is_empty() {
+            message = format!("{} {}", message, context);
+        }
+        message = format!("{}, found {}", message, found);
+
+        let mut error = ParseError::new(kind, message, self.last_location());
+        error.expected = expected.iter().map(|x| x.to_string()).collect();
+        error.found = Some(found);
+        error
+    }
+
+    /// Records an error the parser recovered from. An error reported at the same place as the
+    /// previous one is most likely a consequence of it, and is dropped.
+    fn report(&mut self, error: ParseError) {
+        let is_cascade = self
+            .errors
+            .last()
+            .is_some_and(|last| last.location.span == error.location.span);
+
+        if !is_cascade {
+            self.errors.push(error);
+        }
+    }
+
+    /// Skips tokens after an error in a block, up to the next `;` ending a statement,
+    /// the `}` closing the block or the next definition. The stopping token is not consumed.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+
+        while let Some(token) = self.tokens.get(self.pos) {
+            match token {
+                Semicolon | BlockRight | Def | Struct if depth == 0 => return,
+                BlockLeft => depth += 1,
+                BlockRight => depth -= 1,
+                _ => (),
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Skips tokens after an error at top level, up to the next `def` or `struct`.
+    fn skip_to_definition(&mut self) {
+        while let Some(token) = self.tokens.get(self.pos) {
+            if let Def | Struct | Import = token {
+                return;
+            }
+            self.pos += 1;
         }
     }
 
+    /// Runs `parse` one nesting level deeper, so that pathologically nested input
+    /// is reported as an error instead of overflowing the stack.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(ParseError::new(
+                ParseErrorKind::TooDeeplyNested,
+                format!(
+                    "expression is nested more than {} levels deep",
+                    MAX_NESTING_DEPTH
+                ),
+                self.last_location(),
+            ));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+
+        result
+    }
+
     /// Returns a value indicating whether or not the `Parser`
     /// has reached the end of the input.
     fn at_end(&self) -> bool {
@@ -423,15 +1131,25 @@ impl<'a> Parser<'a> {
 
     /// Returns the precedence of the current `Token`, or 0 if it is not recognized as a binary operator.
     fn get_tok_precedence(&self) -> i32 {
-        if let Ok(Op(op)) = self.current() {
+        if let Some(op) = self.binary_op() {
             *self.prec.get(&op).unwrap_or(&100)
         } else {
             -1
         }
     }
 
+    /// Returns the operator of the current `Token` when used in a binary expression.
+    /// `<` is lexed as an angle bracket, as it also opens the shape of a variable.
+    fn binary_op(&self) -> Option<char> {
+        match self.curr() {
+            Op(op) => Some(op),
+            AngleLeft => Some('<'),
+            _ => None,
+        }
+    }
+
     /// Parses the prototype of a function, whether external or user-defined.
-    fn parse_prototype(&mut self) -> Result<Prototype, &'static str> {
+    fn parse_prototype(&mut self) -> ParseResult<Prototype> {
         let location = self.last_location();
         let (id, is_operator, precedence) = match self.curr() {
             Ident(id) => {
@@ -440,12 +1158,28 @@ impl<'a> Parser<'a> {
                 (id, false, 0)
             }
 
+            Binary if matches!(self.peek(), Op(op) if BUILTIN_BINARY_OPS.contains(&op)) => {
+                self.advance()?;
+
+                return Err(ParseError::new(
+                    ParseErrorKind::UndefinedOperator,
+                    format!("cannot redefine builtin operator {}", self.curr()),
+                    self.last_location(),
+                ));
+            }
+
             Binary => {
                 self.advance()?;
 
                 let op = match self.curr() {
                     Op(ch) => ch,
-                    _ => return Err("Expected operator in custom operator declaration."),
+                    _ => {
+                        return Err(self
+                            .unexpected(&["operator"], "in custom operator declaration")
+                            .with_help(
+                                "custom operators are declared like `def binary| 5 (a, b)`",
+                            ))
+                    }
                 };
 
                 self.advance()?;
@@ -472,7 +1206,11 @@ impl<'a> Parser<'a> {
 
                 let op = match self.curr() {
                     Op(ch) => ch,
-                    _ => return Err("Expected operator in custom operator declaration."),
+                    _ => {
+                        return Err(self
+                            .unexpected(&["operator"], "in custom operator declaration")
+                            .with_help("custom operators are declared like `def unary! (a)`"))
+                    }
                 };
 
                 let mut name = String::from("unary");
@@ -484,61 +1222,148 @@ impl<'a> Parser<'a> {
                 (name, true, 0)
             }
 
-            _ => return Err("Expected identifier in prototype declaration."),
+            _ => return Err(self.unexpected(&["function name"], "in prototype declaration")),
         };
 
         match self.curr() {
             ParenLeft => (),
-            _ => return Err("Expected '(' character in prototype declaration."),
+            _ => return Err(self.unexpected(&["'('"], "in prototype declaration")),
         }
 
         self.advance()?;
 
+        let mut args = vec![];
+        let mut arg_types = vec![];
+
         if let ParenRight = self.curr() {
             self.advance();
-
-            return Ok(Prototype {
-                name: id,
-                args: vec![],
-                is_op: is_operator,
-                prec: precedence,
-                location,
-            });
+        } else {
+            self.parse_parameters(&mut args, &mut arg_types)?;
         }
 
-        let mut args = vec![];
-
-        loop {
+        let return_type = if let Arrow = self.curr() {
+            self.advance()?;
             match self.curr() {
-                Ident(name) => args.push(name),
-                _ => return Err("Expected identifier in parameter declaration."),
+                AngleLeft => self.parse_var_type()?,
+                Op(':') => VarType {
+                    element_type: self.parse_element_type()?,
+                    ..VarType::new()
+                },
+                _ => {
+                    return Err(self.unexpected(&["'<'", "':'"], "after '->'").with_help(
+                        "the result type is a shape, like `-> <2, 3>`, an element type, \
+                             like `-> :f32`, or both",
+                    ))
+                }
             }
+        } else {
+            VarType::new()
+        };
 
-            self.advance()?;
+        // The operands of a custom operator are its parameters.
+        if is_operator {
+            let arity = if id.starts_with("binary") { 2 } else { 1 };
+            if args.len() != arity {
+                return Err(ParseError::new(
+                    ParseErrorKind::WrongArgumentCount,
+                    format!(
+                        "operator `{}` must take {} parameter(s), found {}",
+                        id,
+                        arity,
+                        args.len()
+                    ),
+                    self.location_from(&location),
+                ));
+            }
+        }
+
+        Ok(Prototype {
+            name: id,
+            args,
+            arg_types,
+            return_type,
+            is_op: is_operator,
+            prec: precedence,
+            location: self.location_from(&location),
+        })
+    }
+
+    /// Parses the parameters of a prototype, up to the closing parenthesis.
+    fn parse_parameters(
+        &mut self,
+        args: &mut Vec<String>,
+        arg_types: &mut Vec<VarType>,
+    ) -> ParseResult<()> {
+        loop {
+            // A parameter is either a name, optionally followed by a shape and an element
+            // type, or, for structs, a type name followed by a name.
+            match (self.curr(), self.peek()) {
+                (Ident(struct_name), Ident(name)) => {
+                    self.advance()?;
+                    self.advance()?;
+                    args.push(name);
+                    arg_types.push(VarType::new_struct(struct_name));
+                }
+                (Ident(name), AngleLeft) => {
+                    self.advance()?;
+                    args.push(name);
+                    arg_types.push(self.parse_var_type()?);
+                }
+                (Ident(name), _) => {
+                    self.advance()?;
+                    args.push(name);
+                    arg_types.push(VarType {
+                        element_type: self.parse_element_type()?,
+                        ..VarType::new()
+                    });
+                }
+                _ => return Err(self.unexpected(&["identifier"], "in parameter declaration")),
+            }
 
             match self.curr() {
                 ParenRight => {
                     self.advance();
-                    break;
+                    return Ok(());
                 }
                 Comma => {
                     self.advance();
                 }
-                _ => return Err("Expected ',' or ')' character in prototype declaration."),
+                _ => return Err(self.unexpected(&["','", "')'"], "in prototype declaration")),
             }
         }
+    }
 
-        Ok(Prototype {
-            name: id,
-            args,
-            is_op: is_operator,
-            prec: precedence,
-            location,
+    /// Parses an import, e.g. `import "lib.toy";`.
+    fn parse_import(&mut self) -> ParseResult<Import> {
+        let location = self.last_location();
+        // eat 'import' keyword
+        self.advance()?;
+
+        let path = match self.curr() {
+            Str(path) => path,
+            _ => {
+                return Err(self
+                    .unexpected(&["string"], "after 'import'")
+                    .with_help("imports name a file, like `import \"lib.toy\";`"))
+            }
+        };
+        self.advance()?;
+
+        match self.curr() {
+            Semicolon => (),
+            _ => return Err(self.unexpected(&["';'"], "after import")),
+        }
+        self.advance();
+
+        Ok(Import {
+            path,
+            location: self.location_from(&location),
         })
     }
 
     /// Parses a user-defined function.
-    fn parse_def(&mut self) -> Result<Function, &'static str> {
+    fn parse_def(&mut self) -> ParseResult<Function> {
+        let location = self.last_location();
         // Eat 'def' keyword
         self.pos += 1;
 
@@ -547,17 +1372,18 @@ impl<'a> Parser<'a> {
 
         // Parse body of function
         // let body = self.parse_expr()?;
-        let body = self.parse_block()?;
+        let body = self.parse_block("function body")?;
 
         // Return new function
         Ok(Function {
             prototype: proto,
             body: Some(body),
+            location: self.location_from(&location),
         })
     }
 
     /// Parses any expression.
-    fn parse_expr(&mut self) -> Result<Expr, &'static str> {
+    fn parse_expr(&mut self) -> ParseResult<Expr> {
         match self.parse_unary_expr() {
             Ok(left) => self.parse_binary_expr(0, left),
             err => err,
@@ -565,31 +1391,41 @@ impl<'a> Parser<'a> {
     }
 
     /// Parses a literal number.
-    fn parse_nb_expr(&mut self) -> Result<Expr, &'static str> {
+    fn parse_nb_expr(&mut self) -> ParseResult<Expr> {
         // Simply convert Token::Number to Expr::Number
+        let location = self.last_location();
         match self.curr() {
             Number(nb) => {
                 self.advance();
-                Ok(Expr::Number(nb))
+                Ok(Expr::Number {
+                    location,
+                    value: nb,
+                })
             }
-            _ => Err("Expected number literal."),
+            _ => Err(self.unexpected(&["number"], "")),
         }
     }
 
-    /// Parses an expression enclosed in parenthesis.
-    fn parse_paren_expr(&mut self) -> Result<Expr, &'static str> {
+    /// Parses an expression enclosed in parenthesis, or a tuple of comma-separated ones.
+    fn parse_paren_expr(&mut self) -> ParseResult<Expr> {
+        let location = self.last_location();
         match self.current()? {
             ParenLeft => (),
-            _ => return Err("Expected '(' character at start of parenthesized expression."),
+            _ => {
+                return Err(self.unexpected(&["'('"], "at start of parenthesized expression"));
+            }
         }
 
         self.advance()?;
 
-        let expr = self.parse_expr()?;
+        let mut expr = self.parse_expr()?;
+        if let Comma = self.current()? {
+            expr = self.parse_tuple(location, expr)?;
+        }
 
         match self.current()? {
             ParenRight => (),
-            _ => return Err("Expected ')' character at end of parenthesized expression."),
+            _ => return Err(self.unexpected(&["')'"], "at end of parenthesized expression")),
         }
 
         self.advance();
@@ -597,15 +1433,31 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    /// Parses the values of a tuple following its first one, `first`, up to the closing ')'.
+    fn parse_tuple(&mut self, location: Location, first: Expr) -> ParseResult<Expr> {
+        let mut values = vec![first];
+        while let Comma = self.current()? {
+            self.advance()?;
+            values.push(self.parse_expr()?);
+        }
+
+        Ok(Expr::Tuple {
+            // NB: the location spans the closing ')', which is consumed by the caller
+            location: location.to(&self.last_location()),
+            values,
+        })
+    }
+
     /// Parses an expression that starts with an identifier (either a variable or a function call).
-    fn parse_id_expr(&mut self) -> Result<Expr, &'static str> {
+    fn parse_id_expr(&mut self) -> ParseResult<Expr> {
+        let location = self.last_location();
         let id = match self.curr() {
             Ident(id) => id,
-            _ => return Err("Expected identifier."),
+            _ => return Err(self.unexpected(&["identifier"], "")),
         };
 
         if self.advance().is_err() {
-            return Ok(Expr::Variable(id));
+            return Ok(Expr::Variable { location, name: id });
         }
 
         match self.curr() {
@@ -613,7 +1465,10 @@ impl<'a> Parser<'a> {
                 self.advance()?;
 
                 if let ParenRight = self.curr() {
+                    self.advance();
+
                     return Ok(Expr::Call {
+                        location: self.location_from(&location),
                         fn_name: id,
                         args: vec![],
                     });
@@ -627,7 +1482,7 @@ impl<'a> Parser<'a> {
                     match self.current()? {
                         Comma => (),
                         ParenRight => break,
-                        _ => return Err("Expected ',' character in function call."),
+                        _ => return Err(self.unexpected(&["','", "')'"], "in function call")),
                     }
 
                     self.advance()?;
@@ -635,46 +1490,55 @@ impl<'a> Parser<'a> {
 
                 self.advance();
 
+                let location = self.location_from(&location);
                 if id == String::from("print") {
                     if args.len() != 1 {
-                        return Err("Print must have single argument");
+                        return Err(ParseError::new(
+                            ParseErrorKind::WrongArgumentCount,
+                            format!("print expects a single argument, found {}", args.len()),
+                            location,
+                        )
+                        .with_help("print one tensor per call"));
                     }
-                    let location = self.last_location();
                     return Ok(Expr::Print {
                         location,
                         expression: Box::new(args[0].clone()),
                     });
                 }
 
-                Ok(Expr::Call { fn_name: id, args })
+                Ok(Expr::Call {
+                    location,
+                    fn_name: id,
+                    args,
+                })
             }
 
-            _ => Ok(Expr::Variable(id)),
+            _ => Ok(Expr::Variable { location, name: id }),
         }
     }
 
     /// Parses an unary expression.
-    fn parse_unary_expr(&mut self) -> Result<Expr, &'static str> {
+    fn parse_unary_expr(&mut self) -> ParseResult<Expr> {
+        let location = self.last_location();
         let op = match self.current()? {
             Op(ch) => {
                 self.advance()?;
                 ch
             }
-            _ => return self.parse_primary(),
+            _ => return self.nested(Self::parse_primary),
         };
 
-        let mut name = String::from("unary");
+        let operand = self.nested(Self::parse_unary_expr)?;
 
-        name.push(op);
-
-        Ok(Expr::Call {
-            fn_name: name,
-            args: vec![self.parse_unary_expr()?],
+        Ok(Expr::Unary {
+            location: self.location_from(&location),
+            op,
+            operand: Box::new(operand),
         })
     }
 
     /// Parses a binary expression, given its left-hand expression.
-    fn parse_binary_expr(&mut self, prec: i32, mut left: Expr) -> Result<Expr, &'static str> {
+    fn parse_binary_expr(&mut self, prec: i32, mut left: Expr) -> ParseResult<Expr> {
         loop {
             let curr_prec = self.get_tok_precedence();
 
@@ -682,9 +1546,9 @@ impl<'a> Parser<'a> {
                 return Ok(left);
             }
 
-            let op = match self.curr() {
-                Op(op) => op,
-                _ => return Err("Invalid operator."),
+            let op = match self.binary_op() {
+                Some(op) => op,
+                None => return Err(self.unexpected(&["operator"], "in binary expression")),
             };
 
             self.advance()?;
@@ -698,6 +1562,7 @@ impl<'a> Parser<'a> {
             }
 
             left = Expr::Binary {
+                location: self.location_from(left.location()),
                 op,
                 left: Box::new(left),
                 right: Box::new(right),
@@ -705,31 +1570,64 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// Parses a var expression.
-    fn parse_var_expr(&mut self) -> Result<Expr, &'static str> {
-        // eat 'var' token
+    /// Parses a variable declaration, starting either with `var` or with the name of a struct.
+    fn parse_var_expr(&mut self) -> ParseResult<Expr> {
+        let location = self.last_location();
+        // eat 'var' token or the struct name
+        let struct_name = match self.curr() {
+            Ident(struct_name) => Some(struct_name),
+            _ => None,
+        };
         self.advance()?;
 
         let name = match self.curr() {
             Ident(name) => name,
-            _ => return Err("Expected identifier in 'var..in' declaration."),
+            ParenLeft if struct_name.is_none() => return self.parse_destructure(location),
+            _ => return Err(self.unexpected(&["identifier"], "in variable declaration")),
         };
 
         self.advance()?;
 
-        let var_type = match self.curr() {
-            AngleLeft => self.parse_var_type()?,
-            _ => VarType::new(),
+        let var_type = match (self.curr(), struct_name) {
+            (_, Some(struct_name)) => VarType::new_struct(struct_name),
+            (AngleLeft, None) => self.parse_var_type()?,
+            (_, None) => VarType {
+                element_type: self.parse_element_type()?,
+                ..VarType::new()
+            },
         };
 
-        // read (optional) initializer
         let initializer = match self.curr() {
-            Op('=') => Some({
+            Op('=') => {
                 self.advance()?;
-                self.parse_expr()?
-            }),
-
-            _ => None,
+                Some(Box::new(self.parse_expr()?))
+            }
+            // NB: a tensor declared with a shape only is zero-filled
+            Semicolon | Comma if !var_type.shape.is_empty() => None,
+            Semicolon | Comma => {
+                let what = match &var_type.struct_name {
+                    Some(_) => "struct variable",
+                    None => "variable",
+                };
+                let help = match &var_type.struct_name {
+                    Some(_) => format!("initialize it, like `{} = {{...}};`", name),
+                    None => format!(
+                        "initialize it, like `var {} = [1, 2];`, or give it a shape, like `var {}<2>;`",
+                        name, name
+                    ),
+                };
+                return Err(ParseError::new(
+                    ParseErrorKind::MissingInitializer,
+                    format!("{} `{}` must be initialized", what, name),
+                    self.location_from(&location),
+                )
+                .with_help(help));
+            }
+            _ => {
+                return Err(self
+                    .unexpected(&["'='", "';'"], "in variable declaration")
+                    .with_help("variables are declared like `var a = [1, 2];` or `var a<2, 3>;`"))
+            }
         };
 
         match self.curr() {
@@ -737,17 +1635,63 @@ impl<'a> Parser<'a> {
                 self.advance()?;
             }
             Semicolon => {}
-            _ => return Err("Expected comma or 'in' keyword in variable declaration."),
+            _ => {
+                return Err(self
+                    .unexpected(&["';'"], "after variable declaration")
+                    .with_help("add ';' to end the declaration"))
+            }
         }
 
         Ok(Expr::VarDecl {
+            location: self.location_from(&location),
             name,
             var_type,
-            value: Box::new(initializer.unwrap()),
+            value: initializer,
         })
     }
 
-    fn parse_var_type(&mut self) -> Result<VarType, &'static str> {
+    /// Parses the names and the initializer of a destructuring declaration, after `var`.
+    fn parse_destructure(&mut self, location: Location) -> ParseResult<Expr> {
+        // skip (
+        self.advance()?;
+
+        let mut names = Vec::new();
+        loop {
+            match self.curr() {
+                Ident(name) => names.push(name),
+                _ => return Err(self.unexpected(&["identifier"], "in destructuring declaration")),
+            }
+            self.advance()?;
+            match self.curr() {
+                Comma => {
+                    self.advance()?;
+                }
+                ParenRight => break,
+                _ => return Err(self.unexpected(&["','", "')'"], "in destructuring declaration")),
+            }
+        }
+        self.advance()?;
+
+        match self.curr() {
+            Op('=') => {
+                self.advance()?;
+            }
+            _ => {
+                return Err(self
+                    .unexpected(&["'='"], "in destructuring declaration")
+                    .with_help("the values are declared like `var (a, b) = f();`"))
+            }
+        }
+        let value = self.parse_expr()?;
+
+        Ok(Expr::Destructure {
+            location: self.location_from(&location),
+            names,
+            value: Box::new(value),
+        })
+    }
+
+    fn parse_var_type(&mut self) -> ParseResult<VarType> {
         // skip <
         self.advance();
 
@@ -765,14 +1709,47 @@ impl<'a> Parser<'a> {
                     self.advance();
                     break;
                 }
-                _ => return Err("Cannot define variable type"),
+                _ => return Err(self.unexpected(&["number", "','", "'>'"], "in variable shape")),
             }
         }
 
-        Ok(VarType { shape })
+        Ok(VarType {
+            shape,
+            struct_name: None,
+            element_type: self.parse_element_type()?,
+        })
     }
 
-    fn parse_tensor_literal_expr(&mut self) -> Result<Expr, &'static str> {
+    /// Parses an optional element type annotation, like `:f32`.
+    fn parse_element_type(&mut self) -> ParseResult<Option<ElementType>> {
+        if !matches!(self.curr(), Op(':')) {
+            return Ok(None);
+        }
+        self.advance()?;
+
+        let name = match self.curr() {
+            Ident(name) => name,
+            _ => {
+                return Err(self
+                    .unexpected(&["element type"], "after ':'")
+                    .with_help("the element types are `f64`, `f32` and `i64`"))
+            }
+        };
+        match ElementType::from_name(&name) {
+            Some(element_type) => {
+                self.advance()?;
+                Ok(Some(element_type))
+            }
+            None => Err(ParseError::new(
+                ParseErrorKind::UnknownElementType,
+                format!("unknown element type `{}`", name),
+                self.last_location(),
+            )
+            .with_help("the element types are `f64`, `f32` and `i64`")),
+        }
+    }
+
+    fn parse_tensor_literal_expr(&mut self) -> ParseResult<Expr> {
         let location = self.last_location();
         // eat [
         self.advance();
@@ -781,7 +1758,7 @@ impl<'a> Parser<'a> {
         loop {
             match self.curr() {
                 Token::TensorBegin => {
-                    values.push(self.parse_tensor_literal_expr()?);
+                    values.push(self.nested(Self::parse_tensor_literal_expr)?);
                 }
                 Token::Comma => {
                     self.advance();
@@ -793,77 +1770,436 @@ impl<'a> Parser<'a> {
                     self.advance();
                     break;
                 }
-                _ => return Err("Cannot parse tensor expression"),
+                _ => {
+                    return Err(
+                        self.unexpected(&["number", "'['", "','", "']'"], "in tensor literal")
+                    )
+                }
             }
             // TODO: handling error siutation
         }
         dims.push(values.len());
 
         // FIXME: can we just do if instead match?
-        match &values[0] {
-            Expr::Tensor {
-                location: _,
-                values: _,
-                dims: d,
-            } => {
+        match values.first() {
+            Some(Expr::Tensor { dims: d, .. }) => {
                 dims.append(&mut d.clone());
             }
-            Expr::Number(_num) => {}
-            _ => return Err("Cannot parse tensor"),
+            Some(Expr::Number { .. }) => {}
+            None => {
+                return Err(ParseError::new(
+                    ParseErrorKind::InvalidTensor,
+                    "tensor literal must not be empty",
+                    self.location_from(&location),
+                ))
+            }
+            _ => {
+                return Err(ParseError::new(
+                    ParseErrorKind::InvalidTensor,
+                    "tensor literal must contain numbers or nested tensor literals",
+                    self.location_from(&location),
+                ))
+            }
         };
 
         Ok(Expr::Tensor {
-            location,
+            location: self.location_from(&location),
             values,
             dims,
         })
     }
 
     /// Parses a primary expression (an identifier, a number or a parenthesized expression).
-    fn parse_primary(&mut self) -> Result<Expr, &'static str> {
-        match self.curr() {
-            Ident(_) => self.parse_id_expr(),
-            Number(_) => self.parse_nb_expr(),
-            ParenLeft => self.parse_paren_expr(),
-            Var => self.parse_var_expr(),
-            TensorBegin => self.parse_tensor_literal_expr(),
-            _ => Err("Unknown expression."),
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        match (self.curr(), self.peek()) {
+            // `Pair p = ...` declares a variable of a struct type.
+            (Ident(_), Ident(_)) => self.parse_var_expr(),
+            (Ident(_), _) => {
+                let expr = self.parse_id_expr()?;
+                self.parse_postfix_expr(expr)
+            }
+            (Number(_), _) => self.parse_nb_expr(),
+            (ParenLeft, _) => {
+                let expr = self.parse_paren_expr()?;
+                self.parse_postfix_expr(expr)
+            }
+            (Var, _) => self.parse_var_expr(),
+            (TensorBegin, _) => {
+                let expr = self.parse_tensor_literal_expr()?;
+                self.parse_postfix_expr(expr)
+            }
+            (BlockLeft, _) => self.parse_struct_literal_expr(),
+            _ => Err(self.unexpected(&["expression"], "")),
+        }
+    }
+
+    /// Parses the `.field` accesses and `[...]` subscripts following `value`, if any.
+    fn parse_postfix_expr(&mut self, mut value: Expr) -> ParseResult<Expr> {
+        loop {
+            value = match self.curr() {
+                Op('.') => self.parse_member_access(value)?,
+                TensorBegin => {
+                    let subscripts = self.nested(Self::parse_subscripts)?;
+                    Expr::Index {
+                        location: self.location_from(value.location()),
+                        value: Box::new(value),
+                        subscripts,
+                    }
+                }
+                _ => return Ok(value),
+            };
         }
     }
 
-    fn parse_block(&mut self) -> Result<Expr, &'static str> {
-        // skip {,
-        // TODO: check that block starts with bracket
+    /// Parses a `.field` access of `value`.
+    fn parse_member_access(&mut self, value: Expr) -> ParseResult<Expr> {
+        // eat .
+        self.advance()?;
+
+        let field_location = self.last_location();
+        let field = match self.curr() {
+            Ident(field) => field,
+            _ => return Err(self.unexpected(&["field name"], "after '.'")),
+        };
         self.advance();
 
-        let mut expressions = Vec::new();
+        Ok(Expr::MemberAccess {
+            location: self.location_from(value.location()),
+            value: Box::new(value),
+            field,
+            field_location,
+        })
+    }
+
+    /// Parses the subscripts of an indexing, e.g. `[i, 1:3, :]`.
+    fn parse_subscripts(&mut self) -> ParseResult<Vec<Subscript>> {
+        // eat [
+        self.advance()?;
+
+        let mut subscripts = Vec::new();
+        loop {
+            subscripts.push(self.parse_subscript()?);
+
+            match self.current()? {
+                Comma => (),
+                TensorEnd => break,
+                _ => return Err(self.unexpected(&["','", "']'"], "in subscripts")),
+            }
+
+            self.advance()?;
+        }
+
+        self.advance();
+
+        Ok(subscripts)
+    }
+
+    /// Parses a subscript: a slice, whose bounds are literal integers, or else an index.
+    fn parse_subscript(&mut self) -> ParseResult<Subscript> {
+        let start = match (self.current()?, self.peek()) {
+            (Op(':'), _) => None,
+            (Number(_), Op(':')) => Some(self.parse_slice_bound()?),
+            _ => return Ok(Subscript::Index(self.parse_expr()?)),
+        };
+        // eat :
+        self.advance()?;
+
+        let end = match self.current()? {
+            Comma | TensorEnd => None,
+            _ => Some(self.parse_slice_bound()?),
+        };
+
+        Ok(Subscript::Slice { start, end })
+    }
+
+    /// Parses a bound of a slice, which must be a literal non-negative integer.
+    fn parse_slice_bound(&mut self) -> ParseResult<usize> {
+        let location = self.last_location();
+        let bound = match self.current()? {
+            Number(nb) => nb,
+            _ => return Err(self.unexpected(&["number", "','", "']'"], "after ':'")),
+        };
+
+        if bound.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&bound) {
+            return Err(ParseError::new(
+                ParseErrorKind::InvalidSliceBound,
+                format!("slice bound `{}` is not a non-negative integer", bound),
+                location,
+            ));
+        }
+        self.advance()?;
+
+        Ok(bound as usize)
+    }
+
+    /// Parses a struct literal, e.g. `{[1, 2], [3, 4]}`.
+    fn parse_struct_literal_expr(&mut self) -> ParseResult<Expr> {
+        let location = self.last_location();
+        // eat {
+        self.advance()?;
+
+        let mut values = Vec::new();
         loop {
+            values.push(self.parse_expr()?);
+
             match self.curr() {
-                Token::BlockRight => {
+                Comma => {
+                    self.advance()?;
+                }
+                BlockRight => {
                     self.advance();
                     break;
                 }
-                Token::Semicolon => {}
-                Token::Return => {
-                    let expr = self.parse_return()?;
-                    expressions.push(Box::new(expr));
+                _ => return Err(self.unexpected(&["','", "'}'"], "in struct literal")),
+            }
+        }
+
+        Ok(Expr::StructLiteral {
+            location: self.location_from(&location),
+            values,
+        })
+    }
+
+    /// Parses a struct declaration, e.g. `struct Pair { var a; var b; }`.
+    fn parse_struct(&mut self) -> ParseResult<Struct> {
+        let location = self.last_location();
+        // eat 'struct' keyword
+        self.advance()?;
+
+        let name = match self.curr() {
+            Ident(name) => name,
+            _ => return Err(self.unexpected(&["struct name"], "in struct declaration")),
+        };
+        self.advance()?;
+
+        match self.curr() {
+            BlockLeft => (),
+            _ => return Err(self.unexpected(&["'{'"], "in struct declaration")),
+        }
+        self.advance()?;
+
+        let mut fields = Vec::new();
+        loop {
+            match self.curr() {
+                BlockRight => {
+                    self.advance();
+                    break;
                 }
                 Comment => {
+                    self.advance()?;
+                }
+                Var | Ident(_) => fields.push(self.parse_struct_field()?),
+                _ => return Err(self.unexpected(&["'var'", "'}'"], "in struct declaration")),
+            }
+        }
+
+        Ok(Struct {
+            name,
+            fields,
+            location: self.location_from(&location),
+        })
+    }
+
+    /// Parses a field of a struct: `var name;`, or `Type name;` for a nested struct.
+    fn parse_struct_field(&mut self) -> ParseResult<StructField> {
+        let location = self.last_location();
+        let var_type = match self.curr() {
+            Ident(struct_name) => VarType::new_struct(struct_name),
+            _ => VarType::new(),
+        };
+        self.advance()?;
+
+        let name = match self.curr() {
+            Ident(name) => name,
+            _ => return Err(self.unexpected(&["identifier"], "in struct field")),
+        };
+        self.advance()?;
+
+        match self.curr() {
+            Semicolon => (),
+            _ => {
+                return Err(self
+                    .unexpected(&["';'"], "after struct field")
+                    .with_note("struct fields can't have an initializer or a shape"))
+            }
+        }
+        self.advance()?;
+
+        Ok(StructField {
+            name,
+            var_type,
+            location: self.location_from(&location),
+        })
+    }
+
+    /// Parses a block of statements, e.g. the body of a function or of a loop, which is
+    /// described by `what` in errors. Errors in a statement are reported and the parsing
+    /// resumes at the next statement, so that a partial block is returned.
+    fn parse_block(&mut self, what: &str) -> ParseResult<Expr> {
+        let location = self.last_location();
+        match self.current()? {
+            BlockLeft => (),
+            _ => return Err(self.unexpected(&["'{'"], &format!("before {}", what))),
+        }
+        self.advance();
+
+        let mut expressions = Vec::new();
+        loop {
+            let statement = match self.current() {
+                Ok(BlockRight) => {
+                    self.advance();
+                    break;
+                }
+                Ok(Semicolon) | Ok(Comment) => {
                     self.advance();
                     continue;
                 }
-                _ => {
-                    let expr = self.parse_primary()?;
-                    expressions.push(Box::new(expr));
+                Ok(Def) | Ok(Struct) | Err(_) => {
+                    let error = self
+                        .unexpected(&["'}'"], &format!("at end of {}", what))
+                        .with_help("check for a missing '}'");
+                    self.report(error);
+                    break;
+                }
+                // NB: control flow statements end with a block, not with a ';'
+                Ok(If) => {
+                    match self.nested(Self::parse_if) {
+                        Ok(expr) => expressions.push(Box::new(expr)),
+                        Err(error) => {
+                            self.report(error);
+                            self.synchronize();
+                        }
+                    }
+                    continue;
+                }
+                Ok(For) => {
+                    match self.nested(Self::parse_for) {
+                        Ok(expr) => expressions.push(Box::new(expr)),
+                        Err(error) => {
+                            self.report(error);
+                            self.synchronize();
+                        }
+                    }
+                    continue;
+                }
+                Ok(Return) => self.parse_return(),
+                Ok(_) => self.parse_expr(),
+            };
+
+            let statement = statement.and_then(|expr| match self.current() {
+                Ok(Semicolon) => Ok(expr),
+                _ => Err(self
+                    .unexpected(&["';'"], "after statement")
+                    .with_help("add ';' to end the statement")),
+            });
+
+            match statement {
+                Ok(expr) => expressions.push(Box::new(expr)),
+                Err(error) => {
+                    self.report(error);
+                    self.synchronize();
                 }
             }
-            self.advance();
         }
 
-        Ok(Expr::ExprList { expressions })
+        Ok(Expr::ExprList {
+            location: self.location_from(&location),
+            expressions,
+        })
+    }
+
+    /// Parses a conditional, e.g. `if (a < b) { ... } else { ... }`.
+    fn parse_if(&mut self) -> ParseResult<Expr> {
+        let location = self.last_location();
+        // eat 'if' keyword
+        self.advance()?;
+
+        let condition = self.parse_paren_expr()?;
+        let then_block = self.parse_block("'if' body")?;
+
+        let else_block = match self.curr() {
+            Else => {
+                self.advance()?;
+                match self.curr() {
+                    If => {
+                        let else_if = self.nested(Self::parse_if)?;
+                        Some(Box::new(Expr::ExprList {
+                            location: else_if.location().clone(),
+                            expressions: vec![Box::new(else_if)],
+                        }))
+                    }
+                    _ => Some(Box::new(self.parse_block("'else' body")?)),
+                }
+            }
+            _ => None,
+        };
+
+        Ok(Expr::If {
+            location: self.location_from(&location),
+            condition: Box::new(condition),
+            then_block: Box::new(then_block),
+            else_block,
+        })
     }
 
-    fn parse_return(&mut self) -> Result<Expr, &'static str> {
+    /// Parses a loop over a range, e.g. `for i in 0..3 { ... }`.
+    fn parse_for(&mut self) -> ParseResult<Expr> {
+        let location = self.last_location();
+        // eat 'for' keyword
+        self.advance()?;
+
+        let var = match self.current()? {
+            Ident(var) => var,
+            _ => return Err(self.unexpected(&["identifier"], "after 'for'")),
+        };
+        self.advance()?;
+
+        match self.current()? {
+            In => (),
+            _ => return Err(self.unexpected(&["'in'"], "after loop variable")),
+        }
+        self.advance()?;
+
+        let start = self.parse_loop_bound()?;
+        match self.current()? {
+            DotDot => (),
+            _ => return Err(self.unexpected(&["'..'"], "in loop range")),
+        }
+        self.advance()?;
+        let end = self.parse_loop_bound()?;
+
+        let body = self.parse_block("'for' body")?;
+
+        Ok(Expr::For {
+            location: self.location_from(&location),
+            var,
+            start,
+            end,
+            body: Box::new(body),
+        })
+    }
+
+    /// Parses a bound of a loop range, which must be a literal non-negative integer.
+    fn parse_loop_bound(&mut self) -> ParseResult<i64> {
+        let location = self.last_location();
+        let bound = match self.current()? {
+            Number(nb) => nb,
+            _ => return Err(self.unexpected(&["number"], "in loop range")),
+        };
+
+        if bound.fract() != 0.0 || !(0.0..=i64::MAX as f64).contains(&bound) {
+            return Err(ParseError::new(
+                ParseErrorKind::InvalidLoopBound,
+                format!("loop bound `{}` is not a non-negative integer", bound),
+                location,
+            ));
+        }
+        self.advance()?;
+
+        Ok(bound as i64)
+    }
+
+    fn parse_return(&mut self) -> ParseResult<Expr> {
         let location = self.last_location();
         // skip return
         self.advance();
@@ -875,8 +2211,915 @@ impl<'a> Parser<'a> {
         };
 
         Ok(Expr::Return {
-            location,
+            location: self.location_from(&location),
             expression,
         })
     }
 }
+
+/// Reports the uses of custom operators whose function is not among `defined`.
+struct OperatorChecker<'a> {
+    defined: Vec<&'a str>,
+    errors: &'a mut Vec<ParseError>,
+}
+
+impl OperatorChecker<'_> {
+    fn check(&mut self, kind: &str, op: char, location: &Location, example: &str) {
+        let name = format!("{}{}", kind, op);
+        if !self.defined.contains(&name.as_str()) {
+            let error = ParseError::new(
+                ParseErrorKind::UndefinedOperator,
+                format!("{} operator `{}` is not defined", kind, op),
+                location.clone(),
+            )
+            .with_help(format!("define it like `def {}{} {}`", kind, op, example));
+            self.errors.push(error);
+        }
+    }
+}
+
+impl Visitor for OperatorChecker<'_> {
+    fn visit_binary(&mut self, location: &Location, op: char, left: &Expr, right: &Expr) {
+        if !BUILTIN_BINARY_OPS.contains(&op) {
+            self.check("binary", op, location, "5 (a, b)");
+        }
+        self.visit_expr(left);
+        self.visit_expr(right);
+    }
+
+    fn visit_unary(&mut self, location: &Location, op: char, operand: &Expr) {
+        self.check("unary", op, location, "(a)");
+        self.visit_expr(operand);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn parse(input: &str) -> Module {
+        let mut prec = HashMap::new();
+        prec.insert('+', 20);
+        prec.insert('*', 40);
+        Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap()
+    }
+
+    #[test]
+    fn lexer_tracks_lines_and_columns() {
+        let mut lexer = Lexer::new("def main() {\n  var a = 1;\n}", "test.toy");
+        let mut locations = Vec::new();
+        while !matches!(lexer.lex(), Ok(EOF)) {
+            locations.push(lexer.last_location());
+        }
+
+        let var = &locations[5];
+        assert_eq!(var.filename, "test.toy");
+        assert_eq!((var.line, var.col), (2, 3));
+        assert_eq!(var.span, 15..18);
+        assert_eq!((locations[10].line, locations[10].col), (3, 1));
+    }
+
+    #[test]
+    fn ast_nodes_carry_spans() {
+        let input = "def main() {\n  var a = [1, 2] + b;\n  return;\n}\n";
+        let module = parse(input);
+        let function = &module.functions[0];
+        assert_eq!(&input[function.location.span.clone()], input.trim_end());
+        assert_eq!(&input[function.prototype.location.span.clone()], "main()");
+
+        let expressions = match function.body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => expressions,
+            _ => panic!("expected a block"),
+        };
+        let value = match expressions[0].as_ref() {
+            Expr::VarDecl {
+                location, value, ..
+            } => {
+                assert_eq!(&input[location.span.clone()], "var a = [1, 2] + b");
+                value.as_ref().unwrap()
+            }
+            _ => panic!("expected a variable declaration"),
+        };
+        assert_eq!(&input[value.location().span.clone()], "[1, 2] + b");
+        assert_eq!(value.location().to_string(), "test.toy:2:11");
+    }
+
+    #[test]
+    fn errors_point_at_unexpected_token() {
+        let input = "def main() {\n  var a = [1, 2] 3;\n}\n";
+        let mut prec = HashMap::new();
+        let errors = Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+
+        assert_eq!(error.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(error.expected, vec!["';'"]);
+        let expected = "\
+error: expected ';' after variable declaration, found number `3`
+ --> test.toy:2:18
+  |
+2 |   var a = [1, 2] 3;
+  |                  ^ unexpected number `3`
+  |
+  = help: add ';' to end the declaration
+";
+        assert_eq!(error.render(input), expected);
+    }
+
+    #[test]
+    fn lex_errors_are_reported() {
+        let input = "def main() {\n  print(1.2.3);\n}\n";
+        let mut prec = HashMap::new();
+        let errors = Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+
+        assert_eq!(error.kind, ParseErrorKind::InvalidToken);
+        assert_eq!(error.location.to_string(), "test.toy:2:9");
+        assert_eq!(&input[error.location.span.clone()], "1.2.3");
+    }
+
+    #[test]
+    fn recovers_from_several_errors() {
+        let input = "\
+def broken() {
+  var a = [1, 2] 3;
+  var b = ;
+  print(a)
+  return b;
+}
+
+var c = 1;
+
+def main() {
+  var d = [1, 2];
+  print(d);
+}
+";
+        let mut prec = HashMap::new();
+        let (module, errors) =
+            Parser::new(input.to_string(), "test.toy", &mut prec).parse_module_partial();
+
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "test.toy:2:18: expected ';' after variable declaration, found number `3`",
+                "test.toy:3:11: expected expression, found ';'",
+                "test.toy:5:3: expected ';' after statement, found 'return'",
+                "test.toy:8:1: expected 'def' or 'struct' at top level, found 'var'",
+            ]
+        );
+
+        let names: Vec<&str> = module
+            .functions
+            .iter()
+            .map(|function| function.prototype.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["broken", "main"]);
+        match module.functions[1].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => assert_eq!(expressions.len(), 2),
+            _ => panic!("expected a block"),
+        }
+    }
+
+    #[test]
+    fn reports_missing_closing_brace() {
+        let input = "def f() {\n  return;\n\ndef main() {\n  print(1);\n}\n";
+        let mut prec = HashMap::new();
+        let (module, errors) =
+            Parser::new(input.to_string(), "test.toy", &mut prec).parse_module_partial();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "test.toy:4:1: expected '}' at end of function body, found 'def'"
+        );
+        assert_eq!(module.functions.len(), 2);
+    }
+
+    #[test]
+    fn parses_structs() {
+        let module = parse(include_str!("../../testdata/struct.toy"));
+
+        let struct_ast = &module.structs[0];
+        assert_eq!(struct_ast.name, "Struct");
+        let fields: Vec<&str> = struct_ast.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(fields, vec!["a", "b"]);
+
+        let prototype = &module.functions[0].prototype;
+        assert_eq!(prototype.args, vec!["value"]);
+        assert_eq!(
+            prototype.arg_types[0].struct_name.as_deref(),
+            Some("Struct")
+        );
+
+        let expressions = match module.functions[1].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => expressions,
+            _ => panic!("expected a block"),
+        };
+        match expressions[0].as_ref() {
+            Expr::VarDecl {
+                var_type, value, ..
+            } => {
+                assert_eq!(var_type.struct_name.as_deref(), Some("Struct"));
+                assert!(
+                    matches!(value.as_deref(), Some(Expr::StructLiteral { values, .. }) if values.len() == 2)
+                );
+            }
+            _ => panic!("expected a variable declaration"),
+        }
+    }
+
+    #[test]
+    fn parses_member_access() {
+        let input = "def f(Pair p) {\n  return p.inner.a * 2.5;\n}\n";
+        let module = parse(input);
+        let expressions = match module.functions[0].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => expressions,
+            _ => panic!("expected a block"),
+        };
+        let left = match expressions[0].as_ref() {
+            Expr::Return {
+                expression: Some(expression),
+                ..
+            } => match expression.as_ref() {
+                Expr::Binary { op: '*', left, .. } => left,
+                _ => panic!("expected a multiplication"),
+            },
+            _ => panic!("expected a return"),
+        };
+        match left.as_ref() {
+            Expr::MemberAccess {
+                location,
+                value,
+                field,
+                ..
+            } => {
+                assert_eq!(field, "a");
+                assert_eq!(&input[location.span.clone()], "p.inner.a");
+                assert!(
+                    matches!(value.as_ref(), Expr::MemberAccess { field, .. } if field == "inner")
+                );
+            }
+            _ => panic!("expected a member access"),
+        }
+    }
+
+    #[test]
+    fn parses_comparisons() {
+        let mut prec = HashMap::new();
+        prec.insert('<', 10);
+        prec.insert('-', 20);
+        let module = Parser::new(
+            "def main() { var a<2> = b - c < d; }".to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap();
+
+        let expressions = match module.functions[0].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => expressions,
+            _ => panic!("expected a block"),
+        };
+        match expressions[0].as_ref() {
+            Expr::VarDecl {
+                var_type, value, ..
+            } => {
+                assert_eq!(var_type.shape, vec![2]);
+                match value.as_deref() {
+                    Some(Expr::Binary { op, left, .. }) => {
+                        assert_eq!(*op, '<');
+                        assert!(matches!(left.as_ref(), Expr::Binary { op: '-', .. }));
+                    }
+                    _ => panic!("expected a comparison"),
+                }
+            }
+            _ => panic!("expected a variable declaration"),
+        }
+    }
+
+    #[test]
+    fn parses_control_flow() {
+        let module = parse(
+            "def main() {\n  for i in 0..3 {\n    if (i < 1) { x = i; } else if (i < 2) { print(i); }\n  }\n}",
+        );
+
+        let expressions = match module.functions[0].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => expressions,
+            _ => panic!("expected a block"),
+        };
+        let body = match expressions[0].as_ref() {
+            Expr::For {
+                var,
+                start,
+                end,
+                body,
+                ..
+            } => {
+                assert_eq!((var.as_str(), *start, *end), ("i", 0, 3));
+                body
+            }
+            _ => panic!("expected a loop"),
+        };
+        match body.as_ref() {
+            Expr::ExprList { expressions, .. } => match expressions[0].as_ref() {
+                Expr::If {
+                    location,
+                    else_block: Some(else_block),
+                    ..
+                } => {
+                    assert_eq!((location.line, location.col), (3, 5));
+                    let else_if = match else_block.as_ref() {
+                        Expr::ExprList { expressions, .. } => expressions[0].as_ref(),
+                        _ => panic!("expected a block"),
+                    };
+                    assert!(matches!(
+                        else_if,
+                        Expr::If {
+                            else_block: None,
+                            ..
+                        }
+                    ));
+                }
+                _ => panic!("expected a conditional with an else branch"),
+            },
+            _ => panic!("expected a block"),
+        }
+    }
+
+    #[test]
+    fn loop_bounds_must_be_integers() {
+        let mut prec = HashMap::new();
+        let errors = Parser::new(
+            "def main() {\n  for i in 0..2.5 { print(i); }\n}".to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ParseErrorKind::InvalidLoopBound);
+        assert_eq!(
+            errors[0].to_string(),
+            "test.toy:2:15: loop bound `2.5` is not a non-negative integer"
+        );
+    }
+
+    #[test]
+    fn parses_custom_operators() {
+        let module = parse(
+            "def binary| 5 (a, b) { return a; }\ndef unary! (a) { return a; }\ndef main() { print(!a | b + c); }",
+        );
+
+        let expressions = match module.functions[2].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => expressions,
+            _ => panic!("expected a block"),
+        };
+        let value = match expressions[0].as_ref() {
+            Expr::Print { expression, .. } => expression,
+            _ => panic!("expected a print"),
+        };
+        match value.as_ref() {
+            Expr::Binary {
+                op: '|',
+                left,
+                right,
+                ..
+            } => {
+                assert!(matches!(left.as_ref(), Expr::Unary { op: '!', .. }));
+                assert!(matches!(right.as_ref(), Expr::Binary { op: '+', .. }));
+            }
+            _ => panic!("expected a custom binary operator"),
+        }
+    }
+
+    #[test]
+    fn undefined_operators_are_reported() {
+        let mut prec = HashMap::new();
+        let errors = Parser::new(
+            "def binary+ (a, b) { return a; }\ndef binary& 5 (a) { return a; }\ndef main() { print(a | !b); }".to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap_err();
+
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "test.toy:1:11: cannot redefine builtin operator '+'",
+                "test.toy:2:5: operator `binary&` must take 2 parameter(s), found 1",
+                "test.toy:3:20: binary operator `|` is not defined",
+                "test.toy:3:24: unary operator `!` is not defined",
+            ]
+        );
+        assert_eq!(errors[1].kind, ParseErrorKind::WrongArgumentCount);
+        assert_eq!(errors[2].kind, ParseErrorKind::UndefinedOperator);
+    }
+
+    #[test]
+    fn parses_declarations_without_initializer() {
+        let input = "def main() {\n  var a<2, 3>;\n  var b;\n  Pair p;\n  var c<2> print;\n}";
+        let mut prec = HashMap::new();
+        let (module, errors) =
+            Parser::new(input.to_string(), "test.toy", &mut prec).parse_module_partial();
+
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "test.toy:3:3: variable `b` must be initialized",
+                "test.toy:4:3: struct variable `p` must be initialized",
+                "test.toy:5:12: expected '=' or ';' in variable declaration, found identifier `print`",
+            ]
+        );
+        assert_eq!(errors[0].kind, ParseErrorKind::MissingInitializer);
+
+        let body = module.functions[0].body.as_ref().unwrap();
+        match body {
+            Expr::ExprList { expressions, .. } => match expressions[0].as_ref() {
+                Expr::VarDecl {
+                    var_type, value, ..
+                } => {
+                    assert_eq!(var_type.shape, vec![2, 3]);
+                    assert!(value.is_none());
+                }
+                _ => panic!("expected a variable declaration"),
+            },
+            _ => panic!("expected a block"),
+        }
+    }
+
+    #[test]
+    fn parses_shaped_signatures() {
+        let mut prec = HashMap::new();
+        let module = Parser::new(
+            "def f(a<2, 3>, b, Pair p) -> <3, 2> { return a; }".to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap();
+
+        let prototype = &module.functions[0].prototype;
+        assert_eq!(prototype.args, vec!["a", "b", "p"]);
+        let shapes: Vec<&[usize]> = prototype
+            .arg_types
+            .iter()
+            .map(|arg_type| arg_type.shape.as_slice())
+            .collect();
+        assert_eq!(shapes, vec![&[2, 3][..], &[], &[]]);
+        assert_eq!(prototype.arg_types[2].struct_name.as_deref(), Some("Pair"));
+        assert_eq!(prototype.return_type.shape, vec![3, 2]);
+
+        let errors = Parser::new(
+            "def f(a) -> 2 { return a; }".to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "test.toy:1:13: expected '<' or ':' after '->', found number `2`"
+        );
+    }
+
+    #[test]
+    fn parses_element_types() {
+        let mut prec = HashMap::new();
+        let module = Parser::new(
+            "def f(a:f32, b<2>:i64, c) -> :f32 {\n  var d<2, 3>:i64;\n  var e:f32 = a;\n  return e;\n}"
+                .to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap();
+
+        let prototype = &module.functions[0].prototype;
+        let element_types: Vec<Option<ElementType>> = prototype
+            .arg_types
+            .iter()
+            .map(|arg_type| arg_type.element_type)
+            .collect();
+        assert_eq!(
+            element_types,
+            vec![Some(ElementType::F32), Some(ElementType::I64), None]
+        );
+        assert_eq!(prototype.arg_types[1].shape, vec![2]);
+        assert_eq!(prototype.return_type.element_type, Some(ElementType::F32));
+        assert!(prototype.return_type.shape.is_empty());
+
+        let body = module.functions[0].body.as_ref().unwrap();
+        match body {
+            Expr::ExprList { expressions, .. } => {
+                let element_types: Vec<Option<ElementType>> = expressions[..2]
+                    .iter()
+                    .map(|expression| match expression.as_ref() {
+                        Expr::VarDecl { var_type, .. } => var_type.element_type,
+                        _ => panic!("expected a variable declaration"),
+                    })
+                    .collect();
+                assert_eq!(
+                    element_types,
+                    vec![Some(ElementType::I64), Some(ElementType::F32)]
+                );
+            }
+            _ => panic!("expected a block"),
+        }
+
+        let errors = Parser::new(
+            "def main() { var a<2>:f16; }".to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "test.toy:1:23: unknown element type `f16`"
+        );
+        assert_eq!(errors[0].kind, ParseErrorKind::UnknownElementType);
+    }
+
+    #[test]
+    fn parses_multiple_results() {
+        let module = parse(
+            "def f(a) { return (a, a + 1); }\ndef main() { var (x, y) = f([1]); print((x)); }",
+        );
+
+        let returned = match module.functions[0].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => match expressions[0].as_ref() {
+                Expr::Return {
+                    expression: Some(expression),
+                    ..
+                } => expression.as_ref(),
+                _ => panic!("expected a return"),
+            },
+            _ => panic!("expected a block"),
+        };
+        match returned {
+            Expr::Tuple { values, .. } => assert_eq!(values.len(), 2),
+            _ => panic!("expected a tuple"),
+        }
+
+        let expressions = match module.functions[1].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => expressions,
+            _ => panic!("expected a block"),
+        };
+        match expressions[0].as_ref() {
+            Expr::Destructure { names, value, .. } => {
+                assert_eq!(names, &vec!["x".to_string(), "y".to_string()]);
+                assert!(matches!(value.as_ref(), Expr::Call { fn_name, .. } if fn_name == "f"));
+            }
+            _ => panic!("expected a destructuring declaration"),
+        }
+        // NB: a single parenthesized expression isn't a tuple
+        match expressions[1].as_ref() {
+            Expr::Print { expression, .. } => {
+                assert!(matches!(expression.as_ref(), Expr::Variable { .. }))
+            }
+            _ => panic!("expected a print"),
+        }
+
+        let mut prec = HashMap::new();
+        let errors = Parser::new(
+            "def main() { var (a, 1) = f(); }".to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "test.toy:1:22: expected identifier in destructuring declaration, found number `1`"
+        );
+    }
+
+    #[test]
+    fn parses_subscripts() {
+        let input = "def main() { return p.a[i + 1, 1:3, :2, :][0]; }";
+        let mut prec = HashMap::new();
+        prec.insert('+', 20);
+        let module = Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap();
+
+        let body = module.functions[0].body.as_ref().unwrap();
+        let expression = match body {
+            Expr::ExprList { expressions, .. } => match expressions[0].as_ref() {
+                Expr::Return {
+                    expression: Some(expression),
+                    ..
+                } => expression,
+                _ => panic!("expected a return"),
+            },
+            _ => panic!("expected a block"),
+        };
+        let (value, subscripts) = match expression.as_ref() {
+            Expr::Index {
+                location,
+                value,
+                subscripts,
+            } => {
+                assert_eq!(&input[location.span.clone()], "p.a[i + 1, 1:3, :2, :][0]");
+                assert!(matches!(
+                    subscripts[..],
+                    [Subscript::Index(Expr::Number { .. })]
+                ));
+                match value.as_ref() {
+                    Expr::Index {
+                        value, subscripts, ..
+                    } => (value, subscripts),
+                    _ => panic!("expected an index"),
+                }
+            }
+            _ => panic!("expected an index"),
+        };
+        assert!(matches!(value.as_ref(), Expr::MemberAccess { .. }));
+        assert!(matches!(
+            subscripts[0],
+            Subscript::Index(Expr::Binary { op: '+', .. })
+        ));
+        let slices: Vec<(Option<usize>, Option<usize>)> = subscripts[1..]
+            .iter()
+            .map(|subscript| match subscript {
+                Subscript::Slice { start, end } => (*start, *end),
+                Subscript::Index(_) => panic!("expected a slice"),
+            })
+            .collect();
+        assert_eq!(
+            slices,
+            vec![(Some(1), Some(3)), (None, Some(2)), (None, None)]
+        );
+
+        let errors = Parser::new(
+            "def main() { print(a[1.5:2]); print(a[0 1]); }".to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap_err();
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "test.toy:1:22: slice bound `1.5` is not a non-negative integer",
+                "test.toy:1:41: expected ',' or ']' in subscripts, found number `1`",
+            ]
+        );
+        assert_eq!(errors[0].kind, ParseErrorKind::InvalidSliceBound);
+    }
+
+    #[test]
+    fn parses_imports() {
+        let input =
+            "# Imports\nimport \"lib.toy\";\nimport \"ops.toy\";\ndef main() { print(a | b); }";
+        let mut prec = HashMap::new();
+        prec.insert('|', 5);
+
+        let imports = Parser::new(input.to_string(), "test.toy", &mut prec).parse_imports();
+        let paths: Vec<&str> = imports.iter().map(|import| import.path.as_str()).collect();
+        assert_eq!(paths, vec!["lib.toy", "ops.toy"]);
+        assert_eq!(imports[1].location.line, 3);
+        assert_eq!(imports[1].location.span, 28..45);
+
+        let module = Parser::new(input.to_string(), "test.toy", &mut prec)
+            .with_imported_operators(vec!["binary|".to_string()])
+            .parse_module()
+            .unwrap();
+        assert_eq!(module.imports.len(), 2);
+        assert_eq!(module.functions.len(), 1);
+    }
+
+    #[test]
+    fn import_errors_are_reported() {
+        let input = "import lib;\nimport \"lib.toy\nimport \"ops.toy\"\ndef main() { }\nimport \"late.toy\";\n";
+        let mut prec = HashMap::new();
+        let errors = Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap_err();
+
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "test.toy:1:8: expected string after 'import', found identifier `lib`",
+                "test.toy:2:8: unterminated string literal",
+                "test.toy:3:1: expected string after 'import', found 'import'",
+                "test.toy:4:1: expected ';' after import, found 'def'",
+                "test.toy:5:1: imports must come before any definition",
+            ]
+        );
+        assert_eq!(errors[4].kind, ParseErrorKind::MisplacedImport);
+    }
+
+    /// Tiny xorshift generator, so that the randomized tests are reproducible
+    /// without pulling in a dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    const FRAGMENTS: &[&str] = &[
+        "def",
+        "var",
+        "return",
+        "struct",
+        "Pair",
+        ".a",
+        "if",
+        "else",
+        "for",
+        "in",
+        "..",
+        "import",
+        "\"lib.toy\"",
+        "\"",
+        "->",
+        "a<2, 3>",
+        "a[0, 1:3]",
+        ":f32",
+        "i64",
+        ":",
+        "0..3",
+        "print",
+        "binary",
+        "unary",
+        "transpose",
+        "main",
+        "a",
+        "b",
+        "(",
+        ")",
+        "[",
+        "]",
+        "{",
+        "}",
+        "<",
+        ">",
+        ",",
+        ";",
+        "=",
+        "+",
+        "-",
+        "*",
+        "/",
+        "|",
+        "!",
+        "0",
+        "1",
+        "2.5",
+        ".",
+        "1.2.3",
+        "1a",
+        "#",
+        "# comment\n",
+        "\n",
+        " ",
+        "\t",
+        "é",
+        "\u{1F600}",
+    ];
+
+    const SOURCES: &[&str] = &[
+        include_str!("../../testdata/arith.toy"),
+        include_str!("../../testdata/ast.toy"),
+        include_str!("../../testdata/ast_empty.toy"),
+        include_str!("../../testdata/ast_tensor.toy"),
+        include_str!("../../testdata/control_flow.toy"),
+        include_str!("../../testdata/custom_ops.toy"),
+        include_str!("../../testdata/print.toy"),
+        include_str!("../../testdata/print_transposed.toy"),
+        include_str!("../../testdata/reshape_opt.toy"),
+        include_str!("../../testdata/struct.toy"),
+        include_str!("../../testdata/transpose_transpose_opt.toy"),
+    ];
+
+    /// Runs `input` through the `Lexer` and the `Parser`, failing with the offending
+    /// input if any of them panics or does not terminate.
+    fn check_does_not_panic(input: &str) {
+        let result = std::panic::catch_unwind(|| {
+            let mut lexer = Lexer::new(input, "fuzz.toy");
+            let mut steps = 0;
+            while !matches!(lexer.lex(), Ok(EOF)) {
+                steps += 1;
+                assert!(steps <= input.len(), "lexer does not terminate");
+            }
+
+            let mut prec = HashMap::new();
+            prec.insert('+', 20);
+            prec.insert('*', 40);
+            let (_, errors) =
+                Parser::new(input.to_string(), "fuzz.toy", &mut prec).parse_module_partial();
+            for error in errors {
+                assert!(error.location.span.end <= input.len());
+                error.render(input);
+            }
+        });
+
+        if result.is_err() {
+            panic!("lexing or parsing panicked on {:?}", input);
+        }
+    }
+
+    #[test]
+    fn known_crashers_are_errors() {
+        for input in [
+            "def main() { var a; }",
+            "def main() { var a = []; }",
+            "def main() { var a = 1a; }",
+            "def main() { var a<2, 2",
+            "def main() { print(x",
+            "def main() { return",
+            "def binary| 2147483647 (a, b) { return a | b | a; }",
+            "# comment without newline",
+            "def",
+            "x",
+        ] {
+            check_does_not_panic(input);
+        }
+
+        let input = "def main() {\n  var a = [];\n}\n";
+        let mut prec = HashMap::new();
+        let errors = Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap_err();
+        assert_eq!(errors[0].kind, ParseErrorKind::InvalidTensor);
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        for open in ["(", "[", "-"] {
+            let input = format!("def main() {{ var a = {}1; }}", open.repeat(100_000));
+            let mut prec = HashMap::new();
+            let errors = Parser::new(input, "test.toy", &mut prec)
+                .parse_module()
+                .unwrap_err();
+            assert_eq!(errors[0].kind, ParseErrorKind::TooDeeplyNested);
+        }
+    }
+
+    #[test]
+    fn random_token_soup_does_not_panic() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let len = rng.below(60);
+            let input: String = (0..len)
+                .map(|_| FRAGMENTS[rng.below(FRAGMENTS.len())])
+                .collect::<Vec<_>>()
+                .join(" ");
+            check_does_not_panic(&input);
+        }
+    }
+
+    #[test]
+    fn mutated_sources_do_not_panic() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000 {
+            let mut chars: Vec<char> = SOURCES[rng.below(SOURCES.len())].chars().collect();
+            for _ in 0..1 + rng.below(4) {
+                let at = rng.below(chars.len() + 1);
+                let end = (at + rng.below(8)).min(chars.len());
+                match rng.below(4) {
+                    0 => {
+                        chars.drain(at..end);
+                    }
+                    1 => {
+                        let copy: Vec<char> = chars[at..end].to_vec();
+                        chars.splice(at..at, copy);
+                    }
+                    2 => {
+                        let fragment = FRAGMENTS[rng.below(FRAGMENTS.len())];
+                        chars.splice(at..at, fragment.chars());
+                    }
+                    _ => chars.truncate(at),
+                }
+            }
+            check_does_not_panic(&chars.into_iter().collect::<String>());
+        }
+    }
+}
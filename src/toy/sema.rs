@@ -0,0 +1,1468 @@
+//! Semantic analysis of a parsed module, run before MLIRGen so that it only sees valid
+//! programs.
+//!
+//! It resolves the names of variables, functions and structs, checks the arity of calls,
+//! the rectangularity of tensor literals and the consistency of element types, and reports
+//! the constructs MLIRGen can't generate code for.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::toy::diagnostic::{Diagnostic, Severity};
+use crate::toy::parser::{
+    ElementType, Expr, Function, Location, Module, Prototype, Struct, Subscript, VarType,
+    BUILTIN_BINARY_OPS,
+};
+use crate::toy::visit::{walk_expr, Visitor};
+
+/// The number of arguments a function takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(expected) => count == expected,
+            Arity::AtLeast(expected) => count >= expected,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exactly(expected) => write!(f, "{}", expected),
+            Arity::AtLeast(expected) => write!(f, "at least {}", expected),
+        }
+    }
+}
+
+/// Functions provided by the compiler, along with their number of arguments.
+pub const BUILTIN_FUNCTIONS: &[(&str, Arity)] = &[
+    ("abs", Arity::Exactly(1)),
+    ("exp", Arity::Exactly(1)),
+    ("eye", Arity::Exactly(1)),
+    ("fill", Arity::AtLeast(2)),
+    ("log", Arity::Exactly(1)),
+    ("matmul", Arity::Exactly(2)),
+    ("max", Arity::Exactly(2)),
+    ("mean", Arity::Exactly(2)),
+    ("neg", Arity::Exactly(1)),
+    ("ones", Arity::AtLeast(1)),
+    ("range", Arity::Exactly(1)),
+    ("sqrt", Arity::Exactly(1)),
+    ("sum", Arity::Exactly(2)),
+    ("tanh", Arity::Exactly(1)),
+    ("transpose", Arity::Exactly(1)),
+    ("zeros", Arity::AtLeast(1)),
+];
+
+/// Builtin functions building a constant tensor out of literal arguments: `zeros(2, 3)`,
+/// `ones(2, 3)`, `fill(value, 2, 3)`, `range(n)` holding 0 to n - 1 and the identity
+/// matrix `eye(n)`.
+pub const TENSOR_CONSTRUCTORS: &[&str] = &["eye", "fill", "ones", "range", "zeros"];
+
+/// The largest number of elements a tensor constructor may build, as they are stored in
+/// the compiled program.
+pub const MAX_CONSTRUCTED_ELEMENTS: usize = 1 << 20;
+
+/// Returns the shape of the tensor the constructor `fn_name` builds out of `args`, or
+/// `None` if they aren't valid or it would hold more than `MAX_CONSTRUCTED_ELEMENTS`.
+pub fn constructor_shape(fn_name: &str, args: &[Expr]) -> Option<Vec<usize>> {
+    requested_shape(fn_name, args)
+        .filter(|shape| element_count(shape).is_some_and(|n| n <= MAX_CONSTRUCTED_ELEMENTS))
+}
+
+/// Returns the number of elements of a tensor of `shape`, or `None` if it overflows.
+fn element_count(shape: &[usize]) -> Option<usize> {
+    shape
+        .iter()
+        .try_fold(1usize, |count, &dim| count.checked_mul(dim))
+}
+
+/// Returns the shape `args` request from the constructor `fn_name`, however large.
+fn requested_shape(fn_name: &str, args: &[Expr]) -> Option<Vec<usize>> {
+    let dims = match fn_name {
+        "fill" => args.get(1..)?,
+        "eye" | "ones" | "range" | "zeros" => args,
+        _ => return None,
+    };
+    let dims = dims.iter().map(dimension).collect::<Option<Vec<usize>>>()?;
+    match (fn_name, dims.as_slice()) {
+        ("range", &[n]) => Some(vec![n]),
+        ("eye", &[n]) => Some(vec![n, n]),
+        ("range" | "eye", _) | (_, []) => None,
+        _ => Some(dims),
+    }
+}
+
+/// Builtin functions applying a math function to each element of a tensor, keeping its
+/// shape.
+pub const MATH_FUNCTIONS: &[&str] = &["abs", "exp", "log", "neg", "sqrt", "tanh"];
+
+/// Builtin functions reducing a tensor along one of its dimensions, dropped from the result:
+/// `sum(a, axis)`, `max(a, axis)` and `mean(a, axis)`.
+pub const REDUCTIONS: &[&str] = &["max", "mean", "sum"];
+
+/// Returns the dimension reduced by the call of a reduction with `args`, or `None` if it
+/// isn't a literal non-negative integer.
+pub fn reduction_axis(args: &[Expr]) -> Option<usize> {
+    match args.get(1)? {
+        Expr::Number { value, .. }
+            if value.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(value) =>
+        {
+            Some(*value as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether `expr` is built out of literals only, e.g. `[1, 2] + zeros(2)`. Such an
+/// expression has no element type of its own, and takes the one of the values it is
+/// combined with.
+pub fn is_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number { .. } | Expr::Tensor { .. } => true,
+        Expr::Binary {
+            op, left, right, ..
+        } => *op != '=' && BUILTIN_BINARY_OPS.contains(op) && is_literal(left) && is_literal(right),
+        Expr::Call { fn_name, .. } if TENSOR_CONSTRUCTORS.contains(&fn_name.as_str()) => true,
+        Expr::Call { fn_name, args, .. } => {
+            BUILTIN_FUNCTIONS.iter().any(|(name, _)| name == fn_name) && args.iter().all(is_literal)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the number of values `function` returns, as given by its first `return` of a
+/// value: the length of a tuple, or 1.
+pub fn result_count(function: &Function) -> usize {
+    let expressions = match &function.body {
+        Some(Expr::ExprList { expressions, .. }) => expressions,
+        _ => return 0,
+    };
+    // NB: `return` is only allowed at the top level of a function body
+    expressions
+        .iter()
+        .find_map(|expr| match expr.as_ref() {
+            Expr::Return {
+                expression: Some(expression),
+                ..
+            } => Some(returned_count(expression)),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// Returns the number of values returned by `return expression`.
+fn returned_count(expression: &Expr) -> usize {
+    match expression {
+        Expr::Tuple { values, .. } => values.len(),
+        _ => 1,
+    }
+}
+
+/// Returns the first number of the literal `expr` which isn't an integer, if any.
+fn non_integer(expr: &Expr) -> Option<&Expr> {
+    match expr {
+        Expr::Number { value, .. } if value.fract() != 0.0 => Some(expr),
+        Expr::Tensor { values, .. } => values.iter().find_map(non_integer),
+        Expr::Binary { left, right, .. } => non_integer(left).or_else(|| non_integer(right)),
+        Expr::Call { args, .. } => args.iter().find_map(non_integer),
+        _ => None,
+    }
+}
+
+/// Returns the value of `expr` if it is a literal positive integer, as dimensions are.
+fn dimension(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Number { value, .. }
+            if value.fract() == 0.0 && (1.0..=u32::MAX as f64).contains(value) =>
+        {
+            Some(*value as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Returns `structs` ordered so that each one follows the structs its fields hold, leaving out
+/// those which hold themselves, directly or not.
+pub fn struct_order(structs: &[Struct]) -> Vec<&Struct> {
+    let is_defined = |name: &str| structs.iter().any(|s| s.name == name);
+    let mut ordered: Vec<&Struct> = Vec::new();
+    let mut remaining: Vec<&Struct> = structs.iter().collect();
+    loop {
+        let (ready, pending): (Vec<&Struct>, Vec<&Struct>) =
+            remaining.into_iter().partition(|struct_ast| {
+                struct_ast
+                    .fields
+                    .iter()
+                    .all(|field| match &field.var_type.struct_name {
+                        // NB: unknown structs are reported by `check_type`
+                        Some(name) => ordered.iter().any(|s| &s.name == name) || !is_defined(name),
+                        None => true,
+                    })
+            });
+        if ready.is_empty() {
+            return ordered;
+        }
+        ordered.extend(ready);
+        remaining = pending;
+    }
+}
+
+/// Checks `module`, parsed from `filename`, and returns its violations sorted by location.
+pub fn check(module: &Module, filename: &str) -> Vec<Diagnostic> {
+    let mut sema = Sema {
+        functions: HashMap::new(),
+        result_counts: HashMap::new(),
+        structs: HashMap::new(),
+        scopes: Vec::new(),
+        region_depth: 0,
+        result_type: None,
+        result_element_type: ElementType::F64,
+        result_count: 0,
+        diagnostics: Vec::new(),
+    };
+    sema.declare_items(module);
+    if !sema.functions.contains_key("main") {
+        let location = Location {
+            filename: filename.to_string(),
+            line: 1,
+            col: 1,
+            span: 0..0,
+        };
+        sema.diagnostics.push(
+            Diagnostic::error("`main` function not found", location)
+                .with_help("define it like `def main() { ... }`"),
+        );
+    }
+    sema.visit_module(module);
+
+    let mut diagnostics = sema.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| diagnostic.location.span.start);
+    diagnostics
+}
+
+/// Returns the number of errors, rather than warnings, among `diagnostics`.
+pub fn error_count(diagnostics: &[Diagnostic]) -> usize {
+    diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count()
+}
+
+/// A variable in scope.
+struct Variable {
+    location: Location,
+    /// The struct the variable holds, if any.
+    struct_name: Option<String>,
+    element_type: ElementType,
+}
+
+struct Sema<'a> {
+    functions: HashMap<&'a str, &'a Prototype>,
+    // NB: maps the name of a function to the number of values it returns
+    result_counts: HashMap<&'a str, usize>,
+    structs: HashMap<&'a str, &'a Struct>,
+    // NB: the innermost scope is the last one; `if` and `for` bodies open a scope
+    scopes: Vec<HashMap<String, Variable>>,
+    // NB: number of `if` and `for` the visited code is nested in
+    region_depth: usize,
+    // NB: the shape of the result declared by the function being visited, if any
+    result_type: Option<VarType>,
+    // NB: the element type of the result of the function being visited, f64 unless declared
+    result_element_type: ElementType,
+    // NB: the number of values returned by the function being visited
+    result_count: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Sema<'a> {
+    /// Records the functions and structs of `module`, which may be used before they are defined.
+    fn declare_items(&mut self, module: &'a Module) {
+        for struct_ast in &module.structs {
+            match self.structs.get(struct_ast.name.as_str()) {
+                Some(previous) => self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("struct `{}` is defined multiple times", struct_ast.name),
+                        struct_ast.location.clone(),
+                    )
+                    .with_note(format!("previous definition at {}", previous.location)),
+                ),
+                None => {
+                    self.structs.insert(&struct_ast.name, struct_ast);
+                }
+            }
+        }
+
+        for function in &module.functions {
+            let prototype = &function.prototype;
+            if BUILTIN_FUNCTIONS
+                .iter()
+                .any(|(name, _)| *name == prototype.name)
+            {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("cannot redefine builtin function `{}`", prototype.name),
+                    prototype.location.clone(),
+                ));
+                continue;
+            }
+            match self.functions.get(prototype.name.as_str()) {
+                Some(previous) => self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("function `{}` is defined multiple times", prototype.name),
+                        prototype.location.clone(),
+                    )
+                    .with_note(format!("previous definition at {}", previous.location)),
+                ),
+                None => {
+                    self.functions.insert(&prototype.name, prototype);
+                    self.result_counts
+                        .insert(&prototype.name, result_count(function));
+                }
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Declares `name`, holding elements of `element_type`, in the innermost scope, unless it
+    /// is already declared there.
+    fn declare(
+        &mut self,
+        name: &str,
+        location: &Location,
+        var_type: Option<&VarType>,
+        element_type: ElementType,
+    ) {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("a scope to declare variables in");
+        if let Some(previous) = scope.get(name) {
+            let error = Diagnostic::error(
+                format!("variable `{}` is already declared", name),
+                location.clone(),
+            )
+            .with_note(format!("previous declaration at {}", previous.location))
+            .with_help(format!("assign to it with `{} = ...` instead", name));
+            self.diagnostics.push(error);
+            return;
+        }
+
+        let variable = Variable {
+            location: location.clone(),
+            struct_name: var_type.and_then(|var_type| var_type.struct_name.clone()),
+            element_type,
+        };
+        scope.insert(name.to_string(), variable);
+    }
+
+    /// Reports `var_type` if it names a struct that doesn't exist.
+    fn check_type(&mut self, var_type: &VarType, location: &Location) {
+        if let Some(name) = &var_type.struct_name {
+            if !self.structs.contains_key(name.as_str()) {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("cannot find struct `{}`", name),
+                    location.clone(),
+                ));
+            }
+        }
+    }
+
+    /// Returns whether a value of the struct `name` is held by the fields of `struct_ast`,
+    /// directly or through the structs they hold, skipping those already `visited`.
+    fn holds(&self, struct_ast: &'a Struct, name: &str, visited: &mut Vec<&'a str>) -> bool {
+        struct_ast.fields.iter().any(|field| {
+            let field_struct = match &field.var_type.struct_name {
+                Some(field_struct) => field_struct.as_str(),
+                None => return false,
+            };
+            if field_struct == name {
+                return true;
+            }
+            match self.structs.get(field_struct) {
+                Some(held) if !visited.contains(&field_struct) => {
+                    visited.push(field_struct);
+                    self.holds(held, name, visited)
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Visits `value`, which initializes a variable or parameter of type `var_type`: struct
+    /// literals are only allowed there, for structs.
+    fn visit_initializer(&mut self, value: &Expr, var_type: &VarType) {
+        let struct_ast = var_type
+            .struct_name
+            .as_ref()
+            .and_then(|name| self.structs.get(name.as_str()).copied());
+        match (value, struct_ast) {
+            (Expr::StructLiteral { location, values }, Some(struct_ast)) => {
+                self.check_struct_literal(location, values, struct_ast)
+            }
+            // NB: the unknown struct is reported by `check_type`
+            (Expr::StructLiteral { values, .. }, None) if var_type.struct_name.is_some() => {
+                for value in values {
+                    self.visit_expr(value);
+                }
+            }
+            _ => self.visit_expr(value),
+        }
+    }
+
+    /// Reports the struct literal `values` unless they are constants matching the fields of
+    /// `struct_ast`, which they initialize.
+    fn check_struct_literal(&mut self, location: &Location, values: &[Expr], struct_ast: &Struct) {
+        if values.len() != struct_ast.fields.len() {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "struct `{}` has {} field(s), found {} value(s)",
+                        struct_ast.name,
+                        struct_ast.fields.len(),
+                        values.len()
+                    ),
+                    location.clone(),
+                )
+                .with_note(format!(
+                    "`{}` is defined at {}",
+                    struct_ast.name, struct_ast.location
+                )),
+            );
+        }
+        for (value, field) in values.iter().zip(&struct_ast.fields) {
+            match (value, &field.var_type.struct_name) {
+                (Expr::StructLiteral { .. }, Some(_)) => {
+                    self.visit_initializer(value, &field.var_type)
+                }
+                (Expr::Number { .. } | Expr::Tensor { .. }, None) => self.visit_expr(value),
+                (_, Some(field_struct)) => self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "expected a struct literal of `{}` for field `{}`",
+                        field_struct, field.name
+                    ),
+                    value.location().clone(),
+                )),
+                (_, None) => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            "struct literals can only contain constants",
+                            value.location().clone(),
+                        )
+                        .with_help("use a number or a tensor literal"),
+                    );
+                    self.visit_expr(value);
+                }
+            }
+        }
+    }
+
+    /// Returns the declaration of the struct `expr` evaluates to, if it is known to be one.
+    fn struct_for(&self, expr: &Expr) -> Option<&'a Struct> {
+        let struct_name = match expr {
+            Expr::Variable { name, .. } => self.lookup(name)?.struct_name.clone()?,
+            Expr::MemberAccess { value, field, .. } => {
+                let parent = self.struct_for(value)?;
+                let field = parent.fields.iter().find(|f| &f.name == field)?;
+                field.var_type.struct_name.clone()?
+            }
+            _ => return None,
+        };
+        self.structs.get(struct_name.as_str()).copied()
+    }
+
+    /// Returns the type of the elements of the tensor `expr` evaluates to, or `None` if it is
+    /// a literal, adapting to the values it is combined with, or isn't known.
+    fn element_type_of(&self, expr: &Expr) -> Option<ElementType> {
+        if is_literal(expr) {
+            return None;
+        }
+        match expr {
+            Expr::Variable { name, .. } => self.lookup(name).map(|v| v.element_type),
+            Expr::Binary {
+                op, left, right, ..
+            } if BUILTIN_BINARY_OPS.contains(op) => self
+                .element_type_of(left)
+                .or_else(|| self.element_type_of(right)),
+            Expr::Binary { op, .. } => self.result_element_type(&format!("binary{}", op)),
+            Expr::Unary { op, .. } => self.result_element_type(&format!("unary{}", op)),
+            Expr::Call { fn_name, args, .. }
+                if BUILTIN_FUNCTIONS.iter().any(|(name, _)| name == fn_name) =>
+            {
+                args.iter().find_map(|arg| self.element_type_of(arg))
+            }
+            Expr::Call { fn_name, .. } => self.result_element_type(fn_name),
+            Expr::Index { value, .. } => self.element_type_of(value),
+            // NB: the fields of a struct are f64 tensors
+            Expr::MemberAccess { .. } => Some(ElementType::F64),
+            _ => None,
+        }
+    }
+
+    /// Returns the element type of the result of the function `name`, if it exists.
+    fn result_element_type(&self, name: &str) -> Option<ElementType> {
+        self.functions.get(name).map(|prototype| {
+            prototype
+                .return_type
+                .element_type
+                .unwrap_or(ElementType::F64)
+        })
+    }
+
+    /// Reports `expr` unless it evaluates to elements of `expected`, as `what` holds.
+    fn expect_element_type(&mut self, expr: &Expr, expected: ElementType, what: &str) {
+        match self.element_type_of(expr) {
+            Some(found) if found != expected => self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "mismatched element types for {}: expected {}, found {}",
+                    what, expected, found
+                ),
+                expr.location().clone(),
+            )),
+            Some(_) => (),
+            None => self.check_literal(expr, expected),
+        }
+    }
+
+    /// Reports the numbers of the literal `expr` which can't be held by elements of
+    /// `element_type`.
+    fn check_literal(&mut self, expr: &Expr, element_type: ElementType) {
+        if element_type != ElementType::I64 {
+            return;
+        }
+        if let Some(Expr::Number { location, value }) = non_integer(expr) {
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "expected an integer literal for i64 elements, found `{}`",
+                    value
+                ),
+                location.clone(),
+            ));
+        }
+    }
+
+    /// Reports the element types of `args` which don't match those of the parameters of the
+    /// function `fn_name`, if it is defined.
+    fn check_arguments(&mut self, fn_name: &str, args: &[&Expr]) {
+        let prototype = match self.functions.get(fn_name) {
+            Some(prototype) => *prototype,
+            None => return,
+        };
+        for ((arg, param), param_type) in args.iter().zip(&prototype.args).zip(&prototype.arg_types)
+        {
+            if param_type.struct_name.is_none() {
+                let expected = param_type.element_type.unwrap_or(ElementType::F64);
+                let what = format!("parameter `{}` of `{}`", param, fn_name);
+                self.expect_element_type(arg, expected, &what);
+            }
+        }
+    }
+
+    /// Reports the arguments of the tensor constructor `fn_name` which aren't literals of the
+    /// expected kind: the value of `fill` must be a number, and the dimensions positive
+    /// integers. Reports the call at `location` if it builds too many elements.
+    fn check_constructor(&mut self, location: &Location, fn_name: &str, args: &[Expr]) {
+        let dims = match (fn_name, args) {
+            ("fill", [value, dims @ ..]) => {
+                if !matches!(value, Expr::Number { .. }) {
+                    self.diagnostics.push(Diagnostic::error(
+                        "value of `fill` must be a literal number",
+                        value.location().clone(),
+                    ));
+                }
+                dims
+            }
+            _ => args,
+        };
+        for dim in dims {
+            if dimension(dim).is_none() {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "dimension of `{}` must be a literal positive integer",
+                            fn_name
+                        ),
+                        dim.location().clone(),
+                    )
+                    .with_help("the shape of a constructed tensor must be known statically"),
+                );
+            }
+        }
+        if let Some(shape) = requested_shape(fn_name, args) {
+            if constructor_shape(fn_name, args).is_none() {
+                let error = Diagnostic::error(
+                    format!(
+                        "`{}` builds a tensor of shape <{}>, larger than {} elements",
+                        fn_name,
+                        join(&shape),
+                        MAX_CONSTRUCTED_ELEMENTS
+                    ),
+                    location.clone(),
+                );
+                self.diagnostics.push(error);
+            }
+        }
+    }
+
+    /// Reports the operands of the element-wise operation or builtin `op` if their element
+    /// types don't match.
+    fn check_operands(&mut self, location: &Location, op: &str, left: &Expr, right: &Expr) {
+        match (self.element_type_of(left), self.element_type_of(right)) {
+            (Some(left), Some(right)) if left != right => self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "mismatched element types for `{}`: {} and {}",
+                    op, left, right
+                ),
+                location.clone(),
+            )),
+            (Some(element_type), None) => self.check_literal(right, element_type),
+            (None, Some(element_type)) => self.check_literal(left, element_type),
+            _ => (),
+        }
+    }
+
+    /// Visits `block` in a scope of its own, inside a region of an `if` or a `for`.
+    fn visit_region(&mut self, block: &Expr, bindings: &[(&str, &Location)]) {
+        self.region_depth += 1;
+        self.scopes.push(HashMap::new());
+        for (name, location) in bindings {
+            self.declare(name, location, None, ElementType::F64);
+        }
+        self.visit_expr(block);
+        self.scopes.pop();
+        self.region_depth -= 1;
+    }
+}
+
+impl Visitor for Sema<'_> {
+    fn visit_struct(&mut self, struct_ast: &Struct) {
+        if struct_ast.fields.is_empty() {
+            self.diagnostics.push(Diagnostic::error(
+                format!("struct `{}` has no fields", struct_ast.name),
+                struct_ast.location.clone(),
+            ));
+        }
+        // NB: the struct visited may be a duplicate, left out of `structs`
+        let declared = self.structs.get(struct_ast.name.as_str()).copied();
+        if let Some(declared) = declared.filter(|s| std::ptr::eq(*s, struct_ast)) {
+            if self.holds(declared, &declared.name, &mut Vec::new()) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("struct `{}` holds a value of itself", struct_ast.name),
+                        struct_ast.location.clone(),
+                    )
+                    .with_help("a struct cannot hold itself, directly or through its fields"),
+                );
+            }
+        }
+        for (pos, field) in struct_ast.fields.iter().enumerate() {
+            self.check_type(&field.var_type, &field.location);
+            if let Some(previous) = struct_ast.fields[..pos]
+                .iter()
+                .find(|f| f.name == field.name)
+            {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("field `{}` is declared multiple times", field.name),
+                        field.location.clone(),
+                    )
+                    .with_note(format!("previous declaration at {}", previous.location)),
+                );
+            }
+        }
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        let prototype = &function.prototype;
+        self.scopes.push(HashMap::new());
+        for (arg, arg_type) in prototype.args.iter().zip(&prototype.arg_types) {
+            self.check_type(arg_type, &prototype.location);
+            let element_type = arg_type.element_type.unwrap_or(ElementType::F64);
+            self.declare(arg, &prototype.location, Some(arg_type), element_type);
+        }
+        self.result_type = Some(prototype.return_type.clone()).filter(|t| !t.shape.is_empty());
+        self.result_element_type = prototype
+            .return_type
+            .element_type
+            .unwrap_or(ElementType::F64);
+        self.result_count = result_count(function);
+        if let Some(body) = &function.body {
+            self.visit_expr(body);
+            let returns_value = match body {
+                Expr::ExprList { expressions, .. } => expressions.iter().any(|expr| {
+                    matches!(
+                        expr.as_ref(),
+                        Expr::Return {
+                            expression: Some(_),
+                            ..
+                        }
+                    )
+                }),
+                _ => false,
+            };
+            if let (Some(result_type), false) = (&self.result_type, returns_value) {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "function `{}` must return a value of shape <{}>",
+                        prototype.name,
+                        join(&result_type.shape)
+                    ),
+                    prototype.location.clone(),
+                ));
+            }
+        }
+        self.scopes.pop();
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Call {
+            location, fn_name, ..
+        } = expr
+        {
+            let count = self.result_counts.get(fn_name.as_str()).copied();
+            if let Some(count) = count.filter(|&count| count > 1) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "`{}` returns {} values, which must be destructured",
+                            fn_name, count
+                        ),
+                        location.clone(),
+                    )
+                    .with_help(format!(
+                        "declare them like `var (a, b) = {}(...);`",
+                        fn_name
+                    )),
+                );
+            }
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_variable(&mut self, location: &Location, name: &str) {
+        if self.lookup(name).is_none() {
+            self.diagnostics.push(Diagnostic::error(
+                format!("cannot find variable `{}` in this scope", name),
+                location.clone(),
+            ));
+        }
+    }
+
+    fn visit_var_decl(
+        &mut self,
+        location: &Location,
+        name: &str,
+        var_type: &VarType,
+        value: Option<&Expr>,
+    ) {
+        self.check_type(var_type, location);
+        // NB: the variable isn't in scope in its own initializer
+        if let Some(value) = value {
+            self.visit_initializer(value, var_type);
+            if let Some(element_type) = var_type.element_type {
+                self.expect_element_type(value, element_type, &format!("`{}`", name));
+            }
+        }
+        let element_type = var_type
+            .element_type
+            .or_else(|| value.and_then(|value| self.element_type_of(value)))
+            .unwrap_or(ElementType::F64);
+        self.declare(name, location, Some(var_type), element_type);
+    }
+
+    fn visit_destructure(&mut self, location: &Location, names: &[String], value: &Expr) {
+        // NB: the call is walked rather than visited, as its values are destructured
+        walk_expr(self, value);
+        let fn_name = match value {
+            Expr::Call { fn_name, .. } => fn_name.as_str(),
+            _ => {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "only the values returned by a call can be destructured",
+                        value.location().clone(),
+                    )
+                    .with_help("declare a single value like `var a = ...;`"),
+                );
+                ""
+            }
+        };
+        let builtin = BUILTIN_FUNCTIONS.iter().any(|(name, _)| *name == fn_name);
+        let count = match self.result_counts.get(fn_name) {
+            Some(&count) => Some(count),
+            None if builtin => Some(1),
+            // NB: undefined functions are reported by `visit_call`
+            None => None,
+        };
+        if let Some(count) = count.filter(|&count| count != names.len()) {
+            let mut error = Diagnostic::error(
+                format!(
+                    "cannot destructure {} values out of `{}`, which returns {}",
+                    names.len(),
+                    fn_name,
+                    count
+                ),
+                location.clone(),
+            );
+            if let Some(prototype) = self.functions.get(fn_name) {
+                error = error.with_note(format!(
+                    "`{}` is defined at {}",
+                    fn_name, prototype.location
+                ));
+            }
+            self.diagnostics.push(error);
+        }
+        // NB: the element type of a function result applies to each of its values
+        let element_type = self
+            .result_element_type(fn_name)
+            .unwrap_or(ElementType::F64);
+        for name in names {
+            self.declare(name, location, None, element_type);
+        }
+    }
+
+    fn visit_binary(&mut self, location: &Location, op: char, left: &Expr, right: &Expr) {
+        if op == '=' {
+            match left {
+                Expr::Variable { location, name } if self.lookup(name).is_none() => {
+                    let error = Diagnostic::error(
+                        format!("cannot assign to undeclared variable `{}`", name),
+                        location.clone(),
+                    )
+                    .with_help(format!("declare it with `var {} = ...`", name));
+                    self.diagnostics.push(error);
+                }
+                Expr::Variable { name, .. } => {
+                    let element_type = self.lookup(name).map(|v| v.element_type);
+                    if let Some(element_type) = element_type {
+                        self.expect_element_type(right, element_type, &format!("`{}`", name));
+                    }
+                }
+                _ => self.diagnostics.push(Diagnostic::error(
+                    "left-hand side of `=` must be a variable",
+                    left.location().clone(),
+                )),
+            }
+        } else if BUILTIN_BINARY_OPS.contains(&op) {
+            self.visit_expr(left);
+            self.check_operands(location, &op.to_string(), left, right);
+        } else {
+            self.visit_expr(left);
+            self.check_arguments(&format!("binary{}", op), &[left, right]);
+        }
+        self.visit_expr(right);
+    }
+
+    fn visit_unary(&mut self, _location: &Location, op: char, operand: &Expr) {
+        self.check_arguments(&format!("unary{}", op), &[operand]);
+        self.visit_expr(operand);
+    }
+
+    fn visit_call(&mut self, location: &Location, fn_name: &str, args: &[Expr]) {
+        let builtin = BUILTIN_FUNCTIONS.iter().find(|(name, _)| *name == fn_name);
+        let expected = match (builtin, self.functions.get(fn_name)) {
+            (Some((_, arity)), _) => Some(*arity),
+            (None, Some(prototype)) => Some(Arity::Exactly(prototype.args.len())),
+            (None, None) => None,
+        };
+        match expected {
+            Some(expected) if !expected.accepts(args.len()) => {
+                let mut error = Diagnostic::error(
+                    format!(
+                        "function `{}` takes {} argument(s), found {}",
+                        fn_name,
+                        expected,
+                        args.len()
+                    ),
+                    location.clone(),
+                );
+                if let Some(prototype) = self.functions.get(fn_name) {
+                    error = error.with_note(format!(
+                        "`{}` is defined at {}",
+                        fn_name, prototype.location
+                    ));
+                }
+                self.diagnostics.push(error);
+            }
+            Some(_) => (),
+            None => self.diagnostics.push(Diagnostic::error(
+                format!("cannot find function `{}`", fn_name),
+                location.clone(),
+            )),
+        }
+        if TENSOR_CONSTRUCTORS.contains(&fn_name) {
+            self.check_constructor(location, fn_name, args);
+        }
+        match (fn_name, args) {
+            ("matmul", [lhs, rhs]) => self.check_operands(location, fn_name, lhs, rhs),
+            (_, [input]) if MATH_FUNCTIONS.contains(&fn_name) => {
+                if let Some(element_type) = self.element_type_of(input) {
+                    if !element_type.is_float() {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!(
+                                "`{}` expects a floating-point tensor, found {}",
+                                fn_name, element_type
+                            ),
+                            input.location().clone(),
+                        ));
+                    }
+                }
+            }
+            _ if builtin.is_none() => {
+                let args: Vec<&Expr> = args.iter().collect();
+                self.check_arguments(fn_name, &args);
+            }
+            _ => (),
+        }
+        if REDUCTIONS.contains(&fn_name) && args.len() == 2 && reduction_axis(args).is_none() {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "axis of `{}` must be a literal non-negative integer",
+                        fn_name
+                    ),
+                    args[1].location().clone(),
+                )
+                .with_help("the reduced dimension must be known statically"),
+            );
+        }
+
+        let param_types = match (builtin, self.functions.get(fn_name).copied()) {
+            (None, Some(prototype)) => prototype.arg_types.as_slice(),
+            _ => &[],
+        };
+        for (pos, arg) in args.iter().enumerate() {
+            match param_types.get(pos) {
+                Some(param_type) => self.visit_initializer(arg, param_type),
+                None => self.visit_expr(arg),
+            }
+        }
+    }
+
+    fn visit_return(&mut self, location: &Location, expression: Option<&Expr>) {
+        if self.region_depth > 0 {
+            self.diagnostics.push(Diagnostic::error(
+                "`return` is not allowed inside `if` and `for`",
+                location.clone(),
+            ));
+        }
+        if let (Some(result_type), None) = (&self.result_type, expression) {
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "`return` without a value in a function returning <{}>",
+                    join(&result_type.shape)
+                ),
+                location.clone(),
+            ));
+        }
+        if let Some(expression) = expression {
+            let found = returned_count(expression);
+            if found != self.result_count {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "mismatched number of returned values: expected {}, found {}",
+                        self.result_count, found
+                    ),
+                    expression.location().clone(),
+                ));
+            }
+            let values = match expression {
+                Expr::Tuple { values, .. } => values.iter().collect(),
+                _ => vec![expression],
+            };
+            if let (Some(result_type), true) = (&self.result_type, values.len() > 1) {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`return` of several values in a function returning <{}>",
+                        join(&result_type.shape)
+                    ),
+                    location.clone(),
+                ));
+            }
+            let expected = self.result_element_type;
+            for value in values {
+                self.visit_expr(value);
+                self.expect_element_type(value, expected, "the returned value");
+            }
+        }
+    }
+
+    fn visit_tuple(&mut self, location: &Location, values: &[Expr]) {
+        self.diagnostics.push(
+            Diagnostic::error("tuples can only be returned", location.clone())
+                .with_help("return several values like `return (a, b);`"),
+        );
+        for value in values {
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_tensor(&mut self, _location: &Location, values: &[Expr], _dims: &[usize]) {
+        let shape = |expr: &Expr| match expr {
+            Expr::Tensor { dims, .. } => dims.clone(),
+            _ => Vec::new(),
+        };
+
+        // NB: nested tensor literals check their own elements
+        let expected = values.first().map(shape).unwrap_or_default();
+        for value in values {
+            let found = shape(value);
+            if found != expected {
+                let error = Diagnostic::error(
+                    "tensor literal is not rectangular",
+                    value.location().clone(),
+                )
+                .with_label(format!(
+                    "expected shape <{}>, found <{}>",
+                    join(&expected),
+                    join(&found)
+                ));
+                self.diagnostics.push(error);
+            }
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_struct_literal(&mut self, location: &Location, values: &[Expr]) {
+        self.diagnostics.push(
+            Diagnostic::error(
+                "struct literal must initialize a variable or parameter of a struct",
+                location.clone(),
+            )
+            .with_help("declare the struct it holds like `Point p = {...};`"),
+        );
+        for value in values {
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_member_access(
+        &mut self,
+        _location: &Location,
+        value: &Expr,
+        field: &str,
+        field_location: &Location,
+    ) {
+        self.visit_expr(value);
+        if let Some(struct_ast) = self.struct_for(value) {
+            if !struct_ast.fields.iter().any(|f| f.name == field) {
+                let fields: Vec<&str> = struct_ast.fields.iter().map(|f| f.name.as_str()).collect();
+                let error = Diagnostic::error(
+                    format!("struct `{}` has no field `{}`", struct_ast.name, field),
+                    field_location.clone(),
+                )
+                .with_note(format!("available fields are: {}", fields.join(", ")));
+                self.diagnostics.push(error);
+            }
+        }
+    }
+
+    fn visit_index(&mut self, location: &Location, value: &Expr, subscripts: &[Subscript]) {
+        self.visit_expr(value);
+        if let Some(struct_ast) = self.struct_for(value) {
+            self.diagnostics.push(Diagnostic::error(
+                format!("cannot index a value of struct `{}`", struct_ast.name),
+                value.location().clone(),
+            ));
+        }
+
+        for subscript in subscripts {
+            match subscript {
+                Subscript::Index(index) => {
+                    if let Expr::Number { location, value } = index {
+                        if value.fract() != 0.0 || *value < 0.0 {
+                            self.diagnostics.push(Diagnostic::error(
+                                format!("index `{}` is not a non-negative integer", value),
+                                location.clone(),
+                            ));
+                        }
+                    }
+                    self.visit_expr(index);
+                }
+                Subscript::Slice {
+                    start: Some(start),
+                    end: Some(end),
+                } if start >= end => {
+                    let error = Diagnostic::error(
+                        format!("slice `{}:{}` is empty", start, end),
+                        location.clone(),
+                    )
+                    .with_help("the end of a slice is excluded, and must follow its start");
+                    self.diagnostics.push(error);
+                }
+                Subscript::Slice { .. } => (),
+            }
+        }
+    }
+
+    fn visit_if(
+        &mut self,
+        _location: &Location,
+        condition: &Expr,
+        then_block: &Expr,
+        else_block: Option<&Expr>,
+    ) {
+        self.visit_expr(condition);
+        self.visit_region(then_block, &[]);
+        if let Some(else_block) = else_block {
+            self.visit_region(else_block, &[]);
+        }
+    }
+
+    fn visit_for(&mut self, location: &Location, var: &str, _start: i64, _end: i64, body: &Expr) {
+        self.visit_region(body, &[(var, location)]);
+    }
+}
+
+fn join(shape: &[usize]) -> String {
+    shape
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::toy::parser::Parser;
+
+    use super::*;
+
+    use test_case::test_case;
+
+    fn check_source(input: &str) -> Vec<String> {
+        let mut prec = HashMap::new();
+        prec.insert('=', 2);
+        prec.insert('+', 20);
+        prec.insert('*', 40);
+        let module = Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap();
+        check(&module, "test.toy")
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test_case("arith")]
+    #[test_case("ast")]
+    #[test_case("ast_empty")]
+    #[test_case("ast_tensor")]
+    #[test_case("broadcast")]
+    #[test_case("call_chain")]
+    #[test_case("calls")]
+    #[test_case("constructors")]
+    #[test_case("control_flow")]
+    #[test_case("custom_ops")]
+    #[test_case("element_types")]
+    #[test_case("indexing")]
+    #[test_case("math")]
+    #[test_case("multiple_results")]
+    #[test_case("print")]
+    #[test_case("print_transposed")]
+    #[test_case("recursion")]
+    #[test_case("reductions")]
+    #[test_case("reshape_opt")]
+    #[test_case("signatures")]
+    #[test_case("struct")]
+    #[test_case("uninitialized")]
+    fn accepts_valid_programs(name: &str) {
+        let filename = format!("testdata/{}.toy", name);
+        let content = std::fs::read_to_string(&filename).unwrap();
+        let mut prec = HashMap::new();
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+        let module = Parser::new(content, &filename, &mut prec)
+            .parse_module()
+            .unwrap();
+
+        let diagnostics: Vec<String> = check(&module, &filename)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn resolves_names() {
+        let diagnostics = check_source(
+            "def f(a) { return a * b; }
+def main() {
+  var a = g(1);
+  var a = 2;
+  c = a;
+  for i in 0..2 { var d = i; }
+  print(d);
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:1:23: error: cannot find variable `b` in this scope",
+                "test.toy:3:11: error: cannot find function `g`",
+                "test.toy:4:3: error: variable `a` is already declared",
+                "test.toy:5:3: error: cannot assign to undeclared variable `c`",
+                "test.toy:7:9: error: cannot find variable `d` in this scope",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_functions() {
+        let diagnostics = check_source(
+            "def f(a, b) { return a; }
+def f(a) { return a; }
+def transpose(a) { return a; }
+def g() {
+  if (1) { return; }
+  return f(1) + transpose(1, 2);
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:1:1: error: `main` function not found",
+                "test.toy:2:5: error: function `f` is defined multiple times",
+                "test.toy:3:5: error: cannot redefine builtin function `transpose`",
+                "test.toy:5:12: error: `return` is not allowed inside `if` and `for`",
+                "test.toy:6:10: error: function `f` takes 2 argument(s), found 1",
+                "test.toy:6:17: error: function `transpose` takes 1 argument(s), found 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_tensors_and_structs() {
+        let diagnostics = check_source(
+            "struct S { var a; var a; }
+def main() {
+  var t = [[1, 2], [3]];
+  var u = [[1, 2], 3];
+  S s = {t, t};
+  print(s.b);
+  Unknown x = {t};
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:1:19: error: field `a` is declared multiple times",
+                "test.toy:3:20: error: tensor literal is not rectangular",
+                "test.toy:4:20: error: tensor literal is not rectangular",
+                "test.toy:5:10: error: struct literals can only contain constants",
+                "test.toy:5:13: error: struct literals can only contain constants",
+                "test.toy:6:11: error: struct `S` has no field `b`",
+                "test.toy:7:3: error: cannot find struct `Unknown`",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_struct_literals_and_definitions() {
+        let diagnostics = check_source(
+            "struct E {}
+struct A { A a; }
+struct B { C c; }
+struct C { B b; }
+struct P { Q q; var x; }
+struct Q { var y; }
+def f(P p) { return p.x; }
+def main() {
+  P p = {{[1]}, [2], [3]};
+  P q = {[1], 2};
+  var x = {[1], [2]};
+  print(f({{1}, 2}) + f({{1}, 2 + 3}));
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:1:1: error: struct `E` has no fields",
+                "test.toy:2:1: error: struct `A` holds a value of itself",
+                "test.toy:3:1: error: struct `B` holds a value of itself",
+                "test.toy:4:1: error: struct `C` holds a value of itself",
+                "test.toy:9:9: error: struct `P` has 2 field(s), found 3 value(s)",
+                "test.toy:10:10: error: expected a struct literal of `Q` for field `q`",
+                "test.toy:11:11: error: struct literal must initialize a variable or parameter of a struct",
+                "test.toy:12:31: error: struct literals can only contain constants",
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_structs_by_dependency() {
+        let mut prec = HashMap::new();
+        let module = Parser::new(
+            "struct A { B b; C c; } struct B { C c; } struct C { var x; } struct D { D d; }"
+                .to_string(),
+            "test.toy",
+            &mut prec,
+        )
+        .parse_module()
+        .unwrap();
+        let names: Vec<&str> = struct_order(&module.structs)
+            .iter()
+            .map(|struct_ast| struct_ast.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["C", "B", "A"]);
+    }
+
+    #[test]
+    fn checks_tensor_constructors() {
+        let diagnostics = check_source(
+            "def zeros(a) { return a; }
+def main() {
+  var a = [1, 2];
+  print(ones());
+  print(fill(a, 2));
+  print(range(2.5) + eye(0));
+  print(zeros(2, a) + range(3, 4));
+  print(eye(100000) + range(4000000000) + ones(1024, 1024));
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:1:5: error: cannot redefine builtin function `zeros`",
+                "test.toy:4:9: error: function `ones` takes at least 1 argument(s), found 0",
+                "test.toy:5:14: error: value of `fill` must be a literal number",
+                "test.toy:6:15: error: dimension of `range` must be a literal positive integer",
+                "test.toy:6:26: error: dimension of `eye` must be a literal positive integer",
+                "test.toy:7:18: error: dimension of `zeros` must be a literal positive integer",
+                "test.toy:7:23: error: function `range` takes 1 argument(s), found 2",
+                "test.toy:8:9: error: `eye` builds a tensor of shape <100000, 100000>, larger than 1048576 elements",
+                "test.toy:8:23: error: `range` builds a tensor of shape <4000000000>, larger than 1048576 elements",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_reductions() {
+        let diagnostics = check_source(
+            "def main() {
+  var a = [[1, 2], [3, 4]];
+  print(sum(a, 0) + max(a, 1.5));
+  print(mean(a, a) + matmul(a));
+  print(matmul(a, a) + sum(a, 1));
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:3:28: error: axis of `max` must be a literal non-negative integer",
+                "test.toy:4:17: error: axis of `mean` must be a literal non-negative integer",
+                "test.toy:4:22: error: function `matmul` takes 2 argument(s), found 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_element_types() {
+        let diagnostics = check_source(
+            "def f(a:f32, b) -> :f32 { return a; }
+def main() {
+  var a:f32 = [1, 2];
+  var b:i64 = [3, 4.5];
+  var c = a + b;
+  var d:f64 = f(a, a);
+  b = a * 2;
+  print(exp(b) + matmul(a, b));
+  print(b + 1.5);
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:4:19: error: expected an integer literal for i64 elements, found `4.5`",
+                "test.toy:5:11: error: mismatched element types for `+`: f32 and i64",
+                "test.toy:6:15: error: mismatched element types for `d`: expected f64, found f32",
+                "test.toy:6:20: error: mismatched element types for parameter `b` of `f`: \
+                 expected f64, found f32",
+                "test.toy:7:7: error: mismatched element types for `b`: expected i64, found f32",
+                "test.toy:8:9: error: mismatched element types for `+`: i64 and f32",
+                "test.toy:8:13: error: `exp` expects a floating-point tensor, found i64",
+                "test.toy:8:18: error: mismatched element types for `matmul`: f32 and i64",
+                "test.toy:9:13: error: expected an integer literal for i64 elements, found `1.5`",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_multiple_results() {
+        let diagnostics = check_source(
+            "def f(a) { return (a, a * 2); }
+def g(a) -> <2> { return (a, a); }
+def h(a) { return a; return (a, a); }
+def main() {
+  var (a, b) = f([1, 2]);
+  var (c, d, e) = f(a);
+  var (x, y) = [1, 2];
+  print(f(b));
+  print((a, b));
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:2:19: error: `return` of several values in a function returning <2>",
+                "test.toy:3:29: error: mismatched number of returned values: expected 1, found 2",
+                "test.toy:6:3: error: cannot destructure 3 values out of `f`, which returns 2",
+                "test.toy:7:16: error: only the values returned by a call can be destructured",
+                "test.toy:8:9: error: `f` returns 2 values, which must be destructured",
+                "test.toy:9:9: error: tuples can only be returned",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_subscripts() {
+        let diagnostics = check_source(
+            "struct Pair { var a; var b; }
+def main() {
+  Pair p = {[1, 2], [3, 4]};
+  var a = [[1, 2], [3, 4]];
+  print(p[0]);
+  print(a[1.5, 3:1]);
+  print(p.a[0] + a[1, 0:1]);
+}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:5:9: error: cannot index a value of struct `Pair`",
+                "test.toy:6:9: error: slice `3:1` is empty",
+                "test.toy:6:11: error: index `1.5` is not a non-negative integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_declared_results() {
+        let diagnostics = check_source(
+            "def f(a) -> <2> { print(a); }
+def g(a) -> <2> { return; }
+def main() { print(f([1, 2]) + g([1, 2])); }",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:1:5: error: function `f` must return a value of shape <2>",
+                "test.toy:2:5: error: function `g` must return a value of shape <2>",
+                "test.toy:2:19: error: `return` without a value in a function returning <2>",
+            ]
+        );
+    }
+}
@@ -0,0 +1,1168 @@
+//! Static shape inference over the AST, reporting shape mismatches at their source position.
+//!
+//! Like the shape inference pass of the Toy dialect, it specializes generic functions for
+//! the shapes of the arguments of each of their calls, starting from `main`. Unlike that
+//! pass, it runs before inlining, so that errors point at the offending expression and
+//! at the calls that led there.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use crate::toy::diagnostic::Diagnostic;
+use crate::toy::parser::{
+    Expr, Function, Location, Module, Struct, Subscript, VarType, BUILTIN_BINARY_OPS,
+};
+use crate::toy::sema::{
+    constructor_shape, reduction_axis, MATH_FUNCTIONS, REDUCTIONS, TENSOR_CONSTRUCTORS,
+};
+use crate::toy::visit::VisitorMut;
+
+/// The shape of a value, as far as it is known statically.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Shape {
+    /// A tensor whose shape can't be inferred, e.g. a struct field declared without one.
+    Unknown,
+    Tensor(Vec<usize>),
+    /// A value of the named struct.
+    Struct(String),
+    /// The values returned at once by a function, e.g. `return (a, b);`.
+    Tuple(Vec<Shape>),
+}
+
+impl Shape {
+    fn of(var_type: &VarType) -> Self {
+        match &var_type.struct_name {
+            Some(name) => Shape::Struct(name.clone()),
+            None if var_type.shape.is_empty() => Shape::Unknown,
+            None => Shape::Tensor(var_type.shape.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shape::Unknown => write!(f, "<*>"),
+            Shape::Tensor(dims) => {
+                let dims: Vec<String> = dims.iter().map(ToString::to_string).collect();
+                write!(f, "<{}>", dims.join(", "))
+            }
+            Shape::Struct(name) => write!(f, "{}", name),
+            Shape::Tuple(shapes) => {
+                let shapes: Vec<String> = shapes.iter().map(ToString::to_string).collect();
+                write!(f, "({})", shapes.join(", "))
+            }
+        }
+    }
+}
+
+/// A function along with the shapes of the arguments it is called with.
+#[derive(Debug, Clone)]
+pub struct Specialization {
+    pub function: String,
+    pub args: Vec<Shape>,
+    pub result: Shape,
+    /// The shapes of the expressions of the function body, keyed by their span.
+    pub shapes: HashMap<Range<usize>, Shape>,
+}
+
+/// The result of the shape inference of a module.
+#[derive(Debug, Default)]
+pub struct ShapeInfo {
+    /// The specializations of every function reachable from `main`, in order of first call.
+    /// Functions that aren't reachable are inferred with arguments of unknown shapes.
+    pub specializations: Vec<Specialization>,
+    /// The shape mismatches, sorted by location.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ShapeInfo {
+    /// Returns the shapes `expr` of `function` has in the specializations of the function.
+    pub fn shapes_of<'a>(&'a self, function: &'a str, expr: &Expr) -> Vec<&'a Shape> {
+        let span = &expr.location().span;
+        self.specializations
+            .iter()
+            .filter(|specialization| specialization.function == function)
+            .filter_map(|specialization| specialization.shapes.get(span))
+            .collect()
+    }
+}
+
+/// Infers the shapes of the values of `module`, which must have passed semantic analysis.
+pub fn infer(module: &Module) -> ShapeInfo {
+    let mut inference = ShapeInference {
+        functions: module
+            .functions
+            .iter()
+            .map(|function| (function.prototype.name.as_str(), function))
+            .collect(),
+        structs: module
+            .structs
+            .iter()
+            .map(|struct_ast| (struct_ast.name.as_str(), struct_ast))
+            .collect(),
+        specialized: HashMap::new(),
+        stack: Vec::new(),
+        info: ShapeInfo::default(),
+    };
+
+    if module.functions.iter().any(|f| f.prototype.name == "main") {
+        inference.specialize("main", Vec::new());
+    }
+    for function in &module.functions {
+        let name = function.prototype.name.as_str();
+        if !inference.specialized.keys().any(|(f, _)| f == name) {
+            let args = vec![Shape::Unknown; function.prototype.args.len()];
+            inference.specialize(name, args);
+        }
+    }
+
+    let mut info = inference.info;
+    info.diagnostics
+        .sort_by_key(|diagnostic| diagnostic.location.span.start);
+    info
+}
+
+/// Fills in the shape of the variable declarations of `module` that have none, when every
+/// specialization of their function agrees on it, e.g. for tooling showing inferred shapes.
+pub fn annotate(module: &mut Module, info: &ShapeInfo) {
+    let mut annotator = Annotator {
+        info,
+        function: String::new(),
+    };
+    annotator.visit_module_mut(module);
+}
+
+/// A specialization being inferred.
+struct Frame {
+    function: String,
+    args: Vec<Shape>,
+    /// The call that led to the specialization, if any.
+    call_location: Option<Location>,
+    /// The shape of the result declared by the function, if any.
+    result_type: Shape,
+    result: Shape,
+    shapes: HashMap<Range<usize>, Shape>,
+    // NB: the innermost scope is the last one; `if` and `for` bodies open a scope
+    scopes: Vec<HashMap<String, Shape>>,
+    /// The enclosing loops and branches, with the number of scopes outside of them.
+    regions: Vec<(usize, &'static str)>,
+}
+
+struct ShapeInference<'a> {
+    functions: HashMap<&'a str, &'a Function>,
+    structs: HashMap<&'a str, &'a Struct>,
+    // NB: maps a function and the shapes of its arguments to the shape of its result,
+    // which is unknown while the function is being inferred
+    specialized: HashMap<(String, Vec<Shape>), Shape>,
+    stack: Vec<Frame>,
+    info: ShapeInfo,
+}
+
+impl ShapeInference<'_> {
+    fn specialize(&mut self, name: &str, args: Vec<Shape>) -> Shape {
+        self.call(name, args, None)
+    }
+
+    /// Returns the shape of the result of `name` called with arguments of shapes `args`,
+    /// inferring the function for them the first time.
+    fn call(&mut self, name: &str, args: Vec<Shape>, location: Option<&Location>) -> Shape {
+        let function = match self.functions.get(name) {
+            Some(function) if function.prototype.args.len() == args.len() => *function,
+            // NB: undefined functions and wrong arities are reported by semantic analysis
+            _ => return Shape::Unknown,
+        };
+        // NB: parameters declaring a shape have it whatever the arguments, which are
+        // checked against it at the call
+        let prototype = &function.prototype;
+        let args: Vec<Shape> = prototype
+            .arg_types
+            .iter()
+            .zip(args)
+            .map(|(arg_type, shape)| match Shape::of(arg_type) {
+                Shape::Unknown => shape,
+                declared => declared,
+            })
+            .collect();
+        let key = (name.to_string(), args.clone());
+        if let Some(result) = self.specialized.get(&key) {
+            return result.clone();
+        }
+        // NB: recursive calls are of unknown shape until the function is inferred
+        self.specialized.insert(key.clone(), Shape::Unknown);
+
+        let scope = prototype.args.iter().cloned().zip(args.clone()).collect();
+        self.stack.push(Frame {
+            function: name.to_string(),
+            args: args.clone(),
+            call_location: location.cloned(),
+            result_type: Shape::of(&prototype.return_type),
+            result: Shape::of(&prototype.return_type),
+            shapes: HashMap::new(),
+            scopes: vec![scope],
+            regions: Vec::new(),
+        });
+        if let Some(body) = &function.body {
+            self.infer(body);
+        }
+        let frame = self
+            .stack
+            .pop()
+            .expect("the frame of the inferred function");
+
+        self.specialized.insert(key, frame.result.clone());
+        self.info.specializations.push(Specialization {
+            function: frame.function,
+            args: frame.args,
+            result: frame.result.clone(),
+            shapes: frame.shapes,
+        });
+        frame.result
+    }
+
+    /// Reports the arguments `args` of a call to `name` whose shapes, `shapes`, differ from
+    /// the ones declared by its parameters.
+    fn check_args(&mut self, name: &str, args: &[&Expr], shapes: &[Shape]) {
+        let prototype = match self.functions.get(name) {
+            Some(function) => &function.prototype,
+            None => return,
+        };
+        for (((param, param_type), arg), shape) in prototype
+            .args
+            .iter()
+            .zip(&prototype.arg_types)
+            .zip(args)
+            .zip(shapes)
+        {
+            let declared = Shape::of(param_type);
+            if let (Shape::Tensor(_), Shape::Tensor(_)) = (&declared, shape) {
+                if &declared != shape {
+                    self.error(
+                        Diagnostic::error(
+                            format!(
+                                "mismatched shape for parameter `{}` of `{}`: expected {}, found {}",
+                                param, name, declared, shape
+                            ),
+                            arg.location().clone(),
+                        )
+                        .with_label(format!("expected {}", declared))
+                        .with_note(format!("`{}` is declared at {}", name, prototype.location)),
+                    );
+                }
+            }
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.stack.last_mut().expect("a function being inferred")
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Shape> {
+        self.frame()
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    /// Reports a shape mismatch, noting the calls that specialized the enclosing functions.
+    fn error(&mut self, diagnostic: Diagnostic) {
+        let mut diagnostic = diagnostic;
+        for frame in self.stack.iter().rev() {
+            if let Some(call_location) = &frame.call_location {
+                let args: Vec<String> = frame.args.iter().map(ToString::to_string).collect();
+                diagnostic = diagnostic.with_note(format!(
+                    "in `{}` specialized for ({}), called at {}",
+                    frame.function,
+                    args.join(", "),
+                    call_location
+                ));
+            }
+        }
+        self.info.diagnostics.push(diagnostic);
+    }
+
+    fn infer(&mut self, expr: &Expr) -> Shape {
+        let shape = self.infer_expr(expr);
+        self.frame()
+            .shapes
+            .insert(expr.location().span.clone(), shape.clone());
+        shape
+    }
+
+    fn infer_expr(&mut self, expr: &Expr) -> Shape {
+        match expr {
+            Expr::Number { .. } => Shape::Tensor(Vec::new()),
+            Expr::Tensor { dims, .. } => Shape::Tensor(dims.clone()),
+            Expr::Variable { name, .. } => self.lookup(name).cloned().unwrap_or(Shape::Unknown),
+            Expr::VarDecl {
+                name,
+                var_type,
+                value,
+                ..
+            } => {
+                let shape = match value {
+                    Some(value) => {
+                        let value_shape = self.infer(value);
+                        match (Shape::of(var_type), value_shape) {
+                            (Shape::Tensor(dims), Shape::Tensor(value_dims))
+                                if dims.iter().product::<usize>()
+                                    != value_dims.iter().product() =>
+                            {
+                                self.error(
+                                    Diagnostic::error(
+                                        format!(
+                                            "cannot reshape a tensor of shape {} into {}",
+                                            Shape::Tensor(value_dims),
+                                            Shape::Tensor(dims.clone())
+                                        ),
+                                        value.location().clone(),
+                                    )
+                                    .with_label("the number of elements differs"),
+                                );
+                                Shape::Tensor(dims)
+                            }
+                            (Shape::Unknown, value_shape) => value_shape,
+                            (declared, _) => declared,
+                        }
+                    }
+                    // NB: a declaration without initializer has the shape it declares
+                    None => Shape::of(var_type),
+                };
+                let scope = self.frame().scopes.last_mut().expect("a scope");
+                scope.insert(name.clone(), shape.clone());
+                shape
+            }
+            Expr::Destructure { names, value, .. } => {
+                let shapes = match self.infer(value) {
+                    Shape::Tuple(shapes) if shapes.len() == names.len() => shapes,
+                    // NB: a mismatched number of values is reported by semantic analysis
+                    _ => vec![Shape::Unknown; names.len()],
+                };
+                let scope = self.frame().scopes.last_mut().expect("a scope");
+                for (name, shape) in names.iter().zip(&shapes) {
+                    scope.insert(name.clone(), shape.clone());
+                }
+                Shape::Tuple(shapes)
+            }
+            Expr::Tuple { values, .. } => {
+                Shape::Tuple(values.iter().map(|value| self.infer(value)).collect())
+            }
+            Expr::Binary {
+                location,
+                op: '=',
+                left,
+                right,
+            } => {
+                let shape = self.infer(right);
+                if let Expr::Variable { name, .. } = left.as_ref() {
+                    self.assign(location, name, shape.clone());
+                }
+                shape
+            }
+            Expr::Binary {
+                location,
+                op,
+                left,
+                right,
+            } => {
+                let lhs = self.infer(left);
+                let rhs = self.infer(right);
+                if !BUILTIN_BINARY_OPS.contains(op) {
+                    let name = format!("binary{}", op);
+                    let args = vec![lhs, rhs];
+                    self.check_args(&name, &[left, right], &args);
+                    return self.call(&name, args, Some(location));
+                }
+                match (&lhs, &rhs) {
+                    (Shape::Tensor(lhs_dims), Shape::Tensor(rhs_dims)) => {
+                        match broadcast(lhs_dims, rhs_dims) {
+                            Some(dims) => Shape::Tensor(dims),
+                            None => {
+                                self.error(
+                                    Diagnostic::error(
+                                        format!(
+                                            "incompatible shapes for `{}`: {} and {}",
+                                            op, lhs, rhs
+                                        ),
+                                        location.clone(),
+                                    )
+                                    .with_label(format!("{} {} {}", lhs, op, rhs)),
+                                );
+                                Shape::Unknown
+                            }
+                        }
+                    }
+                    // NB: an operand of unknown shape may broadcast the other one to any shape
+                    _ => Shape::Unknown,
+                }
+            }
+            Expr::Unary {
+                location,
+                op,
+                operand,
+            } => {
+                let name = format!("unary{}", op);
+                let shape = self.infer(operand);
+                self.check_args(&name, &[operand], std::slice::from_ref(&shape));
+                self.call(&name, vec![shape], Some(location))
+            }
+            Expr::Call {
+                location,
+                fn_name,
+                args,
+            } => {
+                let shapes: Vec<Shape> = args.iter().map(|arg| self.infer(arg)).collect();
+                match (fn_name.as_str(), shapes.as_slice()) {
+                    ("transpose", [Shape::Tensor(dims)]) => {
+                        Shape::Tensor(dims.iter().rev().cloned().collect())
+                    }
+                    ("transpose", _) => Shape::Unknown,
+                    ("matmul", [Shape::Tensor(lhs), Shape::Tensor(rhs)]) => {
+                        self.matmul(location, lhs, rhs)
+                    }
+                    ("matmul", _) => Shape::Unknown,
+                    (name, [shape]) if MATH_FUNCTIONS.contains(&name) => shape.clone(),
+                    (name, [Shape::Tensor(dims), _]) if REDUCTIONS.contains(&name) => {
+                        self.reduce(args, dims)
+                    }
+                    (name, _) if REDUCTIONS.contains(&name) => Shape::Unknown,
+                    (name, _) if TENSOR_CONSTRUCTORS.contains(&name) => {
+                        constructor_shape(name, args).map_or(Shape::Unknown, Shape::Tensor)
+                    }
+                    _ => {
+                        let args: Vec<&Expr> = args.iter().collect();
+                        self.check_args(fn_name, &args, &shapes);
+                        self.call(fn_name, shapes, Some(location))
+                    }
+                }
+            }
+            Expr::MemberAccess { value, field, .. } => match self.infer(value) {
+                Shape::Struct(name) => self
+                    .structs
+                    .get(name.as_str())
+                    .and_then(|struct_ast| struct_ast.fields.iter().find(|f| &f.name == field))
+                    .map_or(Shape::Unknown, |field| Shape::of(&field.var_type)),
+                _ => Shape::Unknown,
+            },
+            Expr::Index {
+                location,
+                value,
+                subscripts,
+            } => {
+                let shape = self.infer(value);
+                for subscript in subscripts {
+                    if let Subscript::Index(index) = subscript {
+                        if let Shape::Tensor(dims) = self.infer(index) {
+                            if !dims.is_empty() {
+                                self.error(Diagnostic::error(
+                                    format!(
+                                        "index must be a scalar, found a tensor of shape {}",
+                                        Shape::Tensor(dims)
+                                    ),
+                                    index.location().clone(),
+                                ));
+                            }
+                        }
+                    }
+                }
+                match shape {
+                    Shape::Tensor(dims) => self.index(location, &dims, subscripts),
+                    _ => Shape::Unknown,
+                }
+            }
+            Expr::StructLiteral { values, .. } => {
+                for value in values {
+                    self.infer(value);
+                }
+                Shape::Unknown
+            }
+            Expr::Print { expression, .. } => {
+                self.infer(expression);
+                Shape::Unknown
+            }
+            Expr::Return { expression, .. } => {
+                let shape = match expression {
+                    Some(expression) => self.infer(expression),
+                    None => Shape::Unknown,
+                };
+                let declared = self.frame().result_type.clone();
+                match (&declared, &shape, expression) {
+                    (Shape::Tensor(_), Shape::Tensor(_), Some(expression)) if declared != shape => {
+                        let function = self.frame().function.clone();
+                        self.error(
+                            Diagnostic::error(
+                                format!(
+                                    "mismatched shape for the result of `{}`: expected {}, found {}",
+                                    function, declared, shape
+                                ),
+                                expression.location().clone(),
+                            )
+                            .with_label(format!("expected {}", declared)),
+                        );
+                    }
+                    // NB: the declared shape is the one callers see
+                    (Shape::Tensor(_), _, _) => (),
+                    _ => self.frame().result = shape.clone(),
+                }
+                shape
+            }
+            Expr::ExprList { expressions, .. } => {
+                for expr in expressions {
+                    self.infer(expr);
+                }
+                Shape::Unknown
+            }
+            Expr::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => {
+                self.infer(condition);
+                let before = self.frame().scopes.clone();
+                let after_then = self.infer_region(then_block, "branch", None);
+                self.frame().scopes = before.clone();
+                let after_else = match else_block {
+                    Some(else_block) => self.infer_region(else_block, "branch", None),
+                    None => before,
+                };
+                self.frame().scopes = merge(after_then, after_else);
+                Shape::Unknown
+            }
+            Expr::For { var, body, .. } => {
+                let before = self.frame().scopes.clone();
+                let after = self.infer_region(body, "loop", Some(var));
+                self.frame().scopes = merge(before, after);
+                Shape::Unknown
+            }
+        }
+    }
+
+    /// Returns the shape of the product of the matrices of shapes `lhs` and `rhs`, reporting
+    /// operands which aren't matrices or whose inner dimensions differ.
+    fn matmul(&mut self, location: &Location, lhs: &[usize], rhs: &[usize]) -> Shape {
+        match (lhs, rhs) {
+            (&[rows, inner], &[rhs_inner, cols]) if inner == rhs_inner => {
+                return Shape::Tensor(vec![rows, cols]);
+            }
+            (&[_, inner], &[rhs_inner, _]) => self.error(
+                Diagnostic::error(
+                    format!(
+                        "incompatible shapes for `matmul`: {} and {}",
+                        Shape::Tensor(lhs.to_vec()),
+                        Shape::Tensor(rhs.to_vec())
+                    ),
+                    location.clone(),
+                )
+                .with_label(format!(
+                    "{} columns multiplied by {} rows",
+                    inner, rhs_inner
+                )),
+            ),
+            _ => self.error(Diagnostic::error(
+                format!(
+                    "`matmul` expects matrices, found tensors of shapes {} and {}",
+                    Shape::Tensor(lhs.to_vec()),
+                    Shape::Tensor(rhs.to_vec())
+                ),
+                location.clone(),
+            )),
+        }
+        Shape::Unknown
+    }
+
+    /// Returns the shape of the reduction of a tensor of shape `dims` along the dimension
+    /// given by `args`, reporting an axis out of its rank.
+    fn reduce(&mut self, args: &[Expr], dims: &[usize]) -> Shape {
+        // NB: a non-literal axis is reported by semantic analysis
+        let index = match reduction_axis(args) {
+            Some(index) => index,
+            None => return Shape::Unknown,
+        };
+        if index >= dims.len() {
+            self.error(Diagnostic::error(
+                format!(
+                    "axis {} is out of bounds for a tensor of shape {}",
+                    index,
+                    Shape::Tensor(dims.to_vec())
+                ),
+                args[1].location().clone(),
+            ));
+            return Shape::Unknown;
+        }
+        let mut dims = dims.to_vec();
+        dims.remove(index);
+        Shape::Tensor(dims)
+    }
+
+    /// Returns the shape of the part of a tensor of shape `dims` selected by `subscripts`,
+    /// reporting the subscripts which are out of its bounds.
+    fn index(&mut self, location: &Location, dims: &[usize], subscripts: &[Subscript]) -> Shape {
+        if subscripts.len() > dims.len() {
+            self.error(Diagnostic::error(
+                format!(
+                    "too many subscripts for a tensor of shape {}: found {}",
+                    Shape::Tensor(dims.to_vec()),
+                    subscripts.len()
+                ),
+                location.clone(),
+            ));
+            return Shape::Unknown;
+        }
+
+        let mut result = Vec::new();
+        for (dim, (subscript, &size)) in subscripts.iter().zip(dims).enumerate() {
+            match subscript {
+                Subscript::Index(Expr::Number { location, value }) if *value >= size as f64 => {
+                    self.error(Diagnostic::error(
+                        format!(
+                            "index {} is out of bounds for dimension {} of size {}",
+                            value, dim, size
+                        ),
+                        location.clone(),
+                    ));
+                }
+                Subscript::Index(_) => (),
+                Subscript::Slice {
+                    start: given_start,
+                    end: given_end,
+                } => {
+                    let (start, end) = (given_start.unwrap_or(0), given_end.unwrap_or(size));
+                    if end > size {
+                        self.error(Diagnostic::error(
+                            format!(
+                                "slice end {} is out of bounds for dimension {} of size {}",
+                                end, dim, size
+                            ),
+                            location.clone(),
+                        ));
+                        return Shape::Unknown;
+                    }
+                    if start >= end {
+                        // NB: slices with both bounds given are reported by semantic analysis
+                        if given_start.is_none() || given_end.is_none() {
+                            let error = Diagnostic::error(
+                                format!(
+                                    "slice `{}:{}` is empty for dimension {} of size {}",
+                                    start, end, dim, size
+                                ),
+                                location.clone(),
+                            )
+                            .with_help("the end of a slice is excluded, and must follow its start");
+                            self.error(error);
+                        }
+                        return Shape::Unknown;
+                    }
+                    result.push(end - start);
+                }
+            }
+        }
+        result.extend(&dims[subscripts.len()..]);
+        Shape::Tensor(result)
+    }
+
+    /// Infers `block` in a scope of its own, in which `var` is the induction variable of
+    /// a loop if any. Returns the outer scopes, as updated by the assignments of the block.
+    fn infer_region(
+        &mut self,
+        block: &Expr,
+        kind: &'static str,
+        var: Option<&String>,
+    ) -> Vec<HashMap<String, Shape>> {
+        let mut scope = HashMap::new();
+        if let Some(var) = var {
+            scope.insert(var.clone(), Shape::Tensor(Vec::new()));
+        }
+        let outer_scopes = self.frame().scopes.len();
+        self.frame().regions.push((outer_scopes, kind));
+        self.frame().scopes.push(scope);
+        self.infer(block);
+        self.frame().scopes.pop();
+        self.frame().regions.pop();
+        self.frame().scopes.clone()
+    }
+
+    /// Assigns `shape` to the variable `name`, reporting a variable carried by the enclosing
+    /// loop or branch whose shape changes: the Toy dialect carries values of a fixed type.
+    fn assign(&mut self, location: &Location, name: &str, shape: Shape) {
+        let frame = self.frame();
+        let depth = match frame
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+        {
+            Some(depth) => depth,
+            None => return,
+        };
+        let region = frame
+            .regions
+            .last()
+            .filter(|(outer_scopes, _)| depth < *outer_scopes)
+            .map(|(_, kind)| *kind);
+        let current = frame.scopes[depth][name].clone();
+        let shape = match (region, &current, &shape) {
+            (Some(kind), Shape::Tensor(_), Shape::Tensor(_)) if current != shape => {
+                self.error(Diagnostic::error(
+                    format!(
+                        "variable `{}` changes shape from {} to {} inside a {}",
+                        name, current, shape, kind
+                    ),
+                    location.clone(),
+                ));
+                Shape::Unknown
+            }
+            _ => shape,
+        };
+        self.frame().scopes[depth].insert(name.to_string(), shape);
+    }
+}
+
+/// Merges the scopes resulting from two paths of control flow, in which variables keep
+/// their shape only if both paths agree on it.
+fn merge(
+    mut scopes: Vec<HashMap<String, Shape>>,
+    others: Vec<HashMap<String, Shape>>,
+) -> Vec<HashMap<String, Shape>> {
+    for (scope, other) in scopes.iter_mut().zip(others) {
+        for (name, shape) in scope.iter_mut() {
+            if other.get(name) != Some(shape) {
+                *shape = Shape::Unknown;
+            }
+        }
+    }
+    scopes
+}
+
+/// Returns the shape of the result of an element-wise operation between tensors of shapes
+/// `lhs` and `rhs`, as broadcast by the Toy dialect, or `None` if they are incompatible.
+/// The shapes are aligned on their trailing dimensions, in which a missing or size-1
+/// dimension is repeated to match the other one.
+fn broadcast(lhs: &[usize], rhs: &[usize]) -> Option<Vec<usize>> {
+    let (long, short) = if lhs.len() < rhs.len() {
+        (rhs, lhs)
+    } else {
+        (lhs, rhs)
+    };
+    let mut dims = long.to_vec();
+    let offset = long.len() - short.len();
+    for (dim, &other) in dims[offset..].iter_mut().zip(short) {
+        if *dim == 1 {
+            *dim = other;
+        } else if other != 1 && other != *dim {
+            return None;
+        }
+    }
+    Some(dims)
+}
+
+/// Fills in the shapes of variable declarations from a `ShapeInfo`.
+struct Annotator<'a> {
+    info: &'a ShapeInfo,
+    function: String,
+}
+
+impl VisitorMut for Annotator<'_> {
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        self.function = function.prototype.name.clone();
+        if let Some(body) = &mut function.body {
+            self.visit_expr_mut(body);
+        }
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        let shapes = self.info.shapes_of(&self.function, expr);
+        if let Expr::VarDecl { var_type, .. } = expr {
+            let agreed = match shapes.split_first() {
+                Some((first, rest)) if rest.iter().all(|shape| shape == first) => Some(*first),
+                _ => None,
+            };
+            if let (Some(Shape::Tensor(dims)), None, true) =
+                (agreed, &var_type.struct_name, var_type.shape.is_empty())
+            {
+                var_type.shape = dims.clone();
+            }
+        }
+        crate::toy::visit::walk_expr_mut(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::toy::parser::Parser;
+
+    use super::*;
+
+    use test_case::test_case;
+
+    fn parse(filename: &str, input: &str) -> Module {
+        let mut prec = HashMap::new();
+        prec.insert('=', 2);
+        prec.insert('<', 10);
+        prec.insert('+', 20);
+        prec.insert('-', 20);
+        prec.insert('*', 40);
+        prec.insert('/', 40);
+        Parser::new(input.to_string(), filename, &mut prec)
+            .parse_module()
+            .unwrap()
+    }
+
+    fn parse_file(name: &str) -> Module {
+        let filename = format!("testdata/{}.toy", name);
+        let content = std::fs::read_to_string(&filename).unwrap();
+        parse(&filename, &content)
+    }
+
+    #[test_case("arith")]
+    #[test_case("ast_tensor")]
+    #[test_case("broadcast")]
+    #[test_case("call_chain")]
+    #[test_case("calls")]
+    #[test_case("constructors")]
+    #[test_case("control_flow")]
+    #[test_case("custom_ops")]
+    #[test_case("element_types")]
+    #[test_case("indexing")]
+    #[test_case("math")]
+    #[test_case("multiple_results")]
+    #[test_case("print_transposed")]
+    #[test_case("recursion")]
+    #[test_case("reductions")]
+    #[test_case("reshape_opt")]
+    #[test_case("signatures")]
+    #[test_case("struct")]
+    #[test_case("uninitialized")]
+    #[test_case("transpose_transpose_opt")]
+    fn accepts_well_shaped_programs(name: &str) {
+        let info = infer(&parse_file(name));
+        let diagnostics: Vec<String> = info.diagnostics.iter().map(ToString::to_string).collect();
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn specializes_generic_functions() {
+        let info = infer(&parse_file("ast"));
+
+        let specializations: Vec<String> = info
+            .specializations
+            .iter()
+            .filter(|s| s.function == "multiply_transpose")
+            .map(|s| {
+                let args: Vec<String> = s.args.iter().map(ToString::to_string).collect();
+                format!("({}) -> {}", args.join(", "), s.result)
+            })
+            .collect();
+        assert_eq!(
+            specializations,
+            vec![
+                "(<2, 3>, <2, 3>) -> <3, 2>",
+                "(<2, 3>, <3, 2>) -> <*>",
+                "(<3, 2>, <3, 2>) -> <2, 3>",
+            ]
+        );
+
+        // NB: `var e = multiply_transpose(b, c)` multiplies a <3, 2> by a <2, 3>
+        assert_eq!(info.diagnostics.len(), 1);
+        let diagnostic = &info.diagnostics[0];
+        assert_eq!(
+            diagnostic.to_string(),
+            "testdata/ast.toy:3:10: error: incompatible shapes for `*`: <3, 2> and <2, 3>"
+        );
+        assert_eq!(
+            diagnostic.notes,
+            vec!["in `multiply_transpose` specialized for (<2, 3>, <3, 2>), called at testdata/ast.toy:21:11"]
+        );
+    }
+
+    #[test]
+    fn reports_mismatches_at_the_offending_expression() {
+        let info = infer(&parse(
+            "test.toy",
+            "def main() {
+  var a<2, 2> = [1, 2, 3];
+  var b = [1, 2, 3];
+  for i in 0..2 { b = transpose([[1, 2]]); }
+  print(b + [1, 2]);
+  print(transpose(a) + [[1, 2, 3]]);
+}",
+        ));
+        let diagnostics: Vec<String> = info.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:2:17: error: cannot reshape a tensor of shape <3> into <2, 2>",
+                "test.toy:4:19: error: variable `b` changes shape from <3> to <2, 1> inside a loop",
+                "test.toy:6:9: error: incompatible shapes for `+`: <2, 2> and <1, 3>",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_variables_changing_shape_in_branches() {
+        let info = infer(&parse(
+            "test.toy",
+            "def main() {
+  var a = [1, 2];
+  var b = [1, 2];
+  if (a < 2) {
+    var c = [1];
+    c = [1, 2, 3];
+    b = [3, 4];
+  } else {
+    a = [[1, 2]];
+  }
+  print(a + b);
+}",
+        ));
+        let diagnostics: Vec<String> = info.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec!["test.toy:9:5: error: variable `a` changes shape from <2> to <1, 2> inside a branch"]
+        );
+    }
+
+    #[test]
+    fn broadcasts_element_wise_operands() {
+        let info = infer(&parse(
+            "test.toy",
+            "def add(a, b) {
+  return a + b;
+}
+
+def main() {
+  var a = [[1, 2, 3], [4, 5, 6]];
+  print(add(a, 2));
+  print(add([[1], [2]], [1, 2, 3]));
+  print(add(1, 2));
+  print(add(a, [1, 2]));
+}",
+        ));
+
+        let specializations: Vec<String> = info
+            .specializations
+            .iter()
+            .filter(|s| s.function == "add")
+            .map(|s| {
+                let args: Vec<String> = s.args.iter().map(ToString::to_string).collect();
+                format!("({}) -> {}", args.join(", "), s.result)
+            })
+            .collect();
+        assert_eq!(
+            specializations,
+            vec![
+                "(<2, 3>, <>) -> <2, 3>",
+                "(<2, 1>, <3>) -> <2, 3>",
+                "(<>, <>) -> <>",
+                "(<2, 3>, <2>) -> <*>",
+            ]
+        );
+        assert_eq!(info.diagnostics.len(), 1);
+        assert_eq!(
+            info.diagnostics[0].to_string(),
+            "test.toy:2:10: error: incompatible shapes for `+`: <2, 3> and <2>"
+        );
+    }
+
+    #[test]
+    fn infers_constructed_shapes() {
+        let info = infer(&parse(
+            "test.toy",
+            "def main() {
+  print(zeros(2, 3) + ones(2, 3) + fill(0.5, 3));
+  print(eye(3) * range(3));
+  print(range(2) + eye(3));
+}",
+        ));
+        let diagnostics: Vec<String> = info.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec!["test.toy:4:9: error: incompatible shapes for `+`: <2> and <3, 3>"]
+        );
+    }
+
+    #[test]
+    fn infers_matmul_and_reductions() {
+        let info = infer(&parse(
+            "test.toy",
+            "def main() {
+  var a = [[1, 2, 3], [4, 5, 6]];
+  print(matmul(a, transpose(a)) + sum(a, 1) + mean(max(a, 0), 0));
+  print(matmul(a, a));
+  print(matmul(a, [1, 2, 3]) + sum(a, 2));
+}",
+        ));
+        let diagnostics: Vec<String> = info.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:4:9: error: incompatible shapes for `matmul`: <2, 3> and <2, 3>",
+                "test.toy:5:9: error: `matmul` expects matrices, found tensors of shapes <2, 3> and <3>",
+                "test.toy:5:39: error: axis 2 is out of bounds for a tensor of shape <2, 3>",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_shapes_through_math_functions() {
+        let info = infer(&parse(
+            "test.toy",
+            "def main() {
+  var a = [[1, 2, 3], [4, 5, 6]];
+  print(exp(a) + tanh(transpose(a)));
+}",
+        ));
+        let diagnostics: Vec<String> = info.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec!["test.toy:3:9: error: incompatible shapes for `+`: <2, 3> and <3, 2>"]
+        );
+    }
+
+    #[test]
+    fn infers_subscripts() {
+        let info = infer(&parse(
+            "test.toy",
+            "def get(a) {
+  return a;
+}
+
+def main() {
+  var a = [[1, 2, 3], [4, 5, 6]];
+  print(get(a[1]));
+  print(get(a[:, 1:]));
+  print(get(a[0:1, 2]));
+  print(get(a[1, 2]));
+  for i in 0..2 { print(get(a[i])); }
+  print(a[2]);
+  print(a[0, 1:4]);
+  print(a[0, 1, 2]);
+  print(a[[0, 1]]);
+  print(a[0, 5:]);
+  print(a[:0]);
+}",
+        ));
+
+        let specializations: Vec<String> = info
+            .specializations
+            .iter()
+            .filter(|s| s.function == "get")
+            .map(|s| s.result.to_string())
+            .collect();
+        assert_eq!(specializations, vec!["<3>", "<2, 2>", "<1>", "<>"]);
+
+        let diagnostics: Vec<String> = info.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:12:11: error: index 2 is out of bounds for dimension 0 of size 2",
+                "test.toy:13:9: error: slice end 4 is out of bounds for dimension 1 of size 3",
+                "test.toy:14:9: error: too many subscripts for a tensor of shape <2, 3>: found 3",
+                "test.toy:15:11: error: index must be a scalar, found a tensor of shape <2>",
+                "test.toy:16:9: error: slice `5:3` is empty for dimension 1 of size 3",
+                "test.toy:17:9: error: slice `0:0` is empty for dimension 0 of size 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_declared_signatures() {
+        let info = infer(&parse(
+            "test.toy",
+            "def f(a<2>, b) -> <2, 2> {
+  return a + b;
+}
+def binary| 5 (a<1>, b) -> <1> {
+  return a;
+}
+def main() {
+  print(f([1, 2], [3, 4]));
+  print(f([[1, 2]], [3, 4]));
+  print([1, 2] | [3]);
+}",
+        ));
+        let diagnostics: Vec<String> = info.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "test.toy:2:10: error: mismatched shape for the result of `f`: expected <2, 2>, found <2>",
+                "test.toy:9:11: error: mismatched shape for parameter `a` of `f`: expected <2>, found <1, 2>",
+                "test.toy:10:9: error: mismatched shape for parameter `a` of `binary|`: expected <1>, found <2>",
+            ]
+        );
+        assert_eq!(
+            info.diagnostics[0].notes,
+            vec!["in `f` specialized for (<2>, <2>), called at test.toy:8:9"]
+        );
+
+        // NB: callers see the declared shape of the result
+        let main = info
+            .specializations
+            .iter()
+            .find(|s| s.function == "f")
+            .unwrap();
+        assert_eq!(main.result, Shape::Tensor(vec![2, 2]));
+    }
+
+    #[test]
+    fn infers_multiple_results() {
+        let module = parse_file("multiple_results");
+        let info = infer(&module);
+
+        let split = info
+            .specializations
+            .iter()
+            .find(|s| s.function == "split")
+            .unwrap();
+        assert_eq!(
+            split.result,
+            Shape::Tuple(vec![Shape::Tensor(vec![3, 2]), Shape::Tensor(vec![2, 3])])
+        );
+        assert_eq!(split.result.to_string(), "(<3, 2>, <2, 3>)");
+
+        let body = match module.functions[2].body.as_ref().unwrap() {
+            Expr::ExprList { expressions, .. } => expressions,
+            _ => panic!("expected a block"),
+        };
+        let print_d = match body[3].as_ref() {
+            Expr::Print { expression, .. } => expression.as_ref(),
+            _ => panic!("expected a print"),
+        };
+        assert_eq!(
+            info.shapes_of("main", print_d),
+            vec![&Shape::Tensor(vec![2, 3])]
+        );
+    }
+
+    #[test]
+    fn annotates_declarations() {
+        let mut module = parse_file("ast");
+        let info = infer(&module);
+        annotate(&mut module, &info);
+
+        let main = module
+            .functions
+            .iter()
+            .find(|f| f.prototype.name == "main")
+            .unwrap();
+        let shapes: Vec<String> = match &main.body {
+            Some(Expr::ExprList { expressions, .. }) => expressions
+                .iter()
+                .filter_map(|expr| match expr.as_ref() {
+                    Expr::VarDecl { name, var_type, .. } => {
+                        Some(format!("{}{}", name, Shape::of(var_type)))
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => panic!("expected a block"),
+        };
+        assert_eq!(
+            shapes,
+            vec!["a<2, 3>", "b<2, 3>", "c<3, 2>", "d<3, 2>", "e<*>", "f<2, 3>"]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use mlir_sys::MlirDialectHandle;
 
+use crate::block::Region;
 use crate::context::Context;
 use crate::dialect::Dialect;
 use crate::location::Location;
@@ -9,7 +10,7 @@ use crate::toy;
 
 use std::ffi::CString;
 
-use crate::toy::ffi::mlirGetDialectHandle__toy__;
+use crate::toy::ffi::{mlirGetDialectHandle__toy__, mlirToyStructTypeGet};
 
 impl From<toy::ffi::MlirDialectHandle> for mlir_sys::MlirDialectHandle {
     fn from(dialect: toy::ffi::MlirDialectHandle) -> Self {
@@ -17,6 +18,35 @@ impl From<toy::ffi::MlirDialectHandle> for mlir_sys::MlirDialectHandle {
     }
 }
 
+impl From<mlir_sys::MlirType> for toy::ffi::MlirType {
+    fn from(ty: mlir_sys::MlirType) -> Self {
+        Self { ptr: ty.ptr }
+    }
+}
+
+impl From<toy::ffi::MlirType> for mlir_sys::MlirType {
+    fn from(ty: toy::ffi::MlirType) -> Self {
+        Self { ptr: ty.ptr }
+    }
+}
+
+/// Returns the `!toy.struct<...>` type holding `element_types`, which must not be empty.
+pub fn get_struct_type(context: &Context, element_types: Vec<Type>) -> Type {
+    let elements: Vec<toy::ffi::MlirType> = element_types
+        .into_iter()
+        .map(|x| toy::ffi::MlirType::from(x.instance))
+        .collect();
+    let mlir_context = toy::ffi::MlirContext {
+        ptr: context.instance.ptr,
+    };
+
+    unsafe {
+        let instance =
+            mlirToyStructTypeGet(mlir_context, elements.len() as isize, elements.as_ptr());
+        Type::from(mlir_sys::MlirType::from(instance))
+    }
+}
+
 pub struct ToyDialect {
     name: CString,
     instance: MlirDialectHandle,
@@ -122,6 +152,12 @@ impl GenericCallOpBuilder {
         self
     }
 
+    /// One result per value returned by the callee.
+    pub fn results(&mut self, result_types: Vec<Type>) -> &mut Self {
+        self.state.add_results(result_types);
+        self
+    }
+
     pub fn callee(&mut self, callee: &str) -> &mut Self {
         let attr = Attribute::new_flat_symbol_ref(self.location.context(), callee);
         let named_attr = NamedAttribute::new("callee", attr);
@@ -151,6 +187,12 @@ impl ReturnOpBuilder {
         self
     }
 
+    /// Values returned at once by a function returning several of them.
+    pub fn inputs(&mut self, inputs: Vec<Value>) -> &mut Self {
+        self.state.add_operands(inputs);
+        self
+    }
+
     pub fn build(&mut self) -> Operation {
         Operation::new(&mut self.state)
     }
@@ -210,6 +252,87 @@ impl MulOpBuilder {
     }
 }
 
+pub struct SubOpBuilder {
+    state: OperationState,
+}
+
+impl SubOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.sub", location);
+
+        Self { state }
+    }
+
+    pub fn operands(&mut self, lhs: Value, rhs: Value) -> &mut Self {
+        self.state.add_operands([lhs, rhs].to_vec());
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        let results = vec![result_type; 1];
+        self.state.add_results(results);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct DivOpBuilder {
+    state: OperationState,
+}
+
+impl DivOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.div", location);
+
+        Self { state }
+    }
+
+    pub fn operands(&mut self, lhs: Value, rhs: Value) -> &mut Self {
+        self.state.add_operands([lhs, rhs].to_vec());
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        let results = vec![result_type; 1];
+        self.state.add_results(results);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct LtOpBuilder {
+    state: OperationState,
+}
+
+impl LtOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.lt", location);
+
+        Self { state }
+    }
+
+    pub fn operands(&mut self, lhs: Value, rhs: Value) -> &mut Self {
+        self.state.add_operands([lhs, rhs].to_vec());
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        let results = vec![result_type; 1];
+        self.state.add_results(results);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
 pub struct PrintOpBuilder {
     state: OperationState,
 }
@@ -257,29 +380,639 @@ impl ReshapeOpBuilder {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::rc::Rc;
+pub struct StructConstantOpBuilder {
+    state: OperationState,
+}
 
-    use crate::op_builder::OpBuilder;
+impl StructConstantOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.struct_constant", location);
 
-    use super::*;
+        Self { state }
+    }
 
-    #[test]
-    fn create_constant() {
-        let context = Rc::new(Context::default());
-        let dialect = ToyDialect::new(&context);
-        context.load_dialect(Box::new(dialect));
-        let location = Location::new(Rc::clone(&context));
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        let results = vec![result_type; 1];
+        self.state.add_results(results);
+        self
+    }
 
-        let op_builder = OpBuilder::new(None, 0, context);
-        let result_type = op_builder.get_f64_type();
-        let ty = op_builder.get_f64_type();
-        let ty = op_builder.get_ranked_tensor_type(vec![3, 1], ty);
-        let attr: Attribute = op_builder.get_dense_elements_attr(ty.clone(), vec![1.0, 1.0, 1.0]);
-        let _constant = ConstantOpBuilder::new(location)
-            .result(result_type)
-            .attribute(attr)
+    /// Sets the array attribute holding the value of every field.
+    pub fn attribute(&mut self, attr: Attribute) -> &mut Self {
+        let named_attr = NamedAttribute::new("value", attr);
+        self.state.add_attributes(vec![named_attr; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct StructAccessOpBuilder {
+    state: OperationState,
+    location: Location,
+}
+
+impl StructAccessOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.struct_access", location.clone());
+
+        Self { state, location }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    pub fn index(&mut self, index: usize) -> &mut Self {
+        let attr = Attribute::new_i64(self.location.context(), index as i64);
+        let named_attr = NamedAttribute::new("index", attr);
+
+        self.state.add_attributes(vec![named_attr; 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        let results = vec![result_type; 1];
+        self.state.add_results(results);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct ForOpBuilder {
+    state: OperationState,
+    location: Location,
+}
+
+impl ForOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.for", location.clone());
+
+        Self { state, location }
+    }
+
+    pub fn bounds(&mut self, lower: i64, upper: i64) -> &mut Self {
+        let lower = Attribute::new_i64(self.location.context(), lower);
+        let upper = Attribute::new_i64(self.location.context(), upper);
+        let attrs = vec![
+            NamedAttribute::new("lowerBound", lower),
+            NamedAttribute::new("upperBound", upper),
+        ];
+
+        self.state.add_attributes(attrs);
+        self
+    }
+
+    /// Values carried from one iteration to the next, the body yields their updated versions.
+    pub fn init_args(&mut self, init_args: Vec<Value>) -> &mut Self {
+        self.state.add_operands(init_args);
+        self
+    }
+
+    pub fn results(&mut self, result_types: Vec<Type>) -> &mut Self {
+        self.state.add_results(result_types);
+        self
+    }
+
+    /// The body takes the induction variable followed by the carried values as arguments.
+    pub fn body(&mut self, body: Region) -> &mut Self {
+        self.state.add_owned_regions(vec![body; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct IfOpBuilder {
+    state: OperationState,
+}
+
+impl IfOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.if", location);
+
+        Self { state }
+    }
+
+    /// NB: the condition has to be set before the carried values
+    pub fn condition(&mut self, condition: Value) -> &mut Self {
+        self.state.add_operands(vec![condition; 1]);
+        self
+    }
+
+    /// Values the branches may update, each branch yields their updated versions.
+    pub fn carried(&mut self, carried: Vec<Value>) -> &mut Self {
+        self.state.add_operands(carried);
+        self
+    }
+
+    pub fn results(&mut self, result_types: Vec<Type>) -> &mut Self {
+        self.state.add_results(result_types);
+        self
+    }
+
+    pub fn regions(&mut self, then_region: Region, else_region: Region) -> &mut Self {
+        self.state.add_owned_regions(vec![then_region, else_region]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct YieldOpBuilder {
+    state: OperationState,
+}
+
+impl YieldOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.yield", location);
+
+        Self { state }
+    }
+
+    pub fn inputs(&mut self, inputs: Vec<Value>) -> &mut Self {
+        self.state.add_operands(inputs);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct ExtractOpBuilder {
+    state: OperationState,
+    location: Location,
+}
+
+impl ExtractOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.extract", location.clone());
+
+        Self { state, location }
+    }
+
+    /// The tensor to extract from, followed by one 0-d tensor per index.
+    pub fn operands(&mut self, input: Value, indices: Vec<Value>) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self.state.add_operands(indices);
+        self
+    }
+
+    /// The dimensions of the input the indices select an element along, in increasing order.
+    pub fn dims(&mut self, dims: Vec<usize>) -> &mut Self {
+        let context = self.location.context();
+        let dims = dims
+            .into_iter()
+            .map(|dim| Attribute::new_i64(context, dim as i64))
+            .collect();
+        let attr = Attribute::new_array(context, dims);
+
+        self.state
+            .add_attributes(vec![NamedAttribute::new("dims", attr); 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct SliceOpBuilder {
+    state: OperationState,
+    location: Location,
+}
+
+impl SliceOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.slice", location.clone());
+
+        Self { state, location }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    /// The range of each leading dimension of the input to keep, an end of -1 standing for
+    /// the size of the dimension.
+    pub fn bounds(&mut self, starts: Vec<i64>, ends: Vec<i64>) -> &mut Self {
+        let context = self.location.context();
+        let array = |values: Vec<i64>| {
+            let values = values
+                .into_iter()
+                .map(|value| Attribute::new_i64(context, value))
+                .collect();
+            Attribute::new_array(context, values)
+        };
+        let attrs = vec![
+            NamedAttribute::new("starts", array(starts)),
+            NamedAttribute::new("ends", array(ends)),
+        ];
+
+        self.state.add_attributes(attrs);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct MatMulOpBuilder {
+    state: OperationState,
+}
+
+impl MatMulOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.matmul", location);
+
+        Self { state }
+    }
+
+    pub fn operands(&mut self, lhs: Value, rhs: Value) -> &mut Self {
+        self.state.add_operands(vec![lhs, rhs]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct SumOpBuilder {
+    state: OperationState,
+    location: Location,
+}
+
+impl SumOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.sum", location.clone());
+
+        Self { state, location }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    /// The dimension of the input to reduce, which the result doesn't have.
+    pub fn axis(&mut self, axis: usize) -> &mut Self {
+        let attr = Attribute::new_i64(self.location.context(), axis as i64);
+
+        self.state
+            .add_attributes(vec![NamedAttribute::new("axis", attr); 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct MaxOpBuilder {
+    state: OperationState,
+    location: Location,
+}
+
+impl MaxOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.max", location.clone());
+
+        Self { state, location }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    /// The dimension of the input to reduce, which the result doesn't have.
+    pub fn axis(&mut self, axis: usize) -> &mut Self {
+        let attr = Attribute::new_i64(self.location.context(), axis as i64);
+
+        self.state
+            .add_attributes(vec![NamedAttribute::new("axis", attr); 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct MeanOpBuilder {
+    state: OperationState,
+    location: Location,
+}
+
+impl MeanOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.mean", location.clone());
+
+        Self { state, location }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    /// The dimension of the input to reduce, which the result doesn't have.
+    pub fn axis(&mut self, axis: usize) -> &mut Self {
+        let attr = Attribute::new_i64(self.location.context(), axis as i64);
+
+        self.state
+            .add_attributes(vec![NamedAttribute::new("axis", attr); 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct AbsOpBuilder {
+    state: OperationState,
+}
+
+impl AbsOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.abs", location);
+
+        Self { state }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct ExpOpBuilder {
+    state: OperationState,
+}
+
+impl ExpOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.exp", location);
+
+        Self { state }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct LogOpBuilder {
+    state: OperationState,
+}
+
+impl LogOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.log", location);
+
+        Self { state }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct NegOpBuilder {
+    state: OperationState,
+}
+
+impl NegOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.neg", location);
+
+        Self { state }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct SqrtOpBuilder {
+    state: OperationState,
+}
+
+impl SqrtOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.sqrt", location);
+
+        Self { state }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+pub struct TanhOpBuilder {
+    state: OperationState,
+}
+
+impl TanhOpBuilder {
+    pub fn new(location: Location) -> Self {
+        let state = OperationState::new("toy.tanh", location);
+
+        Self { state }
+    }
+
+    pub fn input(&mut self, input: Value) -> &mut Self {
+        self.state.add_operands(vec![input; 1]);
+        self
+    }
+
+    pub fn result(&mut self, result_type: Type) -> &mut Self {
+        self.state.add_results(vec![result_type; 1]);
+        self
+    }
+
+    pub fn build(&mut self) -> Operation {
+        Operation::new(&mut self.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::block::Block;
+    use crate::op_builder::OpBuilder;
+
+    use super::*;
+
+    #[test]
+    fn create_constant() {
+        let context = Rc::new(Context::default());
+        let dialect = ToyDialect::new(&context);
+        context.load_dialect(Box::new(dialect));
+        let location = Location::new(Rc::clone(&context));
+
+        let op_builder = OpBuilder::new(None, 0, context);
+        let result_type = op_builder.get_f64_type();
+        let ty = op_builder.get_f64_type();
+        let ty = op_builder.get_ranked_tensor_type(vec![3, 1], ty);
+        let attr: Attribute = op_builder.get_dense_elements_attr(ty.clone(), vec![1.0, 1.0, 1.0]);
+        let _constant = ConstantOpBuilder::new(location)
+            .result(result_type)
+            .attribute(attr)
+            .build();
+    }
+
+    #[test]
+    fn create_struct_access() {
+        let context = Rc::new(Context::default());
+        let dialect = ToyDialect::new(&context);
+        context.load_dialect(Box::new(dialect));
+        let location = Location::new(Rc::clone(&context));
+
+        let op_builder = OpBuilder::new(None, 0, Rc::clone(&context));
+        let tensor_type = op_builder.get_unranked_tensor_type(op_builder.get_f64_type());
+        let struct_type = get_struct_type(&context, vec![tensor_type.clone(); 2]);
+
+        let ty = op_builder.get_ranked_tensor_type(vec![2], op_builder.get_f64_type());
+        let field: Attribute = op_builder.get_dense_elements_attr(ty, vec![1.0, 2.0]);
+        let value = Attribute::new_array(&context, vec![field; 2]);
+        let constant = StructConstantOpBuilder::new(location.clone())
+            .result(struct_type)
+            .attribute(value)
+            .build();
+        let _access = StructAccessOpBuilder::new(location)
+            .input(Value::from(constant))
+            .index(1)
+            .result(tensor_type)
+            .build();
+    }
+
+    #[test]
+    fn create_for() {
+        let context = Rc::new(Context::default());
+        let dialect = ToyDialect::new(&context);
+        context.load_dialect(Box::new(dialect));
+        let location = Location::new(Rc::clone(&context));
+
+        let op_builder = OpBuilder::new(None, 0, Rc::clone(&context));
+        let ty = op_builder.get_ranked_tensor_type(vec![1], op_builder.get_f64_type());
+        let attr: Attribute = op_builder.get_dense_elements_attr(ty.clone(), vec![0.0]);
+        let init = ConstantOpBuilder::new(location.clone())
+            .result(ty.clone())
+            .attribute(attr)
+            .build();
+
+        let body = Region::new();
+        let block = Block::new(vec![ty.clone(); 2], &location);
+        let carried = block.argument(1);
+        body.append_owned_block(&block);
+        let mut op_builder = OpBuilder::new(Some(Rc::new(block)), 0, Rc::clone(&context));
+        op_builder.insert(
+            YieldOpBuilder::new(location.clone())
+                .inputs(vec![carried])
+                .build(),
+        );
+
+        let _loop = ForOpBuilder::new(location)
+            .bounds(0, 3)
+            .init_args(vec![Value::from(init)])
+            .results(vec![ty])
+            .body(body)
             .build();
     }
 }
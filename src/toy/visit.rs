@@ -0,0 +1,601 @@
+//! Traversals of the Toy AST.
+//!
+//! `Visitor` walks a module by shared reference and `VisitorMut` by mutable reference.
+//! Every method has a default implementation visiting the children of its node, so an
+//! implementation only overrides the nodes it is interested in. An overriding method
+//! keeps walking by calling the visitor back on the children, e.g. with `self.visit_expr`.
+//!
+//! ```
+//! use rsml::toy::parser::{Expr, Location};
+//! use rsml::toy::visit::Visitor;
+//!
+//! /// Counts the calls to `transpose`.
+//! struct TransposeCounter(usize);
+//!
+//! impl Visitor for TransposeCounter {
+//!     fn visit_call(&mut self, _location: &Location, fn_name: &str, args: &[Expr]) {
+//!         if fn_name == "transpose" {
+//!             self.0 += 1;
+//!         }
+//!         for arg in args {
+//!             self.visit_expr(arg);
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::toy::parser::{Expr, Function, Location, Module, Prototype, Struct, Subscript, VarType};
+
+/// A traversal of the AST by shared reference.
+pub trait Visitor {
+    fn visit_module(&mut self, module: &Module) {
+        walk_module(self, module);
+    }
+
+    fn visit_struct(&mut self, _struct_ast: &Struct) {}
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function);
+    }
+
+    fn visit_prototype(&mut self, _prototype: &Prototype) {}
+
+    /// Visits any expression, dispatching to the method of its variant.
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_binary(&mut self, _location: &Location, _op: char, left: &Expr, right: &Expr) {
+        self.visit_expr(left);
+        self.visit_expr(right);
+    }
+
+    fn visit_call(&mut self, _location: &Location, _fn_name: &str, args: &[Expr]) {
+        for arg in args {
+            self.visit_expr(arg);
+        }
+    }
+
+    fn visit_unary(&mut self, _location: &Location, _op: char, operand: &Expr) {
+        self.visit_expr(operand);
+    }
+
+    fn visit_number(&mut self, _location: &Location, _value: f64) {}
+
+    fn visit_variable(&mut self, _location: &Location, _name: &str) {}
+
+    fn visit_var_decl(
+        &mut self,
+        _location: &Location,
+        _name: &str,
+        _var_type: &VarType,
+        value: Option<&Expr>,
+    ) {
+        if let Some(value) = value {
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_destructure(&mut self, _location: &Location, _names: &[String], value: &Expr) {
+        self.visit_expr(value);
+    }
+
+    fn visit_tensor(&mut self, _location: &Location, values: &[Expr], _dims: &[usize]) {
+        for value in values {
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_expr_list(&mut self, _location: &Location, expressions: &[Box<Expr>]) {
+        for expr in expressions {
+            self.visit_expr(expr);
+        }
+    }
+
+    fn visit_return(&mut self, _location: &Location, expression: Option<&Expr>) {
+        if let Some(expression) = expression {
+            self.visit_expr(expression);
+        }
+    }
+
+    fn visit_tuple(&mut self, _location: &Location, values: &[Expr]) {
+        for value in values {
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_print(&mut self, _location: &Location, expression: &Expr) {
+        self.visit_expr(expression);
+    }
+
+    fn visit_struct_literal(&mut self, _location: &Location, values: &[Expr]) {
+        for value in values {
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_member_access(
+        &mut self,
+        _location: &Location,
+        value: &Expr,
+        _field: &str,
+        _field_location: &Location,
+    ) {
+        self.visit_expr(value);
+    }
+
+    fn visit_index(&mut self, _location: &Location, value: &Expr, subscripts: &[Subscript]) {
+        self.visit_expr(value);
+        for subscript in subscripts {
+            if let Subscript::Index(index) = subscript {
+                self.visit_expr(index);
+            }
+        }
+    }
+
+    fn visit_if(
+        &mut self,
+        _location: &Location,
+        condition: &Expr,
+        then_block: &Expr,
+        else_block: Option<&Expr>,
+    ) {
+        self.visit_expr(condition);
+        self.visit_expr(then_block);
+        if let Some(else_block) = else_block {
+            self.visit_expr(else_block);
+        }
+    }
+
+    fn visit_for(&mut self, _location: &Location, _var: &str, _start: i64, _end: i64, body: &Expr) {
+        self.visit_expr(body);
+    }
+}
+
+/// Visits the structs, then the functions of `module`.
+pub fn walk_module<V: Visitor + ?Sized>(visitor: &mut V, module: &Module) {
+    for struct_ast in &module.structs {
+        visitor.visit_struct(struct_ast);
+    }
+    for function in &module.functions {
+        visitor.visit_function(function);
+    }
+}
+
+/// Visits the prototype, then the body of `function`.
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &Function) {
+    visitor.visit_prototype(&function.prototype);
+    if let Some(body) = &function.body {
+        visitor.visit_expr(body);
+    }
+}
+
+/// Calls the method of `visitor` matching the variant of `expr`.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Binary {
+            location,
+            op,
+            left,
+            right,
+        } => visitor.visit_binary(location, *op, left, right),
+        Expr::Call {
+            location,
+            fn_name,
+            args,
+        } => visitor.visit_call(location, fn_name, args),
+        Expr::Unary {
+            location,
+            op,
+            operand,
+        } => visitor.visit_unary(location, *op, operand),
+        Expr::Number { location, value } => visitor.visit_number(location, *value),
+        Expr::Variable { location, name } => visitor.visit_variable(location, name),
+        Expr::VarDecl {
+            location,
+            name,
+            var_type,
+            value,
+        } => visitor.visit_var_decl(location, name, var_type, value.as_deref()),
+        Expr::Destructure {
+            location,
+            names,
+            value,
+        } => visitor.visit_destructure(location, names, value),
+        Expr::Tensor {
+            location,
+            values,
+            dims,
+        } => visitor.visit_tensor(location, values, dims),
+        Expr::ExprList {
+            location,
+            expressions,
+        } => visitor.visit_expr_list(location, expressions),
+        Expr::Return {
+            location,
+            expression,
+        } => visitor.visit_return(location, expression.as_deref()),
+        Expr::Tuple { location, values } => visitor.visit_tuple(location, values),
+        Expr::Print {
+            location,
+            expression,
+        } => visitor.visit_print(location, expression),
+        Expr::StructLiteral { location, values } => visitor.visit_struct_literal(location, values),
+        Expr::MemberAccess {
+            location,
+            value,
+            field,
+            field_location,
+        } => visitor.visit_member_access(location, value, field, field_location),
+        Expr::Index {
+            location,
+            value,
+            subscripts,
+        } => visitor.visit_index(location, value, subscripts),
+        Expr::If {
+            location,
+            condition,
+            then_block,
+            else_block,
+        } => visitor.visit_if(location, condition, then_block, else_block.as_deref()),
+        Expr::For {
+            location,
+            var,
+            start,
+            end,
+            body,
+        } => visitor.visit_for(location, var, *start, *end, body),
+    }
+}
+
+/// A traversal of the AST by mutable reference, e.g. to rewrite it in place.
+///
+/// An expression can be replaced as a whole from `visit_expr_mut`, and its parts from the
+/// method of its variant.
+pub trait VisitorMut {
+    fn visit_module_mut(&mut self, module: &mut Module) {
+        walk_module_mut(self, module);
+    }
+
+    fn visit_struct_mut(&mut self, _struct_ast: &mut Struct) {}
+
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        walk_function_mut(self, function);
+    }
+
+    fn visit_prototype_mut(&mut self, _prototype: &mut Prototype) {}
+
+    /// Visits any expression, dispatching to the method of its variant.
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_binary_mut(
+        &mut self,
+        _location: &mut Location,
+        _op: &mut char,
+        left: &mut Expr,
+        right: &mut Expr,
+    ) {
+        self.visit_expr_mut(left);
+        self.visit_expr_mut(right);
+    }
+
+    fn visit_call_mut(
+        &mut self,
+        _location: &mut Location,
+        _fn_name: &mut String,
+        args: &mut [Expr],
+    ) {
+        for arg in args {
+            self.visit_expr_mut(arg);
+        }
+    }
+
+    fn visit_unary_mut(&mut self, _location: &mut Location, _op: &mut char, operand: &mut Expr) {
+        self.visit_expr_mut(operand);
+    }
+
+    fn visit_number_mut(&mut self, _location: &mut Location, _value: &mut f64) {}
+
+    fn visit_variable_mut(&mut self, _location: &mut Location, _name: &mut String) {}
+
+    fn visit_var_decl_mut(
+        &mut self,
+        _location: &mut Location,
+        _name: &mut String,
+        _var_type: &mut VarType,
+        value: Option<&mut Expr>,
+    ) {
+        if let Some(value) = value {
+            self.visit_expr_mut(value);
+        }
+    }
+
+    fn visit_destructure_mut(
+        &mut self,
+        _location: &mut Location,
+        _names: &mut Vec<String>,
+        value: &mut Expr,
+    ) {
+        self.visit_expr_mut(value);
+    }
+
+    fn visit_tensor_mut(
+        &mut self,
+        _location: &mut Location,
+        values: &mut [Expr],
+        _dims: &mut Vec<usize>,
+    ) {
+        for value in values {
+            self.visit_expr_mut(value);
+        }
+    }
+
+    fn visit_expr_list_mut(&mut self, _location: &mut Location, expressions: &mut Vec<Box<Expr>>) {
+        for expr in expressions {
+            self.visit_expr_mut(expr);
+        }
+    }
+
+    fn visit_return_mut(&mut self, _location: &mut Location, expression: Option<&mut Expr>) {
+        if let Some(expression) = expression {
+            self.visit_expr_mut(expression);
+        }
+    }
+
+    fn visit_tuple_mut(&mut self, _location: &mut Location, values: &mut [Expr]) {
+        for value in values {
+            self.visit_expr_mut(value);
+        }
+    }
+
+    fn visit_print_mut(&mut self, _location: &mut Location, expression: &mut Expr) {
+        self.visit_expr_mut(expression);
+    }
+
+    fn visit_struct_literal_mut(&mut self, _location: &mut Location, values: &mut [Expr]) {
+        for value in values {
+            self.visit_expr_mut(value);
+        }
+    }
+
+    fn visit_member_access_mut(
+        &mut self,
+        _location: &mut Location,
+        value: &mut Expr,
+        _field: &mut String,
+        _field_location: &mut Location,
+    ) {
+        self.visit_expr_mut(value);
+    }
+
+    fn visit_index_mut(
+        &mut self,
+        _location: &mut Location,
+        value: &mut Expr,
+        subscripts: &mut [Subscript],
+    ) {
+        self.visit_expr_mut(value);
+        for subscript in subscripts {
+            if let Subscript::Index(index) = subscript {
+                self.visit_expr_mut(index);
+            }
+        }
+    }
+
+    fn visit_if_mut(
+        &mut self,
+        _location: &mut Location,
+        condition: &mut Expr,
+        then_block: &mut Expr,
+        else_block: Option<&mut Expr>,
+    ) {
+        self.visit_expr_mut(condition);
+        self.visit_expr_mut(then_block);
+        if let Some(else_block) = else_block {
+            self.visit_expr_mut(else_block);
+        }
+    }
+
+    fn visit_for_mut(
+        &mut self,
+        _location: &mut Location,
+        _var: &mut String,
+        _start: &mut i64,
+        _end: &mut i64,
+        body: &mut Expr,
+    ) {
+        self.visit_expr_mut(body);
+    }
+}
+
+/// Visits the structs, then the functions of `module`.
+pub fn walk_module_mut<V: VisitorMut + ?Sized>(visitor: &mut V, module: &mut Module) {
+    for struct_ast in &mut module.structs {
+        visitor.visit_struct_mut(struct_ast);
+    }
+    for function in &mut module.functions {
+        visitor.visit_function_mut(function);
+    }
+}
+
+/// Visits the prototype, then the body of `function`.
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, function: &mut Function) {
+    visitor.visit_prototype_mut(&mut function.prototype);
+    if let Some(body) = &mut function.body {
+        visitor.visit_expr_mut(body);
+    }
+}
+
+/// Calls the method of `visitor` matching the variant of `expr`.
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Binary {
+            location,
+            op,
+            left,
+            right,
+        } => visitor.visit_binary_mut(location, op, left, right),
+        Expr::Call {
+            location,
+            fn_name,
+            args,
+        } => visitor.visit_call_mut(location, fn_name, args),
+        Expr::Unary {
+            location,
+            op,
+            operand,
+        } => visitor.visit_unary_mut(location, op, operand),
+        Expr::Number { location, value } => visitor.visit_number_mut(location, value),
+        Expr::Variable { location, name } => visitor.visit_variable_mut(location, name),
+        Expr::VarDecl {
+            location,
+            name,
+            var_type,
+            value,
+        } => visitor.visit_var_decl_mut(location, name, var_type, value.as_deref_mut()),
+        Expr::Destructure {
+            location,
+            names,
+            value,
+        } => visitor.visit_destructure_mut(location, names, value),
+        Expr::Tensor {
+            location,
+            values,
+            dims,
+        } => visitor.visit_tensor_mut(location, values, dims),
+        Expr::ExprList {
+            location,
+            expressions,
+        } => visitor.visit_expr_list_mut(location, expressions),
+        Expr::Return {
+            location,
+            expression,
+        } => visitor.visit_return_mut(location, expression.as_deref_mut()),
+        Expr::Tuple { location, values } => visitor.visit_tuple_mut(location, values),
+        Expr::Print {
+            location,
+            expression,
+        } => visitor.visit_print_mut(location, expression),
+        Expr::StructLiteral { location, values } => {
+            visitor.visit_struct_literal_mut(location, values)
+        }
+        Expr::MemberAccess {
+            location,
+            value,
+            field,
+            field_location,
+        } => visitor.visit_member_access_mut(location, value, field, field_location),
+        Expr::Index {
+            location,
+            value,
+            subscripts,
+        } => visitor.visit_index_mut(location, value, subscripts),
+        Expr::If {
+            location,
+            condition,
+            then_block,
+            else_block,
+        } => visitor.visit_if_mut(location, condition, then_block, else_block.as_deref_mut()),
+        Expr::For {
+            location,
+            var,
+            start,
+            end,
+            body,
+        } => visitor.visit_for_mut(location, var, start, end, body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::toy::parser::Parser;
+
+    use super::*;
+
+    fn parse(input: &str) -> Module {
+        let mut prec = HashMap::new();
+        prec.insert('=', 2);
+        prec.insert('+', 20);
+        prec.insert('*', 40);
+        Parser::new(input.to_string(), "test.toy", &mut prec)
+            .parse_module()
+            .unwrap()
+    }
+
+    /// Records the names of the variables it visits.
+    struct Variables(Vec<String>);
+
+    impl Visitor for Variables {
+        fn visit_variable(&mut self, _location: &Location, name: &str) {
+            self.0.push(name.to_string());
+        }
+    }
+
+    #[test]
+    fn visitor_walks_nested_expressions() {
+        let module = parse(
+            "def f(a, b) { return transpose(a) * b; }
+             def main() {
+               var x = [1, 2];
+               for i in 0..2 { if (x) { print(f(x, x + i)); } }
+             }",
+        );
+
+        let mut variables = Variables(Vec::new());
+        variables.visit_module(&module);
+        assert_eq!(variables.0, vec!["a", "b", "x", "x", "x", "i"]);
+    }
+
+    /// Renames the variable `from` to `to` everywhere it is declared or used.
+    struct Rename {
+        from: &'static str,
+        to: &'static str,
+    }
+
+    impl VisitorMut for Rename {
+        fn visit_variable_mut(&mut self, _location: &mut Location, name: &mut String) {
+            if name == self.from {
+                *name = self.to.to_string();
+            }
+        }
+
+        fn visit_var_decl_mut(
+            &mut self,
+            _location: &mut Location,
+            name: &mut String,
+            _var_type: &mut VarType,
+            value: Option<&mut Expr>,
+        ) {
+            if name == self.from {
+                *name = self.to.to_string();
+            }
+            if let Some(value) = value {
+                self.visit_expr_mut(value);
+            }
+        }
+    }
+
+    #[test]
+    fn visitor_mut_rewrites_in_place() {
+        let mut module = parse("def main() { var a = 1; a = a + 2; print(a); }");
+        let mut rename = Rename { from: "a", to: "b" };
+        rename.visit_module_mut(&mut module);
+
+        let mut variables = Variables(Vec::new());
+        variables.visit_module(&module);
+        assert_eq!(variables.0, vec!["b", "b", "b"]);
+
+        let body = module.functions[0].body.as_ref().unwrap();
+        match body {
+            Expr::ExprList { expressions, .. } => match expressions[0].as_ref() {
+                Expr::VarDecl { name, .. } => assert_eq!(name, "b"),
+                _ => panic!("expected a declaration"),
+            },
+            _ => panic!("expected a block"),
+        }
+    }
+}